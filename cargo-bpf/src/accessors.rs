@@ -1,4 +1,4 @@
-use proc_macro2::{Ident, Span};
+use proc_macro2::{Ident, Literal, Span};
 use quote::quote;
 use std::collections::{HashMap, HashSet};
 use syn::visit::{visit_item_struct, Visit};
@@ -141,7 +141,33 @@ fn parse_items(tree: &File) -> Items {
     ci.items
 }
 
+/// The section holding the field paths of CO-RE accessors, see
+/// `generate_core_read_accessors`.
+const CORE_SECTION: &str = ".redbpf.core";
+
 pub fn generate_read_accessors(bindings: &str, whitelist: &[&str]) -> String {
+    generate_accessors(bindings, whitelist, false)
+}
+
+/// Generates accessors that read fields at the offsets of the running kernel.
+///
+/// Like `generate_read_accessors`, but the offsets of the fields aren't baked
+/// in at build time: every accessor records the path of the field it reads,
+/// as `<struct>.<field>`, in the `.redbpf.core` section and uses the address
+/// of the record as the offset of the field. When loading the probe,
+/// `redbpf::Module::parse` relocates those addresses to the offsets found in
+/// `/sys/kernel/btf/vmlinux`, so probes keep working across kernel versions.
+///
+/// Loading such probes requires BTF: either the kernel's, or the one the
+/// probe was built with (see `cargo bpf build --btf`). Without it the field
+/// offsets can't be resolved and `Module::parse` fails, so use
+/// `generate_read_accessors` for probes that must load on kernels without
+/// BTF.
+pub fn generate_core_read_accessors(bindings: &str, whitelist: &[&str]) -> String {
+    generate_accessors(bindings, whitelist, true)
+}
+
+fn generate_accessors(bindings: &str, whitelist: &[&str], core: bool) -> String {
     // parse the bindgen generated bindings
     let tree: File = parse_str(&bindings).unwrap();
 
@@ -167,12 +193,39 @@ pub fn generate_read_accessors(bindings: &str, whitelist: &[&str]) -> String {
                     let ident = acc.field.ident.clone().unwrap();
                     let ty = &acc.field.ty;
                     let prefix = acc.prefix.iter().map(|p| Ident::new(p, Span::call_site()));
+                    let addr = if core {
+                        // anonymous members are looked up by the loader, so
+                        // only named members make up the path
+                        let mut path = vec![item_id.clone()];
+                        path.extend(
+                            acc.prefix
+                                .iter()
+                                .skip(1)
+                                .filter(|p| !p.starts_with("__bindgen_anon"))
+                                .cloned(),
+                        );
+                        path.push(ident.to_string());
+                        let mut path = path.join(".").into_bytes();
+                        path.push(0);
+                        let len = path.len();
+                        let path = Literal::byte_string(&path);
+                        quote! {
+                            {
+                                #[link_section = #CORE_SECTION]
+                                static FIELD: [u8; #len] = *#path;
+                                (self as *const Self as *const u8)
+                                    .wrapping_add(&FIELD as *const _ as usize) as *const #ty
+                            }
+                        }
+                    } else {
+                        quote! { &#(#prefix).*.#ident }
+                    };
 
                     let _ = cache.entry(ident.to_string()).or_insert_with(|| match ty {
                         Type::Ptr(_) => {
                             quote! {
                                 pub fn #ident(&self) -> Option<#ty> {
-                                    let v = unsafe { bpf_probe_read(&#(#prefix).*.#ident) }.ok()?;
+                                    let v = unsafe { bpf_probe_read(#addr) }.ok()?;
                                     if v.is_null() {
                                         None
                                     } else {
@@ -184,7 +237,7 @@ pub fn generate_read_accessors(bindings: &str, whitelist: &[&str]) -> String {
                         _ => {
                             quote! {
                                 pub fn #ident(&self) -> Option<#ty> {
-                                    unsafe { bpf_probe_read(&#(#prefix).*.#ident) }.ok()
+                                    unsafe { bpf_probe_read(#addr) }.ok()
                                 }
                             }
                         }
@@ -12,7 +12,8 @@ use std::path::Path;
 use std::process::Command;
 use std::str;
 
-pub use crate::accessors::generate_read_accessors;
+pub use crate::accessors::{generate_core_read_accessors, generate_read_accessors};
+pub use crate::tracepoint::generate_tracepoint_bindings;
 use crate::build_constants::{kernel_headers, BUILD_FLAGS};
 use crate::CommandError;
 
@@ -95,6 +96,40 @@ pub use generated_bindings::*;
     Ok(())
 }
 
+pub fn cmd_tracepoint_bindgen(tracepoints: &[&str]) -> Result<(), CommandError> {
+    let mut bindings = String::new();
+    for tracepoint in tracepoints {
+        let mut parts = tracepoint.splitn(2, ':');
+        let (category, name) = match (parts.next(), parts.next()) {
+            (Some(category), Some(name)) => (category, name),
+            _ => {
+                return Err(CommandError(format!(
+                    "invalid tracepoint `{}', expected `category:name'",
+                    tracepoint
+                )))
+            }
+        };
+        bindings.push_str(&generate_tracepoint_bindings(category, name).map_err(CommandError)?);
+    }
+
+    let mut out = io::stdout();
+    writeln!(
+        &mut out,
+        r"
+mod generated_tracepoints {{
+#![allow(non_camel_case_types)]
+#![allow(clippy::all)]
+{}
+}}
+pub use generated_tracepoints::*;
+",
+        bindings
+    )
+    .unwrap();
+
+    Ok(())
+}
+
 #[derive(Debug)]
 struct Callbacks;
 
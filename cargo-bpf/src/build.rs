@@ -71,7 +71,26 @@ impl From<Error> for CommandError {
     }
 }
 
-fn build_probe(cargo: &Path, package: &Path, target_dir: &Path, probe: &str) -> Result<(), Error> {
+/// Options controlling how probes are compiled.
+#[derive(Debug, Default, Clone)]
+pub struct BuildOptions {
+    /// Emit BTF type information in the `.BTF` and `.BTF.ext` sections of
+    /// the generated ELF files.
+    ///
+    /// When available, the BTF information is loaded into the kernel together
+    /// with the programs and maps. Tools like `bpftool` use it to pretty-print
+    /// map contents, and the verifier uses it to annotate its log with the
+    /// source code lines.
+    pub btf: bool,
+}
+
+fn build_probe(
+    cargo: &Path,
+    package: &Path,
+    target_dir: &Path,
+    probe: &str,
+    options: &BuildOptions,
+) -> Result<(), Error> {
     fs::create_dir_all(&target_dir)?;
     let target_dir = target_dir.canonicalize().unwrap().join("bpf");
     let artifacts_dir = target_dir.join("programs").join(probe);
@@ -111,6 +130,11 @@ fn build_probe(cargo: &Path, package: &Path, target_dir: &Path, probe: &str) ->
             "--emit=llvm-bc -C panic=abort -C lto -C link-arg=-nostartfiles -C opt-level=3"
                 .split(' '),
         )
+        .args(if options.btf {
+            &["-C", "debuginfo=2"][..]
+        } else {
+            &[]
+        })
         .arg("-o")
         .arg(artifacts_dir.join(probe).to_str().unwrap())
         .status()?
@@ -137,21 +161,33 @@ fn build_probe(cargo: &Path, package: &Path, target_dir: &Path, probe: &str) ->
     let bc_file = bc_files.drain(..).next().unwrap();
     let opt_bc_file = bc_file.with_extension("bc.opt");
     let target = artifacts_dir.join(format!("{}.elf", probe));
-    unsafe { llvm::compile(&bc_file, &target, Some(&opt_bc_file)) }.map_err(|msg| {
-        Error::Compile(
-            probe.into(),
-            Some(format!("couldn't process IR file: {}", msg)),
-        )
-    })?;
+    unsafe { llvm::compile(&bc_file, &target, Some(&opt_bc_file), options.btf) }.map_err(
+        |msg| {
+            Error::Compile(
+                probe.into(),
+                Some(format!("couldn't process IR file: {}", msg)),
+            )
+        },
+    )?;
 
     Ok(())
 }
 
 pub fn build(
+    cargo: &Path,
+    package: &Path,
+    target_dir: &Path,
+    probes: Vec<String>,
+) -> Result<(), Error> {
+    build_with_options(cargo, package, target_dir, probes, &BuildOptions::default())
+}
+
+pub fn build_with_options(
     cargo: &Path,
     package: &Path,
     target_dir: &Path,
     mut probes: Vec<String>,
+    options: &BuildOptions,
 ) -> Result<(), Error> {
     let path = package.join("Cargo.toml");
     if !path.exists() {
@@ -166,15 +202,25 @@ pub fn build(
     unsafe { llvm::init() };
 
     for probe in probes {
-        build_probe(cargo, package, &target_dir, &probe)?;
+        build_probe(cargo, package, &target_dir, &probe, options)?;
     }
 
     Ok(())
 }
 
-pub fn cmd_build(programs: Vec<String>, target_dir: PathBuf) -> Result<(), CommandError> {
+pub fn cmd_build(
+    programs: Vec<String>,
+    target_dir: PathBuf,
+    options: &BuildOptions,
+) -> Result<(), CommandError> {
     let current_dir = std::env::current_dir().unwrap();
-    Ok(build(Path::new("cargo"), &current_dir, &target_dir, programs)?)
+    Ok(build_with_options(
+        Path::new("cargo"),
+        &current_dir,
+        &target_dir,
+        programs,
+        options,
+    )?)
 }
 
 pub fn probe_files(package: &Path) -> Result<Vec<String>, Error> {
@@ -186,7 +232,7 @@ pub fn probe_files(package: &Path) -> Result<Vec<String>, Error> {
         })
 }
 
-fn load_package(package: &Path) -> Result<Document, Error> {
+pub(crate) fn load_package(package: &Path) -> Result<Document, Error> {
     let path = package.join("Cargo.toml");
     if !path.exists() {
         return Err(Error::MissingManifest(path));
@@ -10,6 +10,8 @@ mod build_constants;
 mod accessors;
 #[cfg(feature = "bindings")]
 pub mod bindgen;
+#[cfg(feature = "bindings")]
+mod tracepoint;
 
 #[cfg(feature = "build")]
 mod build;
@@ -24,6 +26,8 @@ mod load;
 mod new;
 #[cfg(feature = "command-line")]
 mod new_program;
+#[cfg(feature = "command-line")]
+mod test;
 
 pub struct CommandError(pub String);
 
@@ -43,3 +47,5 @@ pub use load::load;
 pub use new::new;
 #[cfg(feature = "command-line")]
 pub use new_program::new_program;
+#[cfg(feature = "command-line")]
+pub use test::cmd_test;
@@ -91,17 +91,22 @@ unsafe fn inject_exit_call(context: LLVMContextRef, func: LLVMValueRef, builder:
     );
 }
 
-pub unsafe fn compile(input: &Path, output: &Path, bc_output: Option<&Path>) -> Result<()> {
+pub unsafe fn compile(
+    input: &Path,
+    output: &Path,
+    bc_output: Option<&Path>,
+    btf: bool,
+) -> Result<()> {
     let context = LLVMGetGlobalContext();
     let module = load_module(context, input)?;
-    process_ir(context, module)?;
+    process_ir(context, module, btf)?;
     let ret = compile_module(module, output, bc_output);
     LLVMDisposeModule(module);
 
     ret
 }
 
-pub unsafe fn process_ir(context: LLVMContextRef, module: LLVMModuleRef) -> Result<()> {
+pub unsafe fn process_ir(context: LLVMContextRef, module: LLVMModuleRef, btf: bool) -> Result<()> {
     let builder = LLVMCreateBuilderInContext(context);
 
     let no_inline = CString::new("noinline").unwrap();
@@ -131,9 +136,13 @@ pub unsafe fn process_ir(context: LLVMContextRef, module: LLVMModuleRef) -> Resu
         func = LLVMGetNextFunction(func);
     }
 
-    // the debug info generated by rustc seems to trigger a segfault in the
-    // BTF code in llvm, so strip it until that is fixed
-    LLVMStripModuleDebugInfo(module);
+    // the BPF backend generates the .BTF and .BTF.ext sections from the debug
+    // info. Some of the debug info generated by rustc seems to trigger a
+    // segfault in the BTF code in llvm, so strip it unless BTF was explicitly
+    // requested.
+    if !btf {
+        LLVMStripModuleDebugInfo(module);
+    }
 
     Ok(())
 }
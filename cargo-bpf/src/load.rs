@@ -9,24 +9,38 @@ use crate::CommandError;
 
 use futures::{future, stream::StreamExt};
 use hexdump::hexdump;
-use redbpf::xdp;
-use redbpf::{load::Loader, Program::*};
+use redbpf::load::{Loader, LoaderError};
+use redbpf::{cgroup, cpus, tc, xdp, Error, Program::*};
 use std::path::PathBuf;
 use tokio::runtime::Runtime;
 use tokio::signal;
 
+/// The frequency `perf_event` programs are run at on every CPU, in Hz.
+const SAMPLE_FREQ: u64 = 99;
+
 pub fn load(
     program: &PathBuf,
     interface: Option<&str>,
     uprobe_path: Option<&str>,
     pid: Option<i32>,
+    cgroup_path: Option<&str>,
 ) -> Result<(), CommandError> {
     let mut runtime = Runtime::new().unwrap();
     runtime.block_on(async {
         // Load all the programs and maps included in the program
-        let mut loader = Loader::load_file(&program).expect("error loading file");
+        let mut loader = match Loader::load_file(&program) {
+            Ok(loader) => loader,
+            Err(LoaderError::LoadError(_, Error::Verifier { program, log })) => {
+                return Err(CommandError(format!(
+                    "the verifier rejected program {}:\n{}",
+                    program, log
+                )))
+            }
+            Err(e) => return Err(CommandError(format!("error loading file: {:?}", e))),
+        };
 
-        // attach the programs
+        // attach the programs, keeping the links to the probes alive until exit
+        let mut links = Vec::new();
         for program in loader.module.programs.iter_mut() {
             let name = program.name().to_string();
             let ret = match program {
@@ -39,9 +53,21 @@ pub fn load(
                             ))
                         }
                     };
-                    prog.attach_xdp(&iface, xdp::Flags::default())
+                    prog.attach_xdp(&iface, xdp::Flags::default()).map(|_| None)
+                }
+                TcAction(prog) => {
+                    let iface = match interface {
+                        Some(i) => i,
+                        None => {
+                            return Err(CommandError(
+                                "tc program found, but no interface specified".to_string(),
+                            ))
+                        }
+                    };
+                    prog.attach_tc(&iface, tc::AttachPoint::Ingress, 1)
+                        .map(|_| None)
                 }
-                KProbe(prog) | KRetProbe(prog) => prog.attach_kprobe(&name, 0),
+                KProbe(prog) | KRetProbe(prog) => prog.attach_kprobe(&name, 0).map(Some),
                 UProbe(prog) | URetProbe(prog) => {
                     let path = match uprobe_path {
                         Some(p) => p,
@@ -52,14 +78,52 @@ pub fn load(
                         }
                     };
                     prog.attach_uprobe(Some(&prog.name()), 0, path, pid)
+                        .map(Some)
+                }
+                TracePoint(prog) => match (prog.category(), prog.tracepoint_name()) {
+                    (Some(category), Some(tp_name)) => {
+                        prog.attach_trace_point(&category, &tp_name).map(Some)
+                    }
+                    _ => {
+                        return Err(CommandError(format!(
+                            "invalid tracepoint `{}', expected `category:name'",
+                            name
+                        )))
+                    }
+                },
+                RawTracePoint(prog) => prog.attach_raw_trace_point(&name).map(Some),
+                BtfTracePoint(prog) => prog.attach_btf_trace_point().map(Some),
+                FEntry(prog) | FExit(prog) => prog.attach_fentry().map(Some),
+                Lsm(prog) => prog.attach_lsm().map(Some),
+                CgroupSkb(prog) | CgroupSock(prog) | CgroupSockAddr(prog) | SockOps(prog) => {
+                    let path = match cgroup_path {
+                        Some(p) => p,
+                        None => {
+                            return Err(CommandError(
+                                "cgroup program found, but no cgroup specified".to_string(),
+                            ))
+                        }
+                    };
+                    prog.attach_cgroup(path, cgroup::Flags::AllowMulti)
+                        .map(Some)
                 }
-                _ => Ok(()),
+                PerfEvent(prog) => cpus::get_online().map_err(Error::IO).and_then(|cpus| {
+                    // sample the stacks of every CPU
+                    for cpu in cpus {
+                        links.push(prog.attach_sampling(SAMPLE_FREQ, cpu)?);
+                    }
+                    Ok(None)
+                }),
+                _ => Ok(None),
             };
-            if let Err(e) = ret {
-                return Err(CommandError(format!(
-                    "failed to attach program {}: {:?}",
-                    name, e
-                )));
+            match ret {
+                Ok(link) => links.extend(link),
+                Err(e) => {
+                    return Err(CommandError(format!(
+                        "failed to attach program {}: {:?}",
+                        name, e
+                    )))
+                }
             }
         }
 
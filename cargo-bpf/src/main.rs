@@ -130,6 +130,31 @@ Loading eBPF programs requires admin priviledges, so you'll have to run
 $ sudo cargo bpf load -i eth0 target/bpf/programs/block_http.elf
 ```
 
+# Testing programs against packets
+
+`cargo bpf test` runs programs against packets without attaching them to an
+interface, using `BPF_PROG_TEST_RUN`. The test cases are listed in
+`Cargo.toml`:
+
+```toml
+[[package.metadata.cargo-bpf.test]]
+name = "drops http"
+probe = "block_http"
+fixture = "tests/http.pcap"
+expect = "drop"
+```
+
+Fixtures can be pcap files of Ethernet frames or hex dumps. Packets can also
+be given inline with `packet = "..."`, and the expected contents of maps after
+the run can be listed in `[[package.metadata.cargo-bpf.test.map]]` tables
+with `name`, `key` and `value` in hex.
+
+Like `load`, `test` needs admin priviledges:
+
+```
+$ sudo cargo bpf test
+```
+
 */
 use clap::{self, crate_authors, crate_version, App, AppSettings, Arg, SubCommand};
 use std::path::PathBuf;
@@ -172,7 +197,10 @@ fn main() {
                     .subcommand(
                         SubCommand::with_name("bindgen")
                             .about("Generates rust bindings from C headers")
-                            .arg(Arg::with_name("HEADER").required(true).help(
+                            .arg(Arg::with_name("TRACEPOINT").value_name("CATEGORY:NAME").long("tracepoint").multiple(true).number_of_values(1).help(
+                                "Generates the type of the record passed to the given tracepoint",
+                            ))
+                            .arg(Arg::with_name("HEADER").required_unless("TRACEPOINT").help(
                                 "The C header file to generate bindings for",
                             ))
                             .arg(Arg::with_name("BINDGEN_ARGS").required(false).multiple(true).help(
@@ -185,10 +213,26 @@ fn main() {
                             .arg(Arg::with_name("TARGET_DIR").value_name("DIRECTORY").long("target-dir").help(
                                 "Directory for all generated artifacts"
                             ))
+                            .arg(Arg::with_name("BTF").long("btf").help(
+                                "Emit BTF type information for maps and programs"
+                            ))
                             .arg(Arg::with_name("NAME").required(false).multiple(true).help(
                                 "The names of the programs to compile. When no names are specified, all the programs are built",
                             ))
                     )
+                    .subcommand(
+                        SubCommand::with_name("test")
+                            .about("Runs the eBPF programs against the packets listed in Cargo.toml")
+                            .arg(Arg::with_name("TARGET_DIR").value_name("DIRECTORY").long("target-dir").help(
+                                "Directory for all generated artifacts"
+                            ))
+                            .arg(Arg::with_name("BTF").long("btf").help(
+                                "Emit BTF type information for maps and programs"
+                            ))
+                            .arg(Arg::with_name("NAME").required(false).multiple(true).help(
+                                "Only run the tests whose name contains NAME. When no names are specified, all the tests are run",
+                            ))
+                    )
                     .subcommand(
                         SubCommand::with_name("load")
                             .about("Loads the specified eBPF program")
@@ -201,6 +245,9 @@ fn main() {
                             .arg(Arg::with_name("PID").value_name("PID").short("p").long("pid").help(
                                 "Attach uprobes to the given PID"
                             ))
+                            .arg(Arg::with_name("CGROUP").value_name("PATH").short("c").long("cgroup").help(
+                                "Attach cgroup programs to the given cgroup v2 directory"
+                            ))
                             .arg(Arg::with_name("PROGRAM").required(true).help(
                                 "Loads the specified eBPF program and outputs all the events generated",
                             ))
@@ -221,6 +268,13 @@ fn main() {
         }
     }
     if let Some(m) = matches.subcommand_matches("bindgen") {
+        if let Some(tracepoints) = m.values_of("TRACEPOINT") {
+            let tracepoints: Vec<&str> = tracepoints.collect();
+            if let Err(e) = cargo_bpf::bindgen::cmd_tracepoint_bindgen(&tracepoints[..]) {
+                clap::Error::with_description(&e.0, clap::ErrorKind::InvalidValue).exit()
+            }
+            return;
+        }
         let header = m.value_of("HEADER").map(PathBuf::from).unwrap();
         let extra_args = m
             .values_of("BINDGEN_ARGS")
@@ -240,7 +294,27 @@ fn main() {
             .values_of("NAME")
             .map(|i| i.map(String::from).collect())
             .unwrap_or_else(Vec::new);
-        if let Err(e) = cargo_bpf::cmd_build(programs, target_dir) {
+        let options = cargo_bpf::BuildOptions {
+            btf: m.is_present("BTF"),
+        };
+        if let Err(e) = cargo_bpf::cmd_build(programs, target_dir, &options) {
+            clap::Error::with_description(&e.0, clap::ErrorKind::InvalidValue).exit()
+        }
+    }
+    if let Some(m) = matches.subcommand_matches("test") {
+        let current_dir = std::env::current_dir().unwrap();
+        let target_dir = m
+            .value_of("TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(||current_dir.join("target"));
+        let filters = m
+            .values_of("NAME")
+            .map(|i| i.map(String::from).collect())
+            .unwrap_or_else(Vec::new);
+        let options = cargo_bpf::BuildOptions {
+            btf: m.is_present("BTF"),
+        };
+        if let Err(e) = cargo_bpf::cmd_test(filters, target_dir, &options) {
             clap::Error::with_description(&e.0, clap::ErrorKind::InvalidValue).exit()
         }
     }
@@ -249,7 +323,8 @@ fn main() {
         let interface = m.value_of("INTERFACE");
         let uprobe_path = m.value_of("UPROBE_PATH");
         let uprobe_pid = m.value_of("PID").map(|p| p.parse::<i32>().unwrap());
-        if let Err(e) = cargo_bpf::load(&program, interface, uprobe_path, uprobe_pid) {
+        let cgroup = m.value_of("CGROUP");
+        if let Err(e) = cargo_bpf::load(&program, interface, uprobe_path, uprobe_pid, cgroup) {
             clap::Error::with_description(&e.0, clap::ErrorKind::InvalidValue).exit()
         }
     }
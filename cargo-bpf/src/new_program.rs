@@ -103,8 +103,15 @@ use cty::*;
 
 // use one of the preludes
 // use redbpf_probes::kprobe::prelude::*;
+// use redbpf_probes::fentry::prelude::*;
+// use redbpf_probes::lsm::prelude::*;
+// use redbpf_probes::tracepoint::prelude::*;
+// use redbpf_probes::raw_tracepoint::prelude::*;
+// use redbpf_probes::perf_event::prelude::*;
 // use redbpf_probes::xdp::prelude::*;
 // use redbpf_probes::socket_filter::prelude::*;
+// use redbpf_probes::cgroup::prelude::*;
+// use redbpf_probes::sockmap::prelude::*;
 
 // Use the types you're going to share with userspace, eg:
 // use {lib}::{name}::SomeEvent;
@@ -0,0 +1,442 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*! Runs probes against packet fixtures.
+
+The test cases are listed in the `Cargo.toml` of the probe package:
+
+```toml
+[[package.metadata.cargo-bpf.test]]
+name = "drops http"
+# the probe, as in `cargo bpf build <probe>`
+probe = "block_http"
+# the program to run, only needed if the probe has more than one
+program = "block_port_80"
+# a pcap file of Ethernet frames or a hex dump, relative to the package
+fixture = "tests/http.pcap"
+# or the packet itself, as hex
+# packet = "ffffffffffff 000000000000 0800 ..."
+# the action returned by XDP programs, or the return value of the program
+expect = "drop"
+# expect = 0
+
+# the expected contents of the maps after running all the packets
+[[package.metadata.cargo-bpf.test.map]]
+name = "blocked"
+key = "50000000"
+value = "0100000000000000"
+```
+
+Every packet of the fixture is run through the program with
+`BPF_PROG_TEST_RUN` and must return the expected value. Only XDP, socket
+filter, `tc_action` and `cgroup_skb` programs can be run this way, and
+actions like `"drop"` can only be expected from XDP programs: use the return
+value for the others.
+
+Each test case loads the probe again, so the maps are empty when a test case
+starts. Maps pinned with `#[map(pinning = "by_name")]` are the exception:
+they're reused as is, so they keep the entries of the previous test cases and
+of any other program using them.
+*/
+
+use redbpf::{xdp, Module, Program};
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{Document, Item, Table};
+
+use crate::build::{build_with_options, load_package, BuildOptions};
+use crate::CommandError;
+
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NSEC: u32 = 0xa1b2_3c4d;
+const PCAP_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+struct TestCase {
+    name: String,
+    probe: String,
+    program: Option<String>,
+    packets: Vec<Vec<u8>>,
+    expect: Expect,
+    maps: Vec<MapExpectation>,
+}
+
+enum Expect {
+    Action(xdp::Action),
+    Retval(u32),
+}
+
+struct MapExpectation {
+    name: String,
+    key: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+/// Builds the probes of the package in the current directory and runs the
+/// test cases listed in its `Cargo.toml`.
+///
+/// Only the test cases whose name contains one of `filters` are run, or all
+/// of them if `filters` is empty.
+pub fn cmd_test(
+    filters: Vec<String>,
+    target_dir: PathBuf,
+    options: &BuildOptions,
+) -> Result<(), CommandError> {
+    let package = std::env::current_dir().unwrap();
+    let doc = load_package(&package)?;
+    let tests = parse_tests(&doc, &package)?
+        .into_iter()
+        .filter(|test| filters.is_empty() || filters.iter().any(|f| test.name.contains(f)))
+        .collect::<Vec<_>>();
+
+    let mut probes = tests.iter().map(|t| t.probe.clone()).collect::<Vec<_>>();
+    probes.sort();
+    probes.dedup();
+    if !probes.is_empty() {
+        build_with_options(Path::new("cargo"), &package, &target_dir, probes, options)?;
+    }
+
+    let _ = redbpf::raise_memlock_limit();
+    println!("\nrunning {} tests", tests.len());
+    let mut failed = Vec::new();
+    for test in tests.iter() {
+        let elf = target_dir
+            .join("bpf")
+            .join("programs")
+            .join(&test.probe)
+            .join(format!("{}.elf", test.probe));
+        match run_test(test, &elf) {
+            Ok(()) => println!("test {} ... ok", test.name),
+            Err(e) => {
+                println!("test {} ... FAILED", test.name);
+                failed.push((&test.name, e));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        println!("\nfailures:");
+        for (name, e) in failed.iter() {
+            println!("    {}: {}", name, e);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed\n",
+        if failed.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failed.len(),
+        failed.len()
+    );
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(CommandError(format!("{} tests failed", failed.len())))
+    }
+}
+
+fn run_test(test: &TestCase, elf: &Path) -> Result<(), String> {
+    let data = fs::read(elf).map_err(|e| format!("couldn't read {}: {}", elf.display(), e))?;
+    let mut module = Module::parse(&data).map_err(|e| format!("couldn't parse probe: {:?}", e))?;
+    let (version, license) = (module.version, module.license.clone());
+    let program = match &test.program {
+        Some(name) => module
+            .programs
+            .iter_mut()
+            .find(|p| p.name() == name.as_str()),
+        None if module.programs.len() == 1 => module.programs.first_mut(),
+        None => {
+            return Err(format!(
+                "probe `{}' has more than one program, set `program'",
+                test.probe
+            ))
+        }
+    }
+    .ok_or_else(|| format!("program not found in probe `{}'", test.probe))?;
+    let expect = expected_retval(program, &test.expect)?;
+    program
+        .load(version, license)
+        .map_err(|e| format!("couldn't load program {}: {:?}", program.name(), e))?;
+
+    for (i, packet) in test.packets.iter().enumerate() {
+        let run = program
+            .test_run(packet, 1)
+            .map_err(|e| format!("couldn't run program {}: {:?}", program.name(), e))?;
+        if run.retval != expect {
+            return Err(format!(
+                "packet {}: expected {}, got {}",
+                i,
+                verdict(program, expect),
+                verdict(program, run.retval)
+            ));
+        }
+    }
+
+    for expected in test.maps.iter() {
+        let map = module
+            .maps
+            .iter()
+            .find(|m| m.name == expected.name)
+            .ok_or_else(|| format!("map `{}' not found", expected.name))?;
+        let value = map
+            .get_bytes(&expected.key)
+            .map_err(|e| format!("couldn't read map `{}': {:?}", expected.name, e))?;
+        if value != expected.value {
+            return Err(format!(
+                "map `{}', key {}: expected {}, got {}",
+                expected.name,
+                to_hex(&expected.key),
+                expected
+                    .value
+                    .as_deref()
+                    .map(to_hex)
+                    .unwrap_or_else(|| "none".into()),
+                value
+                    .as_deref()
+                    .map(to_hex)
+                    .unwrap_or_else(|| "none".into())
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the value `program` must return, or an error if it can't be tested.
+fn expected_retval(program: &Program, expect: &Expect) -> Result<u32, String> {
+    match (program, expect) {
+        (Program::XDP(_), Expect::Action(action)) => Ok(*action as u32),
+        (_, Expect::Action(action)) => Err(format!(
+            "program {} isn't an XDP program and can't return {:?}, expect its return value",
+            program.name(),
+            action
+        )),
+        (Program::XDP(_), Expect::Retval(retval))
+        | (Program::SocketFilter(_), Expect::Retval(retval))
+        | (Program::TcAction(_), Expect::Retval(retval))
+        | (Program::CgroupSkb(_), Expect::Retval(retval)) => Ok(*retval),
+        _ => Err(format!(
+            "program {} can't be tested, only XDP, socket filter, tc_action and cgroup_skb programs can",
+            program.name()
+        )),
+    }
+}
+
+fn verdict(program: &Program, retval: u32) -> String {
+    match (program, xdp::Action::from_u32(retval)) {
+        (Program::XDP(_), Some(action)) => format!("{:?}", action),
+        _ => retval.to_string(),
+    }
+}
+
+fn parse_tests(doc: &Document, package: &Path) -> Result<Vec<TestCase>, CommandError> {
+    let tests = match &doc["package"]["metadata"]["cargo-bpf"]["test"] {
+        Item::ArrayOfTables(tests) => tests,
+        Item::None => return Ok(Vec::new()),
+        _ => {
+            return Err(CommandError(
+                "expected [[package.metadata.cargo-bpf.test]] tables".to_string(),
+            ))
+        }
+    };
+
+    tests
+        .iter()
+        .enumerate()
+        .map(|(i, test)| {
+            parse_test(test, package).map_err(|e| CommandError(format!("test {}: {}", i, e)))
+        })
+        .collect()
+}
+
+fn parse_test(test: &Table, package: &Path) -> Result<TestCase, String> {
+    let probe = test["probe"]
+        .as_str()
+        .ok_or_else(|| "missing `probe'".to_string())?
+        .to_string();
+    let program = test["program"].as_str().map(String::from);
+    let name = test["name"]
+        .as_str()
+        .map(String::from)
+        .unwrap_or_else(|| program.clone().unwrap_or_else(|| probe.clone()));
+
+    let packets = match (test["fixture"].as_str(), test["packet"].as_str()) {
+        (Some(fixture), None) => {
+            let path = package.join(fixture);
+            let data =
+                fs::read(&path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+            if path.extension().map(|ext| ext == "pcap").unwrap_or(false) {
+                parse_pcap(&data)?
+            } else {
+                vec![parse_hex(&String::from_utf8_lossy(&data))?]
+            }
+        }
+        (None, Some(packet)) => vec![parse_hex(packet)?],
+        _ => return Err("expected one of `fixture' or `packet'".to_string()),
+    };
+
+    let expect = match (test["expect"].as_str(), test["expect"].as_integer()) {
+        (Some(action), _) => Expect::Action(parse_action(action)?),
+        (_, Some(retval)) => Expect::Retval(retval as u32),
+        _ => return Err("missing `expect'".to_string()),
+    };
+
+    let mut maps = Vec::new();
+    match &test["map"] {
+        Item::ArrayOfTables(tables) => {
+            for map in tables.iter() {
+                maps.push(MapExpectation {
+                    name: map["name"]
+                        .as_str()
+                        .ok_or_else(|| "missing map `name'".to_string())?
+                        .to_string(),
+                    key: parse_hex(
+                        map["key"]
+                            .as_str()
+                            .ok_or_else(|| "missing map `key'".to_string())?,
+                    )?,
+                    // a missing value means that the key must not be in the map
+                    value: map["value"].as_str().map(parse_hex).transpose()?,
+                });
+            }
+        }
+        Item::None => {}
+        _ => return Err("expected [[package.metadata.cargo-bpf.test.map]] tables".to_string()),
+    }
+
+    Ok(TestCase {
+        name,
+        probe,
+        program,
+        packets,
+        expect,
+        maps,
+    })
+}
+
+fn parse_action(action: &str) -> Result<xdp::Action, String> {
+    match action.to_lowercase().as_str() {
+        "aborted" => Ok(xdp::Action::Aborted),
+        "drop" => Ok(xdp::Action::Drop),
+        "pass" => Ok(xdp::Action::Pass),
+        "tx" => Ok(xdp::Action::Tx),
+        "redirect" => Ok(xdp::Action::Redirect),
+        _ => Err(format!("unknown XDP action `{}'", action)),
+    }
+}
+
+/// Parses a hex dump, ignoring whitespace.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits = hex
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<_>>();
+    if digits.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in `{}'", hex.trim()));
+    }
+
+    digits
+        .chunks(2)
+        .map(|byte| {
+            let byte = byte.iter().collect::<String>();
+            u8::from_str_radix(&byte, 16).map_err(|_| format!("invalid hex byte `{}'", byte))
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the packets of a pcap file.
+///
+/// Only captures of Ethernet frames are supported, since that's what XDP and
+/// `tc` programs are run on.
+fn parse_pcap(data: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    if data.len() < PCAP_HEADER_LEN {
+        return Err("invalid pcap file".to_string());
+    }
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let read_u32 = |offset: usize| -> u32 {
+        let bytes = [
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ];
+        if magic == PCAP_MAGIC || magic == PCAP_MAGIC_NSEC {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    if read_u32(0) != PCAP_MAGIC && read_u32(0) != PCAP_MAGIC_NSEC {
+        return Err("invalid pcap file".to_string());
+    }
+    let link_type = read_u32(20);
+    if link_type != LINKTYPE_ETHERNET {
+        return Err(format!(
+            "unsupported pcap link type {}, only Ethernet captures are supported",
+            link_type
+        ));
+    }
+
+    let mut packets = Vec::new();
+    let mut offset = PCAP_HEADER_LEN;
+    while offset + PCAP_RECORD_HEADER_LEN <= data.len() {
+        let len = read_u32(offset + 8) as usize;
+        offset += PCAP_RECORD_HEADER_LEN;
+        if offset + len > data.len() {
+            return Err("truncated pcap file".to_string());
+        }
+        packets.push(data[offset..offset + len].to_vec());
+        offset += len;
+    }
+
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(
+            parse_hex("00ff 10\n0a").unwrap(),
+            vec![0x00, 0xff, 0x10, 0x0a]
+        );
+        assert!(parse_hex("0").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("Drop"), Ok(xdp::Action::Drop));
+        assert!(parse_action("shot").is_err());
+    }
+
+    #[test]
+    fn test_parse_pcap() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&PCAP_MAGIC.to_be_bytes());
+        data.extend_from_slice(&[0; PCAP_HEADER_LEN - 8]);
+        data.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        for packet in &[&[1u8, 2, 3][..], &[4u8][..]] {
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            data.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            data.extend_from_slice(packet);
+        }
+        assert_eq!(parse_pcap(&data).unwrap(), vec![vec![1, 2, 3], vec![4]]);
+
+        // captures on `any` have Linux cooked headers instead of Ethernet
+        const LINKTYPE_LINUX_SLL: u32 = 113;
+        data[20..24].copy_from_slice(&LINKTYPE_LINUX_SLL.to_be_bytes());
+        assert!(parse_pcap(&data).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+const TRACEFS_EVENTS: [&str; 2] = [
+    "/sys/kernel/debug/tracing/events",
+    "/sys/kernel/tracing/events",
+];
+
+const RUST_KEYWORDS: [&str; 10] = [
+    "type", "ref", "fn", "mod", "move", "self", "struct", "impl", "match", "loop",
+];
+
+#[derive(Debug, PartialEq)]
+struct Field {
+    name: String,
+    offset: usize,
+    size: usize,
+    signed: bool,
+    pointer: bool,
+    array_len: Option<usize>,
+}
+
+/// Generates the rust type of the record passed to the `category:name` tracepoint.
+///
+/// The layout is read from the tracepoint `format` file in tracefs. The
+/// resulting struct is named `trace_event_raw_<name>`, after the type the
+/// kernel uses for the same record, and can be used with
+/// `redbpf_probes::tracepoint::TracePointContext`.
+pub fn generate_tracepoint_bindings(category: &str, name: &str) -> Result<String, String> {
+    let path = TRACEFS_EVENTS
+        .iter()
+        .map(|dir| PathBuf::from(dir).join(category).join(name).join("format"))
+        .find(|path| path.exists())
+        .ok_or_else(|| format!("tracepoint {}:{} not found", category, name))?;
+    let format = fs::read_to_string(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+
+    tracepoint_struct(name, &format)
+}
+
+fn tracepoint_struct(name: &str, format: &str) -> Result<String, String> {
+    let fields = parse_format(format)?;
+    let mut out = String::new();
+    writeln!(out, "#[repr(C)]").unwrap();
+    writeln!(out, "#[derive(Clone, Copy)]").unwrap();
+    writeln!(out, "pub struct trace_event_raw_{} {{", name).unwrap();
+    let mut offset = 0;
+    for field in fields.iter() {
+        if field.offset < offset {
+            return Err(format!("overlapping field `{}'", field.name));
+        }
+        if field.offset > offset {
+            writeln!(
+                out,
+                "    pub _pad_{}: [u8; {}],",
+                offset,
+                field.offset - offset
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            "    pub {}: {},",
+            field_ident(&field.name),
+            field_type(field)
+        )
+        .unwrap();
+        offset = field.offset + field.size;
+    }
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
+
+fn parse_format(format: &str) -> Result<Vec<Field>, String> {
+    format
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with("field:"))
+        .map(parse_field)
+        .collect()
+}
+
+fn parse_field(line: &str) -> Result<Field, String> {
+    let mut decl = None;
+    let mut offset = None;
+    let mut size = None;
+    let mut signed = false;
+    for attr in line.split(';').map(str::trim).filter(|a| !a.is_empty()) {
+        let mut kv = attr.splitn(2, ':');
+        let (key, value) = (kv.next().unwrap(), kv.next().unwrap_or("").trim());
+        match key {
+            "field" => decl = Some(value),
+            "offset" => offset = value.parse::<usize>().ok(),
+            "size" => size = value.parse::<usize>().ok(),
+            "signed" => signed = value == "1",
+            _ => {}
+        }
+    }
+    let invalid = || format!("invalid field: {}", line);
+    let decl = decl.ok_or_else(invalid)?;
+    let offset = offset.ok_or_else(invalid)?;
+    let size = size.ok_or_else(invalid)?;
+
+    // __data_loc fields are a u32 holding the length and offset of the data
+    let data_loc = decl.starts_with("__data_loc");
+    let ident = decl.rsplit(&[' ', '*'][..]).next().ok_or_else(invalid)?;
+    let (name, array_len) = match ident.find('[') {
+        Some(i) => {
+            let len = ident[i + 1..].trim_end_matches(']').parse::<usize>().ok();
+            (&ident[..i], len)
+        }
+        None => (ident, None),
+    };
+    if name.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(Field {
+        name: name.to_string(),
+        offset,
+        size,
+        signed: signed && !data_loc,
+        pointer: !data_loc && decl.contains('*'),
+        array_len: if data_loc { None } else { array_len },
+    })
+}
+
+fn field_ident(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn field_type(field: &Field) -> String {
+    let int_type = |size: usize| {
+        let bits = match size {
+            1 | 2 | 4 | 8 => size * 8,
+            _ => return None,
+        };
+        Some(format!("{}{}", if field.signed { "i" } else { "u" }, bits))
+    };
+    if field.pointer && field.array_len.is_none() && field.size == 8 {
+        return "*const ::cty::c_void".to_string();
+    }
+    match field.array_len {
+        Some(len) if len > 0 && field.size % len == 0 => match int_type(field.size / len) {
+            Some(ty) => format!("[{}; {}]", ty, len),
+            None => format!("[u8; {}]", field.size),
+        },
+        _ => int_type(field.size).unwrap_or_else(|| format!("[u8; {}]", field.size)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SYS_ENTER_OPENAT: &str = r#"name: sys_enter_openat
+ID: 614
+format:
+	field:unsigned short common_type;	offset:0;	size:2;	signed:0;
+	field:unsigned char common_flags;	offset:2;	size:1;	signed:0;
+	field:unsigned char common_preempt_count;	offset:3;	size:1;	signed:0;
+	field:int common_pid;	offset:4;	size:4;	signed:1;
+
+	field:int __syscall_nr;	offset:8;	size:4;	signed:1;
+	field:int dfd;	offset:16;	size:8;	signed:0;
+	field:const char * filename;	offset:24;	size:8;	signed:0;
+	field:int flags;	offset:32;	size:8;	signed:0;
+	field:umode_t mode;	offset:40;	size:8;	signed:0;
+
+print fmt: "dfd: 0x%08lx, filename: 0x%08lx", ((unsigned long)(REC->dfd)), ((unsigned long)(REC->filename))
+"#;
+
+    #[test]
+    fn test_parse_field() {
+        let field = parse_field("field:char prev_comm[16];	offset:8;	size:16;	signed:1;").unwrap();
+        assert_eq!(field.name, "prev_comm");
+        assert_eq!(field.array_len, Some(16));
+        assert_eq!(field_type(&field), "[i8; 16]");
+
+        let field =
+            parse_field("field:__data_loc char[] name;	offset:8;	size:4;	signed:1;").unwrap();
+        assert_eq!(field.name, "name");
+        assert_eq!(field_type(&field), "u32");
+    }
+
+    #[test]
+    fn test_tracepoint_struct() {
+        let code = tracepoint_struct("sys_enter_openat", SYS_ENTER_OPENAT).unwrap();
+        assert!(code.contains("pub struct trace_event_raw_sys_enter_openat {"));
+        assert!(code.contains("    pub common_type: u16,\n"));
+        assert!(code.contains("    pub __syscall_nr: i32,\n    pub _pad_12: [u8; 4],\n"));
+        assert!(code.contains("    pub dfd: u64,\n"));
+        assert!(code.contains("    pub filename: *const ::cty::c_void,\n"));
+    }
+}
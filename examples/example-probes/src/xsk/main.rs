@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+use redbpf_probes::xdp::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+#[map("sockets")]
+static mut sockets: XskMap = XskMap::with_max_entries(64);
+
+#[xdp]
+fn to_user_space(ctx: XdpContext) -> XdpResult {
+    // packets of the queues without a socket go through the stack as usual
+    Ok(unsafe { sockets.redirect(ctx.rx_queue_index()) })
+}
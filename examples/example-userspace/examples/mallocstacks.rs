@@ -79,9 +79,12 @@ fn main() {
     let _ = Runtime::new().unwrap().block_on(async {
         let mut loaded = Loader::load(probe_code()).expect("error loading BPF program");
 
+        let mut links = Vec::new();
         for prb in loaded.uprobes_mut() {
-            prb.attach_uprobe(Some(&prb.name()), 0, "libc", Some(pid))
-                .expect(&format!("error attaching uprobe program {}", prb.name()));
+            links.push(
+                prb.attach_uprobe(Some(&prb.name()), 0, "libc", Some(pid))
+                    .expect(&format!("error attaching uprobe program {}", prb.name())),
+            );
         }
         start_perf_event_handler(loaded, acc.clone());
 
@@ -82,9 +82,12 @@ fn main() {
     ));
     let _ = Runtime::new().unwrap().block_on(async {
         let mut loaded = Loader::load(probe_code()).expect("error loading BPF program");
+        let mut links = Vec::new();
         for kp in loaded.kprobes_mut() {
-            kp.attach_kprobe(&kp.name(), 0)
-                .expect(&format!("error attaching kprobe program {}", kp.name()));
+            links.push(
+                kp.attach_kprobe(&kp.name(), 0)
+                    .expect(&format!("error attaching kprobe program {}", kp.name())),
+            );
         }
 
         start_perf_event_handler(loaded, counts.clone());
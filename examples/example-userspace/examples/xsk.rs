@@ -0,0 +1,44 @@
+use redbpf::load::Loader;
+use redbpf::xdp;
+use redbpf::{XskConfig, XskMode, XskSocket};
+use std::env;
+use std::process;
+
+fn main() {
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("You must be root to use eBPF!");
+        process::exit(1);
+    }
+    let interface = match env::args().nth(1) {
+        Some(interface) => interface,
+        None => {
+            eprintln!("usage: xsk <interface>");
+            process::exit(1);
+        }
+    };
+
+    let mut loaded = Loader::load(probe_code()).expect("error loading BPF program");
+    for prog in loaded.xdps_mut() {
+        // generic XDP works with any interface, but only in copy mode
+        let name = prog.name();
+        prog.attach_xdp(&interface, xdp::Flags::SkbMode)
+            .unwrap_or_else(|e| panic!("error attaching XDP program {}: {:?}", name, e));
+    }
+
+    let config = XskConfig {
+        mode: XskMode::Copy,
+        ..XskConfig::default()
+    };
+    let map = loaded.map("sockets").expect("sockets map not found");
+    let mut socket =
+        XskSocket::bind(map, &interface, 0, config).expect("error binding AF_XDP socket");
+    println!("receiving packets of queue 0 of {}", interface);
+    loop {
+        socket.poll(None).expect("error waiting for packets");
+        socket.recv(|packet| println!("received {} bytes", packet.len()));
+    }
+}
+
+fn probe_code() -> &'static [u8] {
+    include_bytes!(concat!(env!("OUT_DIR"), "/target/bpf/programs/xsk/xsk.elf"))
+}
@@ -17,6 +17,7 @@
 #include <linux/version.h>
 #include <uapi/linux/ptrace.h>
 #include <uapi/linux/bpf.h>
+#include <uapi/linux/bpf_perf_event.h>
 #include <net/sock.h>
 #include <net/inet_sock.h>
 #include "bpf_helpers.h"
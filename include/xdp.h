@@ -14,6 +14,10 @@
 #include <linux/tcp.h>
 #include <linux/in.h>
 #include <linux/ip.h>
+#include <linux/in6.h>
+#include <linux/ipv6.h>
+#include <linux/icmp.h>
+#include <linux/icmpv6.h>
 #include <linux/if_ether.h>
 #pragma clang diagnostic pop
 
@@ -51,12 +51,12 @@ use proc_macro::{Diagnostic, Level};
 use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
 use quote::quote;
 use std::str;
-use syn::parse::{Parse, ParseStream};
+use syn::parse::{Parse, ParseStream, Parser};
 use syn::punctuated::Punctuated;
 use syn::token::Comma;
 use syn::{
-    parse_macro_input, parse_quote, parse_str, Expr, ExprLit, File, ItemFn, ItemStatic, Lit, Meta,
-    Result,
+    parse_macro_input, parse_quote, parse_str, Expr, ExprLit, File, ItemFn, ItemStatic, Lit,
+    LitByteStr, MetaNameValue, Result,
 };
 
 fn inline_string_literal(e: &Expr) -> (TokenStream2, TokenStream2) {
@@ -168,6 +168,20 @@ pub fn impl_network_buffer_array(_: TokenStream) -> TokenStream {
 /// **NOTE:** The `#[map("foo")` (which uses link section `maps/foo`) has
 /// been deprecated in favor of `#[map]` or `#[map(link_section = "maps/foo")]`
 ///
+/// Maps placed in `maps/<name>` sections also get a `____btf_map_<name>`
+/// type describing their key and value types. When the program is built with
+/// BTF type information, the loader uses it to attach the key and value
+/// types to the map.
+///
+/// Maps declared with `#[map(pinning = "by_name")]` are pinned to
+/// `/sys/fs/bpf/<name>` when loaded. If a map is already pinned there, the
+/// loader reuses it instead of creating a new one, so its contents survive
+/// restarts of the userspace program.
+///
+/// Maps of maps, like `ArrayOfMaps` and `HashOfMaps`, must name the map the
+/// loader uses as a template for their inner maps with
+/// `#[map(inner_map = "<name>")]`.
+///
 /// # Example
 ///
 /// ```no_run
@@ -183,23 +197,44 @@ pub fn impl_network_buffer_array(_: TokenStream) -> TokenStream {
 /// struct Query {
 /// // ...
 /// }
+///
+/// // Will be pinned to /sys/fs/bpf/flows
+/// #[map(pinning = "by_name")]
+/// static mut flows: HashMap<u32, u64> = HashMap::with_max_entries(1024);
+///
+/// // Holds maps like rules_template
+/// #[map]
+/// static mut rules_template: HashMap<u32, u32> = HashMap::with_max_entries(1024);
+/// #[map(inner_map = "rules_template")]
+/// static mut tenants: ArrayOfMaps<HashMap<u32, u32>> = ArrayOfMaps::with_max_entries(64);
 /// ```
 #[proc_macro_attribute]
 pub fn map(attrs: TokenStream, item: TokenStream) -> TokenStream {
-    let section_name = if attrs.is_empty() {
-        let item = item.clone();
-        let item = parse_macro_input!(item as ItemStatic);
-        format!("maps/{}", item.ident.to_string())
-    } else {
-        match syn::parse::<Meta>(attrs.clone()) {
-            // First try #[map(section_name = "..")]
-            Ok(Meta::NameValue(mnv)) => {
-                if !mnv.path.is_ident("link_section") {
-                    panic!("expected #[map(link_section = \"...\")]");
-                }
-                match mnv.lit {
-                    Lit::Str(lit_str) => lit_str.value(),
-                    _ => panic!("expected #[map(link_section = \"...\")]"),
+    let mut section_name = None;
+    let mut pinning = false;
+    let mut inner_map = None;
+    if !attrs.is_empty() {
+        match Punctuated::<MetaNameValue, Comma>::parse_terminated.parse(attrs.clone()) {
+            // First try #[map(link_section = "..", pinning = "..")]
+            Ok(args) => {
+                for arg in args {
+                    let value = match arg.lit {
+                        Lit::Str(lit_str) => lit_str.value(),
+                        _ => panic!("expected #[map(link_section = \"...\")]"),
+                    };
+                    if arg.path.is_ident("link_section") {
+                        section_name = Some(value);
+                    } else if arg.path.is_ident("pinning") {
+                        pinning = match value.as_str() {
+                            "by_name" => true,
+                            "none" => false,
+                            _ => panic!("expected #[map(pinning = \"by_name\")]"),
+                        };
+                    } else if arg.path.is_ident("inner_map") {
+                        inner_map = Some(value);
+                    } else {
+                        panic!("expected #[map(link_section = \"...\")]");
+                    }
                 }
             }
             // Fallback to deprecated #[map("..")]
@@ -210,23 +245,121 @@ pub fn map(attrs: TokenStream, item: TokenStream) -> TokenStream {
                     #[cfg(RUSTC_IS_NIGHTLY)]
                     Diagnostic::new(Level::Warning, "`#[map(\"..\")` has been deprecated in favor of `#[map]` or `#[map(link_section = \"..\")]`")
                         .emit();
-                    format!("maps/{}", s.value())
+                    section_name = Some(format!("maps/{}", s.value()));
                 }
                 _ => panic!("expected #[map(\"...\")]"),
             },
         }
+    }
+    let section_name = match section_name {
+        Some(section_name) => section_name,
+        None => {
+            let item = item.clone();
+            let item = parse_macro_input!(item as ItemStatic);
+            format!("maps/{}", item.ident.to_string())
+        }
     };
 
+    let btf = map_btf(&section_name, &item);
+    let pin = if pinning {
+        map_pinning(&section_name)
+    } else {
+        quote! {}
+    };
+    let inner = match inner_map {
+        Some(inner_map) => map_inner_map(&section_name, &inner_map),
+        None => quote! {},
+    };
     let item = TokenStream2::from(item);
     let tokens = quote! {
         #[no_mangle]
         #[link_section = #section_name]
         #item
+
+        #btf
+        #pin
+        #inner
     };
 
     tokens.into()
 }
 
+/// Generates the `____pin_map_<name>` symbol telling the loader to pin the
+/// map.
+fn map_pinning(section_name: &str) -> TokenStream2 {
+    if !section_name.starts_with("maps/") {
+        panic!("pinning is only supported for maps in `maps/<name>` sections");
+    }
+    let ident = match parse_str::<Ident>(&format!("____pin_map_{}", &section_name[5..])) {
+        Ok(ident) => ident,
+        Err(_) => panic!("invalid map name `{}'", &section_name[5..]),
+    };
+
+    quote! {
+        #[allow(non_upper_case_globals)]
+        #[no_mangle]
+        #[link_section = "maps.ext"]
+        static #ident: [u8; 0] = [];
+    }
+}
+
+/// Generates the `____inner_map_<name>` symbol holding the name of the inner
+/// map template of a map of maps.
+fn map_inner_map(section_name: &str, inner_map: &str) -> TokenStream2 {
+    if !section_name.starts_with("maps/") {
+        panic!("inner_map is only supported for maps in `maps/<name>` sections");
+    }
+    let ident = match parse_str::<Ident>(&format!("____inner_map_{}", &section_name[5..])) {
+        Ok(ident) => ident,
+        Err(_) => panic!("invalid map name `{}'", &section_name[5..]),
+    };
+    let len = inner_map.len();
+    let name = LitByteStr::new(inner_map.as_bytes(), Span::call_site());
+
+    quote! {
+        #[allow(non_upper_case_globals)]
+        #[no_mangle]
+        #[link_section = "maps.ext"]
+        static #ident: [u8; #len] = *#name;
+    }
+}
+
+/// Generates the `____btf_map_<name>` type describing the key and value types
+/// of the map.
+///
+/// This follows the libbpf convention, and is used by the loader to find the
+/// BTF type ids of the key and value of the map.
+fn map_btf(section_name: &str, item: &TokenStream) -> TokenStream2 {
+    let item = match syn::parse::<ItemStatic>(item.clone()) {
+        Ok(item) => item,
+        Err(_) => return quote! {},
+    };
+    if !section_name.starts_with("maps/") {
+        return quote! {};
+    }
+    let ident = match parse_str::<Ident>(&format!("____btf_map_{}", &section_name[5..])) {
+        Ok(ident) => ident,
+        Err(_) => return quote! {},
+    };
+    let ty = &item.ty;
+
+    quote! {
+        #[allow(non_camel_case_types)]
+        #[repr(C)]
+        struct #ident {
+            key: <#ty as ::redbpf_probes::maps::MapBtf>::KeyType,
+            value: <#ty as ::redbpf_probes::maps::MapBtf>::ValueType,
+        }
+
+        unsafe impl Sync for #ident {}
+
+        #[allow(non_upper_case_globals)]
+        #[no_mangle]
+        #[link_section = "maps.ext"]
+        static #ident: [#ident; 0] = [];
+    }
+}
+
 fn probe_impl(ty: &str, attrs: TokenStream, item: ItemFn, mut name: String) -> TokenStream {
     if !attrs.is_empty() {
         name = match parse_macro_input!(attrs as Expr) {
@@ -337,6 +470,256 @@ pub fn uretprobe(attrs: TokenStream, item: TokenStream) -> TokenStream {
     probe_impl("uretprobe", attrs, wrapper, name)
 }
 
+/// Attribute macro that must be used to define [`tracepoints`](https://www.kernel.org/doc/Documentation/trace/tracepoints.txt).
+///
+/// The attribute takes the tracepoint to attach to, in the form
+/// `"category:name"`. The probe function receives a
+/// [`TracePointContext`](https://ingraind.org/api/redbpf_probes/tracepoint/struct.TracePointContext.html)
+/// parameterized with the layout of the tracepoint record.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::tracepoint::prelude::*;
+///
+/// #[repr(C)]
+/// pub struct sys_enter_args {
+///     pub common: TracePointCommon,
+///     pub __syscall_nr: i32,
+///     pub _pad_12: [u8; 4],
+///     pub args: [u64; 6],
+/// }
+///
+/// #[tracepoint("syscalls:sys_enter_execve")]
+/// fn execve_enter(ctx: TracePointContext<sys_enter_args>) {
+///     // this is executed when execve() is invoked
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn tracepoint(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    if attrs.is_empty() {
+        panic!("expected #[tracepoint(\"category:name\")]");
+    }
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ctx: *mut c_void) -> i32 {
+            let ctx = ::redbpf_probes::tracepoint::TracePointContext::new(ctx);
+            let _ = #ident(ctx);
+            return 0;
+
+            #item
+        }
+    };
+    probe_impl("tracepoint", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define raw tracepoints.
+///
+/// The attribute takes the name of the tracepoint to attach to, without its
+/// category. The probe function receives a
+/// [`RawTracePointContext`](https://ingraind.org/api/redbpf_probes/raw_tracepoint/struct.RawTracePointContext.html)
+/// holding the arguments of the tracepoint.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::raw_tracepoint::prelude::*;
+///
+/// #[raw_tracepoint("sched_switch")]
+/// fn sched_switch(ctx: RawTracePointContext) {
+///     // this is executed every time a process is scheduled out
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn raw_tracepoint(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ctx: *mut c_void) -> i32 {
+            let ctx = ::redbpf_probes::raw_tracepoint::RawTracePointContext::new(ctx);
+            let _ = #ident(ctx);
+            return 0;
+
+            #item
+        }
+    };
+    probe_impl("raw_tracepoint", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define BTF tracepoints.
+///
+/// Like raw tracepoints, BTF tracepoints take the name of the tracepoint to
+/// attach to, without its category, and the probe function receives a
+/// [`RawTracePointContext`](https://ingraind.org/api/redbpf_probes/raw_tracepoint/struct.RawTracePointContext.html).
+/// The tracepoint is looked up in the BTF of the running kernel, which lets
+/// the verifier check accesses to the arguments.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::raw_tracepoint::prelude::*;
+///
+/// #[tp_btf("sched_switch")]
+/// fn sched_switch(ctx: RawTracePointContext) {
+///     // this is executed every time a process is scheduled out
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn tp_btf(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ctx: *mut c_void) -> i32 {
+            let ctx = ::redbpf_probes::raw_tracepoint::RawTracePointContext::new(ctx);
+            let _ = #ident(ctx);
+            return 0;
+
+            #item
+        }
+    };
+    probe_impl("tp_btf", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define BPF LSM programs.
+///
+/// The attribute takes the name of the LSM hook to attach to, like
+/// `file_open`. The probe function receives a
+/// [`LsmContext`](https://ingraind.org/api/redbpf_probes/lsm/struct.LsmContext.html)
+/// holding the arguments of the hook, and returns an `LsmAction` to allow or
+/// deny the operation.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::lsm::prelude::*;
+///
+/// #[lsm("file_open")]
+/// fn check_open(ctx: LsmContext) -> LsmAction {
+///     // this is executed every time a file is opened
+///     LsmAction::Allow
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn lsm(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ctx: *mut c_void) -> i32 {
+            let ctx = ::redbpf_probes::lsm::LsmContext::new(ctx);
+            return match #ident(ctx) {
+                ::redbpf_probes::lsm::LsmAction::Allow => 0,
+                // -EPERM
+                ::redbpf_probes::lsm::LsmAction::Deny => -1,
+                ::redbpf_probes::lsm::LsmAction::Errno(errno) => -errno
+            };
+
+            #item
+        }
+    };
+    probe_impl("lsm", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define `perf_event` programs.
+///
+/// `perf_event` programs run every time the perf event they're attached to
+/// is sampled. The probe function receives a
+/// [`PerfEventContext`](https://ingraind.org/api/redbpf_probes/perf_event/struct.PerfEventContext.html)
+/// holding the registers of the interrupted task.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::perf_event::prelude::*;
+///
+/// #[map]
+/// static mut STACKS: StackTrace = StackTrace::with_max_entries(1024);
+///
+/// #[perf_event]
+/// fn sample(ctx: PerfEventContext) {
+///     let _ = unsafe { STACKS.stack_id(ctx.regs().ctx, 0) };
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn perf_event(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ctx: *mut ::redbpf_probes::bindings::bpf_perf_event_data) -> i32 {
+            let ctx = ::redbpf_probes::perf_event::PerfEventContext { ctx };
+            let _ = #ident(ctx);
+            return 0;
+
+            #item
+        }
+    };
+    probe_impl("perf_event", attrs, wrapper, name)
+}
+
+fn wrap_fentry(item: ItemFn) -> ItemFn {
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    parse_quote! {
+        fn #outer_ident(ctx: *mut c_void) -> i32 {
+            let ctx = ::redbpf_probes::fentry::FEntryContext::new(ctx);
+            let _ = #ident(ctx);
+            return 0;
+
+            #item
+        }
+    }
+}
+
+/// Attribute macro that must be used to define `fentry` probes.
+///
+/// `fentry` probes are called on the entry of a kernel function, like
+/// kprobes, but with a lower overhead. The function is looked up in the BTF
+/// of the running kernel when the probe is loaded.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::fentry::prelude::*;
+///
+/// #[fentry("do_unlinkat")]
+/// fn unlink_enter(ctx: FEntryContext) {
+///     // this is executed when do_unlinkat() is invoked
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn fentry(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let wrapper = wrap_fentry(item);
+    probe_impl("fentry", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define `fexit` probes.
+///
+/// `fexit` probes are called when a kernel function returns, with both its
+/// arguments and its return value.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::fentry::prelude::*;
+///
+/// #[fexit("do_unlinkat")]
+/// fn unlink_exit(ctx: FEntryContext) {
+///     // this is executed when do_unlinkat() returns
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn fexit(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let wrapper = wrap_fentry(item);
+    probe_impl("fexit", attrs, wrapper, name)
+}
+
 /// Attribute macro that must be used to define [`XDP` probes](https://www.iovisor.org/technology/xdp).
 ///
 /// See also the [`XDP` API provided by
@@ -411,6 +794,9 @@ pub fn socket_filter(attrs: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 /// Define [tc action BPF programs](https://man7.org/linux/man-pages/man8/tc-bpf.8.html)
+///
+/// The program takes either a `SkBuff`, or a `TcContext` to access the packet
+/// directly.
 #[proc_macro_attribute]
 pub fn tc_action(attrs: TokenStream, item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as ItemFn);
@@ -420,7 +806,7 @@ pub fn tc_action(attrs: TokenStream, item: TokenStream) -> TokenStream {
     let wrapper = parse_quote! {
         fn #outer_ident(skb: *const ::redbpf_probes::bindings::__sk_buff) -> i32 {
             let skb = ::redbpf_probes::socket::SkBuff { skb };
-            return match #ident(skb) {
+            return match #ident(skb.into()) {
                 Ok(::redbpf_probes::tc::TcAction::Ok) => 0,
                 Ok(::redbpf_probes::tc::TcAction::Shot) => 2,
                 Ok(::redbpf_probes::tc::TcAction::Unspec) => -1,
@@ -435,3 +821,310 @@ pub fn tc_action(attrs: TokenStream, item: TokenStream) -> TokenStream {
 
     probe_impl("tc_action", attrs, wrapper, name)
 }
+
+/// Returns the attach point given to an attribute macro, or `default` if
+/// none is given.
+fn attach_point(ty: &str, attrs: TokenStream, points: &[&str], default: Option<&str>) -> String {
+    let point = if attrs.is_empty() {
+        default.map(String::from)
+    } else {
+        syn::parse::<Ident>(attrs)
+            .ok()
+            .map(|ident| ident.to_string())
+    };
+    match point {
+        Some(point) if points.contains(&point.as_str()) => point,
+        _ => panic!("expected #[{}({})]", ty, points.join("|")),
+    }
+}
+
+/// Attribute macro that must be used to define `cgroup_skb` programs.
+///
+/// The attribute takes the direction of the packets the program runs for,
+/// `ingress` or `egress`. The packets start at the network header.
+///
+/// See also the [`cgroup` API provided by
+/// `redbpf-probes`](https://ingraind.org/api/redbpf_probes/cgroup/index.html).
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::cgroup::prelude::*;
+///
+/// #[cgroup_skb(egress)]
+/// fn count_egress(skb: SkBuff) -> CgroupResult {
+///     // account the packet
+///
+///     Ok(CgroupAction::Allow)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn cgroup_skb(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let point = attach_point("cgroup_skb", attrs, &["ingress", "egress"], None);
+    let item = parse_macro_input!(item as ItemFn);
+    // the section name is `kind/attach_point/name`
+    let name = format!("{}/{}", point, item.sig.ident);
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(skb: *const ::redbpf_probes::bindings::__sk_buff) -> i32 {
+            let skb = ::redbpf_probes::socket::SkBuff { skb };
+            return match #ident(skb) {
+                Ok(::redbpf_probes::cgroup::CgroupAction::Deny) => 0,
+                _ => 1
+            };
+
+            #item
+        }
+    };
+
+    probe_impl("cgroup_skb", TokenStream::new(), wrapper, name)
+}
+
+/// Attribute macro that must be used to define `cgroup_sock` programs.
+///
+/// By default the program runs when a socket is created. The attribute can
+/// take `post_bind4`, `post_bind6` or `sock_release` to run it after a socket
+/// is bound or when it's released instead.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::cgroup::prelude::*;
+///
+/// #[cgroup_sock]
+/// fn inet_only(sk: SockContext) -> CgroupResult {
+///     if sk.family() != AF_INET && sk.family() != AF_INET6 {
+///         return Ok(CgroupAction::Deny);
+///     }
+///
+///     Ok(CgroupAction::Allow)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn cgroup_sock(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let point = attach_point(
+        "cgroup_sock",
+        attrs,
+        &["sock_create", "post_bind4", "post_bind6", "sock_release"],
+        Some("sock_create"),
+    );
+    let item = parse_macro_input!(item as ItemFn);
+    // the section name is `kind/attach_point/name`
+    let name = format!("{}/{}", point, item.sig.ident);
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(sk: *mut ::redbpf_probes::bindings::bpf_sock) -> i32 {
+            let sk = ::redbpf_probes::cgroup::SockContext { sk };
+            return match #ident(sk) {
+                Ok(::redbpf_probes::cgroup::CgroupAction::Deny) => 0,
+                _ => 1
+            };
+
+            #item
+        }
+    };
+
+    probe_impl("cgroup_sock", TokenStream::new(), wrapper, name)
+}
+
+/// Attribute macro that must be used to define `cgroup_sock_addr` programs.
+///
+/// The attribute takes the system call the program runs for: `bind4`,
+/// `bind6`, `connect4`, `connect6`, `sendmsg4`, `sendmsg6`, `recvmsg4`,
+/// `recvmsg6`, `getpeername4`, `getpeername6`, `getsockname4` or
+/// `getsockname6`.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::cgroup::prelude::*;
+///
+/// #[cgroup_sock_addr(connect4)]
+/// fn redirect_dns(mut ctx: SockAddrContext) -> CgroupResult {
+///     if ctx.user_port() == 53 {
+///         // 127.0.0.53
+///         ctx.set_user_ip4(u32::to_be(0x7f00_0035));
+///     }
+///
+///     Ok(CgroupAction::Allow)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn cgroup_sock_addr(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let point = attach_point(
+        "cgroup_sock_addr",
+        attrs,
+        &[
+            "bind4",
+            "bind6",
+            "connect4",
+            "connect6",
+            "sendmsg4",
+            "sendmsg6",
+            "recvmsg4",
+            "recvmsg6",
+            "getpeername4",
+            "getpeername6",
+            "getsockname4",
+            "getsockname6",
+        ],
+        None,
+    );
+    let item = parse_macro_input!(item as ItemFn);
+    // the section name is `kind/attach_point/name`
+    let name = format!("{}/{}", point, item.sig.ident);
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ctx: *mut ::redbpf_probes::bindings::bpf_sock_addr) -> i32 {
+            let ctx = ::redbpf_probes::cgroup::SockAddrContext { ctx };
+            return match #ident(ctx) {
+                Ok(::redbpf_probes::cgroup::CgroupAction::Deny) => 0,
+                _ => 1
+            };
+
+            #item
+        }
+    };
+
+    probe_impl("cgroup_sock_addr", TokenStream::new(), wrapper, name)
+}
+
+/// Attribute macro that must be used to define `sock_ops` programs.
+///
+/// `sock_ops` programs are attached to a cgroup and run on the TCP events of
+/// the sockets in it, like a connection being established.
+///
+/// See also the [`sockmap` API provided by
+/// `redbpf-probes`](https://ingraind.org/api/redbpf_probes/sockmap/index.html).
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::sockmap::prelude::*;
+///
+/// #[map]
+/// static mut SOCKETS: SockMap = SockMap::with_max_entries(1024);
+///
+/// #[sock_ops]
+/// fn add_sockets(ops: SockOpsContext) {
+///     if ops.op() == BPF_SOCK_OPS_PASSIVE_ESTABLISHED_CB {
+///         let _ = unsafe { SOCKETS.update(&ops, 0, BPF_ANY as u64) };
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn sock_ops(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(ops: *mut ::redbpf_probes::bindings::bpf_sock_ops) -> i32 {
+            let ops = ::redbpf_probes::sockmap::SockOpsContext { ops };
+            let _ = #ident(ops);
+            return 1;
+
+            #item
+        }
+    };
+
+    probe_impl("sock_ops", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define `sk_msg` programs.
+///
+/// `sk_msg` programs are attached to a `SockMap` or `SockHash`, and run for
+/// every message sent by the sockets in it.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::sockmap::prelude::*;
+///
+/// #[map]
+/// static mut SOCKETS: SockMap = SockMap::with_max_entries(2);
+///
+/// #[sk_msg]
+/// fn to_peer(msg: SkMsgContext) -> SkMsgResult {
+///     Ok(unsafe { SOCKETS.redirect_msg(&msg, 1, BPF_F_INGRESS as u64) })
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn sk_msg(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as ItemFn);
+    let name = item.sig.ident.to_string();
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = parse_quote! {
+        fn #outer_ident(msg: *mut ::redbpf_probes::bindings::sk_msg_md) -> i32 {
+            let msg = ::redbpf_probes::sockmap::SkMsgContext { msg };
+            return match #ident(msg) {
+                Ok(::redbpf_probes::sockmap::SkAction::Drop) => 0,
+                _ => 1
+            };
+
+            #item
+        }
+    };
+
+    probe_impl("sk_msg", attrs, wrapper, name)
+}
+
+/// Attribute macro that must be used to define `sk_skb` programs.
+///
+/// `sk_skb` programs are attached to a `SockMap` or `SockHash`, and run for
+/// the data received by the sockets in it. The attribute takes the kind of
+/// program:
+///
+/// * `stream_parser` programs return the length of the next message in the
+///   stream, or `0` if more data is needed.
+/// * `stream_verdict` programs decide what to do with each message, and can
+///   redirect it to another socket.
+///
+/// # Example
+/// ```no_run
+/// use redbpf_probes::sockmap::prelude::*;
+///
+/// #[sk_skb(stream_parser)]
+/// fn whole_packet(skb: SkBuff) -> Result<u32, SocketError> {
+///     Ok(unsafe { (*skb.skb).len })
+/// }
+///
+/// #[sk_skb(stream_verdict)]
+/// fn pass(skb: SkBuff) -> SkMsgResult {
+///     Ok(SkAction::Pass)
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn sk_skb(attrs: TokenStream, item: TokenStream) -> TokenStream {
+    let point = attach_point("sk_skb", attrs, &["stream_parser", "stream_verdict"], None);
+    let item = parse_macro_input!(item as ItemFn);
+    // the section name is `kind/attach_point/name`
+    let name = format!("{}/{}", point, item.sig.ident);
+    let ident = item.sig.ident.clone();
+    let outer_ident = Ident::new(&format!("outer_{}", ident), Span::call_site());
+    let wrapper = if point == "stream_parser" {
+        parse_quote! {
+            fn #outer_ident(skb: *const ::redbpf_probes::bindings::__sk_buff) -> i32 {
+                return match #ident(::redbpf_probes::socket::SkBuff { skb }) {
+                    Ok(len) => len as i32,
+                    Err(_) => unsafe { (*skb).len as i32 }
+                };
+
+                #item
+            }
+        }
+    } else {
+        parse_quote! {
+            fn #outer_ident(skb: *const ::redbpf_probes::bindings::__sk_buff) -> i32 {
+                let skb = ::redbpf_probes::socket::SkBuff { skb };
+                return match #ident(skb) {
+                    Ok(::redbpf_probes::sockmap::SkAction::Drop) => 0,
+                    _ => 1
+                };
+
+                #item
+            }
+        }
+    };
+
+    probe_impl("sk_skb", TokenStream::new(), wrapper, name)
+}
@@ -56,8 +56,11 @@ fn main() {
         "xdp_md",
         "ethhdr",
         "iphdr",
+        "ipv6hdr",
         "tcphdr",
         "udphdr",
+        "icmphdr",
+        "icmp6hdr",
         "xdp_action",
         "__sk_.*",
         "sk_.*",
@@ -0,0 +1,209 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+cgroup programs.
+
+cgroup programs are attached to a cgroup v2 directory and run for the
+sockets of the processes in the cgroup. There are three kinds:
+
+* `#[cgroup_skb(ingress|egress)]` programs run for every packet received or
+  sent by the sockets, and get an `SkBuff`. The packet starts at the network
+  header.
+* `#[cgroup_sock]` programs run when a socket is created, and get a
+  `SockContext`. `#[cgroup_sock(post_bind4|post_bind6|sock_release)]` run
+  after a socket is bound or when it's released instead.
+* `#[cgroup_sock_addr(connect4|bind4|...)]` programs run on `connect()`,
+  `bind()`, `sendmsg()`, `recvmsg()`, `getpeername()` and `getsockname()`,
+  and get a `SockAddrContext`. They can rewrite the address passed by the
+  process.
+
+Returning `CgroupAction::Deny` drops the packet or fails the system call with
+`EPERM`.
+
+# Example
+
+Only allow IPv4 connections to port 443:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::cgroup::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+#[cgroup_sock_addr(connect4)]
+fn connect_https_only(ctx: SockAddrContext) -> CgroupResult {
+    if ctx.user_port() == 443 {
+        Ok(CgroupAction::Allow)
+    } else {
+        Ok(CgroupAction::Deny)
+    }
+}
+```
+*/
+pub mod prelude;
+
+use crate::bindings::*;
+use crate::socket::{read_ip6, SocketError};
+use core::ptr;
+
+/// The return type for successful cgroup programs.
+pub enum CgroupAction {
+    /// Let the packet or the system call through.
+    Allow,
+    /// Drop the packet or fail the system call with `EPERM`.
+    Deny,
+}
+
+/// Result type for cgroup programs.
+///
+/// Errors are treated as `CgroupAction::Allow`.
+pub type CgroupResult = Result<CgroupAction, SocketError>;
+
+/// Context object provided to `cgroup_sock` programs.
+pub struct SockContext {
+    /// The socket being created, bound or released.
+    pub sk: *mut bpf_sock,
+}
+
+impl SockContext {
+    #[inline]
+    pub fn family(&self) -> u32 {
+        unsafe { (*self.sk).family }
+    }
+
+    #[inline]
+    pub fn sock_type(&self) -> u32 {
+        unsafe { (*self.sk).type_ }
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> u32 {
+        unsafe { (*self.sk).protocol }
+    }
+
+    /// Returns the local IPv4 address, in network byte order.
+    ///
+    /// Only set in `post_bind4` programs.
+    #[inline]
+    pub fn src_ip4(&self) -> u32 {
+        unsafe { (*self.sk).src_ip4 }
+    }
+
+    /// Returns the local IPv6 address, in network byte order.
+    ///
+    /// Only set in `post_bind6` programs.
+    #[inline]
+    pub fn src_ip6(&self) -> [u32; 4] {
+        unsafe { read_ip6(&(*self.sk).src_ip6) }
+    }
+
+    /// Returns the local port.
+    ///
+    /// Only set in `post_bind4` and `post_bind6` programs.
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        unsafe { (*self.sk).src_port as u16 }
+    }
+
+    /// Binds the socket to the network interface `ifindex`.
+    ///
+    /// Only allowed in `sock_create` programs.
+    #[inline]
+    pub fn set_bound_dev_if(&mut self, ifindex: u32) {
+        unsafe { (*self.sk).bound_dev_if = ifindex }
+    }
+
+    /// Sets the mark of the packets sent by the socket.
+    ///
+    /// Only allowed in `sock_create` programs.
+    #[inline]
+    pub fn set_mark(&mut self, mark: u32) {
+        unsafe { (*self.sk).mark = mark }
+    }
+
+    /// Sets the priority of the packets sent by the socket.
+    ///
+    /// Only allowed in `sock_create` programs.
+    #[inline]
+    pub fn set_priority(&mut self, priority: u32) {
+        unsafe { (*self.sk).priority = priority }
+    }
+}
+
+/// Context object provided to `cgroup_sock_addr` programs.
+pub struct SockAddrContext {
+    /// The address passed to the system call, and the socket.
+    pub ctx: *mut bpf_sock_addr,
+}
+
+impl SockAddrContext {
+    /// Returns the family of the address passed to the system call.
+    #[inline]
+    pub fn user_family(&self) -> u32 {
+        unsafe { (*self.ctx).user_family }
+    }
+
+    /// Returns the IPv4 address passed to the system call, in network byte
+    /// order.
+    #[inline]
+    pub fn user_ip4(&self) -> u32 {
+        unsafe { (*self.ctx).user_ip4 }
+    }
+
+    /// Replaces the IPv4 address passed to the system call. `ip` is in
+    /// network byte order.
+    #[inline]
+    pub fn set_user_ip4(&mut self, ip: u32) {
+        unsafe { (*self.ctx).user_ip4 = ip }
+    }
+
+    /// Returns the IPv6 address passed to the system call, in network byte
+    /// order.
+    #[inline]
+    pub fn user_ip6(&self) -> [u32; 4] {
+        unsafe { read_ip6(&(*self.ctx).user_ip6) }
+    }
+
+    /// Replaces the IPv6 address passed to the system call. `ip` is in
+    /// network byte order.
+    #[inline]
+    pub fn set_user_ip6(&mut self, ip: [u32; 4]) {
+        let ip6 = unsafe { &mut (*self.ctx).user_ip6 };
+        for (dst, src) in ip6.iter_mut().zip(ip.iter()) {
+            unsafe { ptr::write_volatile(dst, *src) };
+        }
+    }
+
+    /// Returns the port passed to the system call.
+    #[inline]
+    pub fn user_port(&self) -> u16 {
+        unsafe { u16::from_be((*self.ctx).user_port as u16) }
+    }
+
+    /// Replaces the port passed to the system call.
+    #[inline]
+    pub fn set_user_port(&mut self, port: u16) {
+        unsafe { (*self.ctx).user_port = port.to_be() as u32 }
+    }
+
+    #[inline]
+    pub fn family(&self) -> u32 {
+        unsafe { (*self.ctx).family }
+    }
+
+    #[inline]
+    pub fn sock_type(&self) -> u32 {
+        unsafe { (*self.ctx).type_ }
+    }
+
+    #[inline]
+    pub fn protocol(&self) -> u32 {
+        unsafe { (*self.ctx).protocol }
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Function entry and exit probes.
+
+Like kprobes and kretprobes, `fentry` and `fexit` programs hook on the entry
+and exit of a kernel function, but they're called through a BPF trampoline
+instead of a breakpoint, so their overhead is much lower. `fexit` programs
+also have access to the arguments of the function, not just its return value.
+
+The function is looked up in the BTF of the running kernel when the program
+is loaded, so these programs require a kernel built with
+`CONFIG_DEBUG_INFO_BTF`.
+
+# Example
+
+Do something when a file is unlinked:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::fentry::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+// int do_unlinkat(int dfd, struct filename *name)
+#[fentry("do_unlinkat")]
+fn unlink_enter(ctx: FEntryContext) {
+    let dfd = ctx.arg(0) as i32;
+    // do something with dfd
+}
+
+#[fexit("do_unlinkat")]
+fn unlink_exit(ctx: FEntryContext) {
+    // the return value follows the two arguments
+    let ret = ctx.arg(2) as i32;
+    // do something with ret
+}
+```
+ */
+pub mod prelude;
+
+use cty::*;
+
+/// Context object provided to `fentry` and `fexit` programs.
+pub struct FEntryContext {
+    /// The arguments passed by the BPF trampoline.
+    pub ctx: *mut c_void,
+}
+
+impl FEntryContext {
+    #[inline]
+    pub fn new(ctx: *mut c_void) -> Self {
+        FEntryContext { ctx }
+    }
+
+    /// Returns the raw arguments passed by the BPF trampoline.
+    #[inline]
+    pub fn inner(&self) -> *mut c_void {
+        self.ctx
+    }
+
+    /// Returns the `n`-th argument of the function.
+    ///
+    /// Arguments are passed as 64 bit integers. In `fexit` programs, the
+    /// return value of the function is passed after the arguments, so it's
+    /// returned by `arg(number of arguments)`.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u64 {
+        unsafe { *(self.ctx as *const u64).add(n) }
+    }
+}
@@ -140,4 +140,53 @@ pub fn bpf_perf_event_output(
         ) -> i32 = ::core::mem::transmute(25usize);
         f(ctx, map, flags, data, size)
     }
-}
\ No newline at end of file
+}
+
+#[inline]
+pub fn bpf_ringbuf_output(ringbuf: *mut c_void, data: *const c_void, size: u64, flags: u64) -> i64 {
+    unsafe {
+        let f: unsafe extern "C" fn(
+            ringbuf: *mut c_void,
+            data: *const c_void,
+            size: u64,
+            flags: u64,
+        ) -> i64 = ::core::mem::transmute(130usize);
+        f(ringbuf, data, size, flags)
+    }
+}
+
+#[inline]
+pub fn bpf_ringbuf_reserve(ringbuf: *mut c_void, size: u64, flags: u64) -> *mut c_void {
+    unsafe {
+        let f: unsafe extern "C" fn(ringbuf: *mut c_void, size: u64, flags: u64) -> *mut c_void =
+            ::core::mem::transmute(131usize);
+        f(ringbuf, size, flags)
+    }
+}
+
+#[inline]
+pub fn bpf_ringbuf_submit(data: *mut c_void, flags: u64) {
+    unsafe {
+        let f: unsafe extern "C" fn(data: *mut c_void, flags: u64) =
+            ::core::mem::transmute(132usize);
+        f(data, flags)
+    }
+}
+
+#[inline]
+pub fn bpf_ringbuf_discard(data: *mut c_void, flags: u64) {
+    unsafe {
+        let f: unsafe extern "C" fn(data: *mut c_void, flags: u64) =
+            ::core::mem::transmute(133usize);
+        f(data, flags)
+    }
+}
+
+#[inline]
+pub fn bpf_ringbuf_query(ringbuf: *mut c_void, flags: u64) -> u64 {
+    unsafe {
+        let f: unsafe extern "C" fn(ringbuf: *mut c_void, flags: u64) -> u64 =
+            ::core::mem::transmute(134usize);
+        f(ringbuf, flags)
+    }
+}
@@ -51,13 +51,20 @@ pub fn block_port_80(ctx: XdpContext) -> XdpResult {
 #![deny(clippy::all)]
 #![no_std]
 pub mod bindings;
+pub mod cgroup;
+pub mod fentry;
 pub mod helpers;
 pub mod kprobe;
+pub mod lsm;
 pub mod maps;
 pub mod net;
+pub mod perf_event;
+pub mod raw_tracepoint;
 pub mod registers;
 pub mod socket;
 pub mod socket_filter;
+pub mod sockmap;
 pub mod tc;
+pub mod tracepoint;
 pub mod uprobe;
 pub mod xdp;
@@ -0,0 +1,85 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+BPF LSM programs.
+
+LSM programs attach to the hooks of the Linux Security Modules framework,
+like `file_open` or `bprm_check_security`, and decide whether the operation
+is allowed. They receive the arguments of the hook, which can be found in
+`include/linux/lsm_hook_defs.h` in the kernel sources.
+
+The hook is looked up in the BTF of the running kernel when the program is
+loaded, so these programs require a kernel built with `CONFIG_BPF_LSM` and
+`CONFIG_DEBUG_INFO_BTF`, and booted with `bpf` in its list of LSMs.
+
+# Example
+
+Deny opening files to the process with PID 1234:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::lsm::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+// LSM_HOOK(int, 0, file_open, struct file *file)
+#[lsm("file_open")]
+fn deny_open(ctx: LsmContext) -> LsmAction {
+    let pid = bpf_get_current_pid_tgid() >> 32;
+    if pid == 1234 {
+        return LsmAction::Deny;
+    }
+
+    LsmAction::Allow
+}
+```
+ */
+pub mod prelude;
+
+use cty::*;
+
+/// The return type of LSM programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsmAction {
+    /// Let the operation through.
+    Allow,
+    /// Fail the operation with `EPERM`.
+    Deny,
+    /// Fail the operation with the given positive `errno`, like `EACCES`.
+    Errno(c_int),
+}
+
+/// Context object provided to LSM programs.
+pub struct LsmContext {
+    /// The arguments passed by the BPF trampoline.
+    pub ctx: *mut c_void,
+}
+
+impl LsmContext {
+    #[inline]
+    pub fn new(ctx: *mut c_void) -> Self {
+        LsmContext { ctx }
+    }
+
+    /// Returns the raw arguments passed by the BPF trampoline.
+    #[inline]
+    pub fn inner(&self) -> *mut c_void {
+        self.ctx
+    }
+
+    /// Returns the `n`-th argument of the hook.
+    ///
+    /// Arguments are passed as 64 bit integers. The value returned by the
+    /// previous program attached to the hook is passed after the arguments,
+    /// so it's returned by `arg(number of arguments)`.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u64 {
+        unsafe { *(self.ctx as *const u64).add(n) }
+    }
+}
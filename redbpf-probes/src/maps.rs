@@ -15,90 +15,436 @@ kernel and user-space code.
 use core::convert::TryInto;
 use core::default::Default;
 use core::marker::PhantomData;
-use core::mem;
+use core::mem::{self, MaybeUninit};
 use cty::*;
 
 use crate::bindings::*;
 use crate::helpers::*;
+use crate::socket::SkBuff;
+use crate::sockmap::{SkAction, SkMsgContext, SockOpsContext};
 
-/// Hash table map.
+/// Key and value types of a map.
 ///
-/// High level API for BPF_MAP_TYPE_HASH maps.
+/// The `#[map]` attribute uses these to describe the map layout in the BTF
+/// type information of the program, so the map can be pretty-printed by
+/// tools like `bpftool`.
+///
+/// Maps without keys, like `Queue`, still need a `KeyType`, but the loader
+/// ignores it.
+pub trait MapBtf {
+    type KeyType;
+    type ValueType;
+}
+
+macro_rules! define_hashmap {
+    ($(#[$attr:meta])* $name:ident, $map_type:expr) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        pub struct $name<K, V> {
+            def: bpf_map_def,
+            _k: PhantomData<K>,
+            _v: PhantomData<V>,
+        }
+
+        impl<K, V> MapBtf for $name<K, V> {
+            type KeyType = K;
+            type ValueType = V;
+        }
+
+        impl<K, V> $name<K, V> {
+            /// Creates a map with the specified maximum number of elements.
+            pub const fn with_max_entries(max_entries: u32) -> Self {
+                Self {
+                    def: bpf_map_def {
+                        type_: $map_type,
+                        key_size: mem::size_of::<K>() as u32,
+                        value_size: mem::size_of::<V>() as u32,
+                        max_entries,
+                        map_flags: 0,
+                    },
+                    _k: PhantomData,
+                    _v: PhantomData,
+                }
+            }
+
+            /// Returns a reference to the value corresponding to the key.
+            #[inline]
+            pub fn get(&mut self, key: &K) -> Option<&V> {
+                unsafe {
+                    let value = bpf_map_lookup_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        key as *const _ as *const c_void,
+                    );
+                    if value.is_null() {
+                        None
+                    } else {
+                        Some(&*(value as *const V))
+                    }
+                }
+            }
+
+            #[inline]
+            pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+                unsafe {
+                    let value = bpf_map_lookup_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        key as *const _ as *const c_void,
+                    );
+                    if value.is_null() {
+                        None
+                    } else {
+                        Some(&mut *(value as *mut V))
+                    }
+                }
+            }
+
+            /// Set the `value` in the map for `key`
+            #[inline]
+            pub fn set(&mut self, key: &K, value: &V) {
+                unsafe {
+                    bpf_map_update_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        key as *const _ as *const c_void,
+                        value as *const _ as *const c_void,
+                        BPF_ANY.into(),
+                    );
+                }
+            }
+
+            /// Delete the entry indexed by `key`
+            #[inline]
+            pub fn delete(&mut self, key: &K) {
+                unsafe {
+                    bpf_map_delete_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        key as *const _ as *const c_void,
+                    );
+                }
+            }
+        }
+    };
+}
+
+macro_rules! define_array {
+    ($(#[$attr:meta])* $name:ident, $map_type:expr) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        pub struct $name<T> {
+            def: bpf_map_def,
+            _element: PhantomData<T>,
+        }
+
+        impl<T> MapBtf for $name<T> {
+            type KeyType = u32;
+            type ValueType = T;
+        }
+
+        impl<T> $name<T> {
+            /// Creates an array with the specified number of elements.
+            pub const fn with_max_entries(max_entries: u32) -> Self {
+                Self {
+                    def: bpf_map_def {
+                        type_: $map_type,
+                        key_size: mem::size_of::<u32>() as u32,
+                        value_size: mem::size_of::<T>() as u32,
+                        max_entries,
+                        map_flags: 0,
+                    },
+                    _element: PhantomData,
+                }
+            }
+
+            /// Returns a reference to the element at `index`.
+            ///
+            /// Returns `None` if `index` is out of bounds.
+            #[inline]
+            pub fn get(&mut self, index: u32) -> Option<&T> {
+                unsafe {
+                    let value = bpf_map_lookup_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        &index as *const _ as *const c_void,
+                    );
+                    if value.is_null() {
+                        None
+                    } else {
+                        Some(&*(value as *const T))
+                    }
+                }
+            }
+
+            /// Returns a mutable reference to the element at `index`.
+            ///
+            /// Returns `None` if `index` is out of bounds.
+            #[inline]
+            pub fn get_mut(&mut self, index: u32) -> Option<&mut T> {
+                unsafe {
+                    let value = bpf_map_lookup_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        &index as *const _ as *const c_void,
+                    );
+                    if value.is_null() {
+                        None
+                    } else {
+                        Some(&mut *(value as *mut T))
+                    }
+                }
+            }
+
+            /// Set the element at `index` to `value`.
+            ///
+            /// Like `HashMap::set`, this does nothing if the update fails, eg.
+            /// because `index` is out of bounds. Array elements can't be
+            /// deleted, they always hold a value.
+            #[inline]
+            pub fn set(&mut self, index: u32, value: &T) {
+                unsafe {
+                    bpf_map_update_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        &index as *const _ as *const c_void,
+                        value as *const _ as *const c_void,
+                        BPF_ANY.into(),
+                    );
+                }
+            }
+        }
+    };
+}
+
+macro_rules! define_queue {
+    ($(#[$attr:meta])* $name:ident, $map_type:expr) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        pub struct $name<T> {
+            def: bpf_map_def,
+            _element: PhantomData<T>,
+        }
+
+        impl<T> MapBtf for $name<T> {
+            type KeyType = u32;
+            type ValueType = T;
+        }
+
+        impl<T> $name<T> {
+            /// Creates a map with the specified maximum number of elements.
+            pub const fn with_max_entries(max_entries: u32) -> Self {
+                Self {
+                    def: bpf_map_def {
+                        type_: $map_type,
+                        key_size: 0,
+                        value_size: mem::size_of::<T>() as u32,
+                        max_entries,
+                        map_flags: 0,
+                    },
+                    _element: PhantomData,
+                }
+            }
+
+            /// Push `value` into the map.
+            ///
+            /// Fails if the map is full.
+            #[inline]
+            pub fn push(&mut self, value: &T) -> Result<(), i32> {
+                self.push_with_flags(value, BPF_ANY.into())
+            }
+
+            /// Push `value` into the map, evicting the oldest element if the
+            /// map is full.
+            #[inline]
+            pub fn force_push(&mut self, value: &T) -> Result<(), i32> {
+                self.push_with_flags(value, BPF_EXIST.into())
+            }
+
+            /// Remove the next element from the map and return it.
+            ///
+            /// Returns `None` if the map is empty.
+            #[inline]
+            pub fn pop(&mut self) -> Option<T> {
+                let mut value = MaybeUninit::<T>::uninit();
+                let ret = unsafe {
+                    bpf_map_pop_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        value.as_mut_ptr() as *mut c_void,
+                    )
+                };
+                if ret < 0 {
+                    return None;
+                }
+
+                Some(unsafe { value.assume_init() })
+            }
+
+            /// Return the next element without removing it from the map.
+            ///
+            /// Returns `None` if the map is empty.
+            #[inline]
+            pub fn peek(&mut self) -> Option<T> {
+                let mut value = MaybeUninit::<T>::uninit();
+                let ret = unsafe {
+                    bpf_map_peek_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        value.as_mut_ptr() as *mut c_void,
+                    )
+                };
+                if ret < 0 {
+                    return None;
+                }
+
+                Some(unsafe { value.assume_init() })
+            }
+
+            #[inline]
+            fn push_with_flags(&mut self, value: &T, flags: u64) -> Result<(), i32> {
+                let ret = unsafe {
+                    bpf_map_push_elem(
+                        &mut self.def as *mut _ as *mut c_void,
+                        value as *const _ as *const c_void,
+                        flags,
+                    )
+                };
+                if ret < 0 {
+                    return Err(ret);
+                }
+
+                Ok(())
+            }
+        }
+    };
+}
+
+define_hashmap!(
+    /// Hash table map.
+    ///
+    /// High level API for BPF_MAP_TYPE_HASH maps.
+    HashMap,
+    bpf_map_type_BPF_MAP_TYPE_HASH
+);
+define_hashmap!(
+    /// Per-CPU hash table map.
+    ///
+    /// High level API for BPF_MAP_TYPE_PERCPU_HASH maps. Every CPU has its own
+    /// copy of each value, so values can be updated without synchronization.
+    /// Lookups return the value of the current CPU.
+    ///
+    /// To read the values of all the CPUs from user-space see
+    /// [`redbpf::PerCpuHashMap`](../../redbpf/struct.PerCpuHashMap.html).
+    PerCpuHashMap,
+    bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH
+);
+define_hashmap!(
+    /// LRU hash table map.
+    ///
+    /// High level API for BPF_MAP_TYPE_LRU_HASH maps. When the map is full,
+    /// inserting a new element evicts the least recently used one instead of
+    /// failing.
+    LruHashMap,
+    bpf_map_type_BPF_MAP_TYPE_LRU_HASH
+);
+define_array!(
+    /// Array map.
+    ///
+    /// High level API for BPF_MAP_TYPE_ARRAY maps. All the elements are
+    /// preallocated and zero-initialized.
+    Array,
+    bpf_map_type_BPF_MAP_TYPE_ARRAY
+);
+define_array!(
+    /// Per-CPU array map.
+    ///
+    /// High level API for BPF_MAP_TYPE_PERCPU_ARRAY maps. Every CPU has its own
+    /// copy of each element, and lookups return the element of the current
+    /// CPU.
+    ///
+    /// To read the elements of all the CPUs from user-space see
+    /// [`redbpf::PerCpuArray`](../../redbpf/struct.PerCpuArray.html).
+    PerCpuArray,
+    bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY
+);
+define_queue!(
+    /// FIFO queue map.
+    ///
+    /// High level API for BPF_MAP_TYPE_QUEUE maps. Elements are popped in the
+    /// order they were pushed. There are no keys, so a queue can be used to
+    /// hand work items over between programs or to user-space, see
+    /// [`redbpf::Queue`](../../redbpf/struct.Queue.html).
+    Queue,
+    bpf_map_type_BPF_MAP_TYPE_QUEUE
+);
+define_queue!(
+    /// LIFO stack map.
+    ///
+    /// High level API for BPF_MAP_TYPE_STACK maps. Like `Queue`, but the most
+    /// recently pushed element is popped first. See
+    /// [`redbpf::Stack`](../../redbpf/struct.Stack.html) for the user-space
+    /// side.
+    Stack,
+    bpf_map_type_BPF_MAP_TYPE_STACK
+);
+
+// Not present in the bindings generated from pre-5.16 kernel headers.
+const BPF_MAP_TYPE_BLOOM_FILTER: bpf_map_type = 30;
+
+/// Bloom filter map.
+///
+/// A probabilistic set: `contains` never returns `false` for a value that was
+/// inserted, but may return `true` for a value that wasn't. Values can't be
+/// removed. This makes it a cheap first check in front of a more expensive
+/// lookup. This is a wrapper for `BPF_MAP_TYPE_BLOOM_FILTER`, which requires
+/// Linux 5.16 or newer.
+///
+/// To insert values from user-space see
+/// [`redbpf::BloomFilter`](../../redbpf/struct.BloomFilter.html).
 #[repr(transparent)]
-pub struct HashMap<K, V> {
+pub struct BloomFilter<T> {
     def: bpf_map_def,
-    _k: PhantomData<K>,
-    _v: PhantomData<V>,
+    _element: PhantomData<T>,
 }
 
-impl<K, V> HashMap<K, V> {
-    /// Creates a map with the specified maximum number of elements.
+impl<T> MapBtf for BloomFilter<T> {
+    type KeyType = u32;
+    type ValueType = T;
+}
+
+impl<T> BloomFilter<T> {
+    /// Creates a bloom filter sized for `max_entries` values.
     pub const fn with_max_entries(max_entries: u32) -> Self {
         Self {
             def: bpf_map_def {
-                type_: bpf_map_type_BPF_MAP_TYPE_HASH,
-                key_size: mem::size_of::<K>() as u32,
-                value_size: mem::size_of::<V>() as u32,
+                type_: BPF_MAP_TYPE_BLOOM_FILTER,
+                key_size: 0,
+                value_size: mem::size_of::<T>() as u32,
                 max_entries,
                 map_flags: 0,
             },
-            _k: PhantomData,
-            _v: PhantomData,
-        }
-    }
-
-    /// Returns a reference to the value corresponding to the key.
-    #[inline]
-    pub fn get(&mut self, key: &K) -> Option<&V> {
-        unsafe {
-            let value = bpf_map_lookup_elem(
-                &mut self.def as *mut _ as *mut c_void,
-                key as *const _ as *const c_void,
-            );
-            if value.is_null() {
-                None
-            } else {
-                Some(&*(value as *const V))
-            }
-        }
-    }
-
-    #[inline]
-    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        unsafe {
-            let value = bpf_map_lookup_elem(
-                &mut self.def as *mut _ as *mut c_void,
-                key as *const _ as *const c_void,
-            );
-            if value.is_null() {
-                None
-            } else {
-                Some(&mut *(value as *mut V))
-            }
+            _element: PhantomData,
         }
     }
 
-    /// Set the `value` in the map for `key`
+    /// Add `value` to the filter.
     #[inline]
-    pub fn set(&mut self, key: &K, value: &V) {
-        unsafe {
-            bpf_map_update_elem(
+    pub fn insert(&mut self, value: &T) -> Result<(), i32> {
+        let ret = unsafe {
+            bpf_map_push_elem(
                 &mut self.def as *mut _ as *mut c_void,
-                key as *const _ as *const c_void,
                 value as *const _ as *const c_void,
                 BPF_ANY.into(),
-            );
+            )
+        };
+        if ret < 0 {
+            return Err(ret);
         }
+
+        Ok(())
     }
 
-    /// Delete the entry indexed by `key`
+    /// Returns `false` if `value` is definitely not in the filter, `true` if
+    /// it may be.
     #[inline]
-    pub fn delete(&mut self, key: &K) {
+    pub fn contains(&mut self, value: &T) -> bool {
         unsafe {
-            bpf_map_delete_elem(
+            bpf_map_peek_elem(
                 &mut self.def as *mut _ as *mut c_void,
-                key as *const _ as *const c_void,
-            );
+                value as *const _ as *mut c_void,
+            ) == 0
         }
     }
 }
@@ -173,6 +519,11 @@ pub struct PerfMap<T> {
     _event: PhantomData<T>,
 }
 
+impl<T> MapBtf for PerfMap<T> {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
 impl<T> PerfMap<T> {
     /// Creates a perf map with the specified maximum number of elements.
     pub const fn with_max_entries(max_entries: u32) -> Self {
@@ -212,6 +563,136 @@ impl<T> PerfMap<T> {
     }
 }
 
+// Not present in the bindings generated from pre-5.8 kernel headers.
+const BPF_MAP_TYPE_RINGBUF: bpf_map_type = 27;
+
+/// Ring buffer map.
+///
+/// A multi-producer, single-consumer ring buffer shared by all CPUs. Unlike
+/// `PerfMap`, events are delivered to user-space in the order they were
+/// committed and a single buffer is allocated for the whole system. This is a
+/// wrapper for `BPF_MAP_TYPE_RINGBUF`, which requires Linux 5.8 or newer.
+///
+/// Events can either be copied into the buffer with `output`, or written in
+/// place by calling `reserve` and then committing the returned entry with
+/// `RingBufEntry::submit`.
+///
+/// To consume the events from user-space see
+/// [`redbpf::RingBuf`](../../redbpf/struct.RingBuf.html).
+#[repr(transparent)]
+pub struct RingBuf<T> {
+    def: bpf_map_def,
+    _event: PhantomData<T>,
+}
+
+impl<T> MapBtf for RingBuf<T> {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
+impl<T> RingBuf<T> {
+    /// Creates a ring buffer of `size` bytes.
+    ///
+    /// `size` must be a power of 2 and a multiple of the page size.
+    pub const fn with_max_entries(size: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: BPF_MAP_TYPE_RINGBUF,
+                key_size: 0,
+                value_size: 0,
+                max_entries: size,
+                map_flags: 0,
+            },
+            _event: PhantomData,
+        }
+    }
+
+    /// Copy `data` into the ring buffer.
+    #[inline]
+    pub fn output(&mut self, data: &T) -> Result<(), i64> {
+        let ret = bpf_ringbuf_output(
+            &mut self.def as *mut _ as *mut c_void,
+            data as *const _ as *const c_void,
+            mem::size_of::<T>() as u64,
+            0,
+        );
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    /// Reserve space for an event in the ring buffer.
+    ///
+    /// Returns `None` if the buffer is full. The event is only made visible
+    /// to user-space once the returned entry is submitted, and is discarded if
+    /// the entry is dropped.
+    #[inline]
+    pub fn reserve(&mut self) -> Option<RingBufEntry<T>> {
+        let data = bpf_ringbuf_reserve(
+            &mut self.def as *mut _ as *mut c_void,
+            mem::size_of::<T>() as u64,
+            0,
+        );
+        if data.is_null() {
+            None
+        } else {
+            Some(RingBufEntry {
+                data: data as *mut T,
+            })
+        }
+    }
+}
+
+/// An event reserved in a `RingBuf`.
+///
+/// The reserved event is uninitialized. Write it with `write`, or field by
+/// field through `as_uninit`, then call `submit` to hand it to user-space.
+pub struct RingBufEntry<T> {
+    data: *mut T,
+}
+
+impl<T> RingBufEntry<T> {
+    /// Initialize the event with `value`.
+    ///
+    /// Returns a reference to the now initialized event.
+    #[inline]
+    pub fn write(&mut self, value: T) -> &mut T {
+        unsafe {
+            self.data.write(value);
+            &mut *self.data
+        }
+    }
+
+    /// Returns the reserved, possibly uninitialized event.
+    #[inline]
+    pub fn as_uninit(&mut self) -> &mut MaybeUninit<T> {
+        unsafe { &mut *(self.data as *mut MaybeUninit<T>) }
+    }
+
+    /// Commit the event, making it visible to user-space.
+    #[inline]
+    pub fn submit(self) {
+        bpf_ringbuf_submit(self.data as *mut c_void, 0);
+        mem::forget(self);
+    }
+
+    /// Discard the event.
+    #[inline]
+    pub fn discard(self) {
+        bpf_ringbuf_discard(self.data as *mut c_void, 0);
+        mem::forget(self);
+    }
+}
+
+impl<T> Drop for RingBufEntry<T> {
+    #[inline]
+    fn drop(&mut self) {
+        bpf_ringbuf_discard(self.data as *mut c_void, 0);
+    }
+}
+
 // TODO Use PERF_MAX_STACK_DEPTH
 const BPF_MAX_STACK_DEPTH: usize = 127;
 
@@ -225,6 +706,11 @@ struct BpfStackFrames {
     ip: [u64; BPF_MAX_STACK_DEPTH]
 }
 
+impl MapBtf for StackTrace {
+    type KeyType = u32;
+    type ValueType = [u64; BPF_MAX_STACK_DEPTH];
+}
+
 impl StackTrace {
     pub const fn with_max_entries(cap: u32) -> Self {
         StackTrace {
@@ -262,6 +748,11 @@ pub struct ProgramArray {
     def: bpf_map_def,
 }
 
+impl MapBtf for ProgramArray {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
 impl ProgramArray {
     /// Creates a program map with the specified maximum number of programs.
     pub const fn with_max_entries(max_entries: u32) -> Self {
@@ -305,3 +796,414 @@ impl ProgramArray {
         Ok(())
     }
 }
+
+/// Array of maps.
+///
+/// An array holding other maps of type `M`, like `HashMap<u32, u64>`. The
+/// maps are set from user-space with
+/// [`redbpf::ArrayOfMaps`](../../redbpf/struct.ArrayOfMaps.html), which
+/// allows replacing them atomically.
+///
+/// The loader needs a template for the inner maps, which is another map of
+/// type `M` declared with `#[map]` and named with `inner_map`:
+///
+/// ```no_run
+/// # use redbpf_probes::xdp::prelude::*;
+/// #[map]
+/// static mut rules_template: HashMap<u32, u32> = HashMap::with_max_entries(1024);
+///
+/// #[map(inner_map = "rules_template")]
+/// static mut tenants: ArrayOfMaps<HashMap<u32, u32>> = ArrayOfMaps::with_max_entries(64);
+/// ```
+#[repr(transparent)]
+pub struct ArrayOfMaps<M> {
+    def: bpf_map_def,
+    _m: PhantomData<M>,
+}
+
+impl<M> MapBtf for ArrayOfMaps<M> {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
+impl<M> ArrayOfMaps<M> {
+    /// Creates an array of maps with the specified maximum number of maps.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_ARRAY_OF_MAPS,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags: 0,
+            },
+            _m: PhantomData,
+        }
+    }
+
+    /// Returns the map at `index`.
+    ///
+    /// Returns `None` if `index` is out of bounds or no map is set there.
+    #[inline]
+    pub fn get(&mut self, index: u32) -> Option<&mut M> {
+        unsafe {
+            let map = bpf_map_lookup_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                &index as *const _ as *const c_void,
+            );
+            if map.is_null() {
+                None
+            } else {
+                Some(&mut *(map as *mut M))
+            }
+        }
+    }
+}
+
+/// Hash table of maps.
+///
+/// Like `ArrayOfMaps`, but the maps are stored by a key of type `K`. The
+/// maps are set from user-space with
+/// [`redbpf::HashOfMaps`](../../redbpf/struct.HashOfMaps.html).
+#[repr(transparent)]
+pub struct HashOfMaps<K, M> {
+    def: bpf_map_def,
+    _k: PhantomData<K>,
+    _m: PhantomData<M>,
+}
+
+impl<K, M> MapBtf for HashOfMaps<K, M> {
+    type KeyType = K;
+    type ValueType = u32;
+}
+
+impl<K, M> HashOfMaps<K, M> {
+    /// Creates a hash table of maps with the specified maximum number of
+    /// maps.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_HASH_OF_MAPS,
+                key_size: mem::size_of::<K>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags: 0,
+            },
+            _k: PhantomData,
+            _m: PhantomData,
+        }
+    }
+
+    /// Returns the map corresponding to the key.
+    #[inline]
+    pub fn get(&mut self, key: &K) -> Option<&mut M> {
+        unsafe {
+            let map = bpf_map_lookup_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *const c_void,
+            );
+            if map.is_null() {
+                None
+            } else {
+                Some(&mut *(map as *mut M))
+            }
+        }
+    }
+}
+
+/// Key of a `LpmTrie`.
+///
+/// `prefixlen` is the number of leading bits of `data` that are significant,
+/// `data` is stored in network byte order. To avoid padding between the
+/// two fields, `T` should be a byte array like `[u8; 4]` for IPv4 or
+/// `[u8; 16]` for IPv6.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct Key<T> {
+    pub prefixlen: u32,
+    pub data: T,
+}
+
+impl<T> Key<T> {
+    /// Creates a key matching the first `prefixlen` bits of `data`.
+    #[inline]
+    pub fn new(prefixlen: u32, data: T) -> Self {
+        Key { prefixlen, data }
+    }
+}
+
+/// Longest prefix match trie.
+///
+/// A map whose lookups return the value of the most specific prefix
+/// matching the key, which makes it suitable for matching addresses against
+/// CIDR ranges. This is a wrapper for `BPF_MAP_TYPE_LPM_TRIE`.
+///
+/// To look up an address, use a `Key` whose `prefixlen` is the full length
+/// of the address in bits, eg. `Key::new(32, addr)` for IPv4. The prefixes
+/// are usually set from user-space with
+/// [`redbpf::LpmTrie`](../../redbpf/struct.LpmTrie.html).
+#[repr(transparent)]
+pub struct LpmTrie<K, V> {
+    def: bpf_map_def,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+impl<K, V> MapBtf for LpmTrie<K, V> {
+    type KeyType = Key<K>;
+    type ValueType = V;
+}
+
+impl<K, V> LpmTrie<K, V> {
+    /// Creates a trie with the specified maximum number of prefixes.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_LPM_TRIE,
+                key_size: mem::size_of::<Key<K>>() as u32,
+                value_size: mem::size_of::<V>() as u32,
+                max_entries,
+                // the kernel refuses to preallocate LPM tries
+                map_flags: BPF_F_NO_PREALLOC as u32,
+            },
+            _k: PhantomData,
+            _v: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the value of the longest prefix matching
+    /// `key`.
+    #[inline]
+    pub fn get(&mut self, key: &Key<K>) -> Option<&V> {
+        unsafe {
+            let value = bpf_map_lookup_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *const c_void,
+            );
+            if value.is_null() {
+                None
+            } else {
+                Some(&*(value as *const V))
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the value of the longest prefix
+    /// matching `key`.
+    #[inline]
+    pub fn get_mut(&mut self, key: &Key<K>) -> Option<&mut V> {
+        unsafe {
+            let value = bpf_map_lookup_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *const c_void,
+            );
+            if value.is_null() {
+                None
+            } else {
+                Some(&mut *(value as *mut V))
+            }
+        }
+    }
+
+    /// Set the `value` in the map for the prefix `key`
+    #[inline]
+    pub fn set(&mut self, key: &Key<K>, value: &V) {
+        unsafe {
+            bpf_map_update_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *const c_void,
+                value as *const _ as *const c_void,
+                BPF_ANY.into(),
+            );
+        }
+    }
+
+    /// Delete the entry for the prefix `key`
+    #[inline]
+    pub fn delete(&mut self, key: &Key<K>) {
+        unsafe {
+            bpf_map_delete_elem(
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *const c_void,
+            );
+        }
+    }
+}
+
+/// Socket array map.
+///
+/// An array of sockets, filled by `sock_ops` programs or from user-space.
+/// `sk_msg` and `sk_skb` programs attached to the map can redirect data to
+/// the sockets in it.
+///
+/// To attach programs to the map use
+/// [`redbpf::SkMsg::attach_sockmap`](../../redbpf/struct.SkMsg.html#method.attach_sockmap)
+/// from user-space.
+#[repr(transparent)]
+pub struct SockMap {
+    def: bpf_map_def,
+}
+
+impl MapBtf for SockMap {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
+impl SockMap {
+    /// Creates a socket map with the specified maximum number of sockets.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_SOCKMAP,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags: 0,
+            },
+        }
+    }
+
+    /// Adds the socket of `ops` to the map at `index`.
+    #[inline]
+    pub fn update(&mut self, ops: &SockOpsContext, index: u32, flags: u64) -> Result<(), i32> {
+        let mut index = index;
+        let ret = unsafe {
+            bpf_sock_map_update(
+                ops.ops,
+                &mut self.def as *mut _ as *mut c_void,
+                &mut index as *mut _ as *mut c_void,
+                flags,
+            )
+        };
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    /// Redirects the message to the socket at `index`.
+    ///
+    /// The message is queued for sending on the socket, or for receiving if
+    /// `flags` contains `BPF_F_INGRESS`. The result should be returned from
+    /// the program.
+    #[inline]
+    pub fn redirect_msg(&mut self, msg: &SkMsgContext, index: u32, flags: u64) -> SkAction {
+        let ret = unsafe {
+            bpf_msg_redirect_map(
+                msg.msg,
+                &mut self.def as *mut _ as *mut c_void,
+                index,
+                flags,
+            )
+        };
+        to_sk_action(ret)
+    }
+
+    /// Redirects the packet to the socket at `index`.
+    ///
+    /// The packet is queued for sending on the socket, or for receiving if
+    /// `flags` contains `BPF_F_INGRESS`. The result should be returned from
+    /// the program.
+    #[inline]
+    pub fn redirect_skb(&mut self, skb: &SkBuff, index: u32, flags: u64) -> SkAction {
+        let ret = unsafe {
+            bpf_sk_redirect_map(
+                skb.skb as *mut _,
+                &mut self.def as *mut _ as *mut c_void,
+                index,
+                flags,
+            )
+        };
+        to_sk_action(ret)
+    }
+}
+
+/// Socket hash map.
+///
+/// Like `SockMap`, but the sockets are looked up by a key of type `K`.
+#[repr(transparent)]
+pub struct SockHash<K> {
+    def: bpf_map_def,
+    _k: PhantomData<K>,
+}
+
+impl<K> MapBtf for SockHash<K> {
+    type KeyType = K;
+    type ValueType = u32;
+}
+
+impl<K> SockHash<K> {
+    /// Creates a socket map with the specified maximum number of sockets.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_SOCKHASH,
+                key_size: mem::size_of::<K>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags: 0,
+            },
+            _k: PhantomData,
+        }
+    }
+
+    /// Adds the socket of `ops` to the map with key `key`.
+    #[inline]
+    pub fn update(&mut self, ops: &SockOpsContext, key: &K, flags: u64) -> Result<(), i32> {
+        let ret = unsafe {
+            bpf_sock_hash_update(
+                ops.ops,
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *mut c_void,
+                flags,
+            )
+        };
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    /// Redirects the message to the socket with key `key`.
+    ///
+    /// See `SockMap::redirect_msg`.
+    #[inline]
+    pub fn redirect_msg(&mut self, msg: &SkMsgContext, key: &K, flags: u64) -> SkAction {
+        let ret = unsafe {
+            bpf_msg_redirect_hash(
+                msg.msg,
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *mut c_void,
+                flags,
+            )
+        };
+        to_sk_action(ret)
+    }
+
+    /// Redirects the packet to the socket with key `key`.
+    ///
+    /// See `SockMap::redirect_skb`.
+    #[inline]
+    pub fn redirect_skb(&mut self, skb: &SkBuff, key: &K, flags: u64) -> SkAction {
+        let ret = unsafe {
+            bpf_sk_redirect_hash(
+                skb.skb as *mut _,
+                &mut self.def as *mut _ as *mut c_void,
+                key as *const _ as *mut c_void,
+                flags,
+            )
+        };
+        to_sk_action(ret)
+    }
+}
+
+#[inline]
+fn to_sk_action(ret: c_int) -> SkAction {
+    if ret == sk_action_SK_PASS as c_int {
+        SkAction::Pass
+    } else {
+        SkAction::Drop
+    }
+}
@@ -11,44 +11,146 @@ Types and traits for working with networking data.
 The main trait exported by this module is `NetworkBuffer`. It's implemented
 by
 [`XdpContext`](https://ingraind.org/api/redbpf_probes/xdp/struct.XdpContext.html)
+and [`TcContext`](https://ingraind.org/api/redbpf_probes/tc/struct.TcContext.html)
 to provide access to the network data.
+
+Both IPv4 and IPv6 packets are parsed, with or without VLAN tags, and the
+`gre`, `vxlan` and `geneve` methods locate packets encapsulated in tunnels.
+
+Headers can be rewritten through the pointers returned by `eth_mut`, `ip_mut`
+and `transport_mut`, or with `NetworkBuffer::store_bytes`. Checksums covering
+the rewritten fields must then be fixed up with
+`NetworkBuffer::l3_csum_replace` and `NetworkBuffer::l4_csum_replace`.
  */
 use crate::bindings::*;
 use core::mem;
+use core::ptr;
 use core::slice;
 use cty::*;
 use redbpf_macros::impl_network_buffer_array;
 
+/// Maximum number of VLAN tags skipped to find the network header.
+pub const MAX_VLAN_TAGS: usize = 2;
+
+/// Maximum number of IPv6 extension headers skipped to find the transport
+/// header.
+pub const MAX_IPV6_EXT_HEADERS: usize = 6;
+
+/// The UDP port of VXLAN tunnels.
+pub const VXLAN_PORT: u16 = 4789;
+
+/// The UDP port of Geneve tunnels.
+pub const GENEVE_PORT: u16 = 6081;
+
 /// The packet transport header.
 ///
-/// Currently only `TCP` and `UDP` transports are supported.
+/// Currently only `TCP`, `UDP`, `ICMP` and `ICMPv6` transports are
+/// supported.
 pub enum Transport {
     TCP(*const tcphdr),
     UDP(*const udphdr),
+    ICMP(*const icmphdr),
+    ICMPV6(*const icmp6hdr),
 }
 
 impl Transport {
-    /// Returns the source port.
+    /// Returns the source port, or 0 for ICMP.
     #[inline]
     pub fn source(&self) -> u16 {
         let source = match *self {
             Transport::TCP(hdr) => unsafe { (*hdr).source },
             Transport::UDP(hdr) => unsafe { (*hdr).source },
+            Transport::ICMP(_) | Transport::ICMPV6(_) => 0,
         };
         u16::from_be(source)
     }
 
-    /// Returns the destination port.
+    /// Returns the destination port, or 0 for ICMP.
     #[inline]
     pub fn dest(&self) -> u16 {
         let dest = match *self {
             Transport::TCP(hdr) => unsafe { (*hdr).dest },
             Transport::UDP(hdr) => unsafe { (*hdr).dest },
+            Transport::ICMP(_) | Transport::ICMPV6(_) => 0,
         };
         u16::from_be(dest)
     }
 }
 
+/// The packet transport header, for writing.
+pub enum TransportMut {
+    TCP(*mut tcphdr),
+    UDP(*mut udphdr),
+    ICMP(*mut icmphdr),
+    ICMPV6(*mut icmp6hdr),
+}
+
+/// 802.1Q or 802.1ad VLAN tag.
+#[repr(C)]
+pub struct VlanHdr {
+    pub h_vlan_tci: u16,
+    pub h_vlan_encapsulated_proto: u16,
+}
+
+/// GRE header, without the optional fields.
+#[repr(C)]
+pub struct GreHdr {
+    pub flags: u16,
+    pub protocol: u16,
+}
+
+/// VXLAN header.
+#[repr(C)]
+pub struct VxlanHdr {
+    pub vx_flags: u32,
+    pub vx_vni: u32,
+}
+
+/// Geneve header, without the options.
+#[repr(C)]
+pub struct GeneveHdr {
+    pub ver_opt_len: u8,
+    pub flags: u8,
+    pub proto_type: u16,
+    pub vni: [u8; 3],
+    pub rsvd2: u8,
+}
+
+/// A tunnel the packet is encapsulated in.
+///
+/// Returned by `NetworkBuffer::gre`, `NetworkBuffer::vxlan` and
+/// `NetworkBuffer::geneve`.
+pub struct Tunnel {
+    /// The EtherType of the encapsulated packet, `ETH_P_TEB` for Ethernet
+    /// frames.
+    pub proto: u16,
+    /// The VXLAN or Geneve network identifier, or the GRE key. 0 if the
+    /// tunnel has none.
+    pub id: u32,
+    /// The offset of the encapsulated packet from the first byte of the
+    /// packet.
+    pub offset: usize,
+}
+
+/// The first bytes of all IPv6 extension headers.
+#[repr(C)]
+struct Ipv6ExtHdr {
+    nexthdr: u8,
+    hdrlen: u8,
+    // the fragment offset in fragment headers
+    frag_off: u16,
+}
+
+const IPV6_FRAG_OFFSET: u16 = 0xfff8;
+
+const GRE_CSUM: u16 = 0x8000;
+const GRE_ROUTING: u16 = 0x4000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQ: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+
+const VXLAN_HF_VNI: u32 = 0x0800_0000;
+
 pub enum NetworkError {
     Other,
     OutOfBounds,
@@ -102,6 +204,25 @@ where
         self.ptr_at(prev as usize + mem::size_of::<T>())
     }
 
+    /// Returns a raw mutable pointer to a given address inside the buffer.
+    ///
+    /// # Safety
+    ///
+    /// See `NetworkBuffer::ptr_at`.
+    #[inline]
+    unsafe fn ptr_at_mut<U>(&self, addr: usize) -> NetworkResult<*mut U> {
+        Ok(self.ptr_at::<U>(addr)? as *mut U)
+    }
+
+    /// Returns the offset of `ptr` from the first byte of the packet.
+    ///
+    /// This is the offset expected by `store_bytes`, `l3_csum_replace` and
+    /// `l4_csum_replace`.
+    #[inline]
+    fn offset<T>(&self, ptr: *const T) -> usize {
+        ptr as usize - self.data_start()
+    }
+
     #[inline]
     fn check_bounds(&self, start: usize, end: usize) -> NetworkResult<()> {
         if start >= end {
@@ -125,28 +246,119 @@ where
         unsafe { self.ptr_at(self.data_start() as usize) }
     }
 
+    /// Returns the packet's VLAN ID, if it's tagged.
+    ///
+    /// For packets with several tags this is the ID in the outer tag.
+    #[inline]
+    fn vlan_id(&self) -> Option<u16> {
+        let eth = self.eth().ok()?;
+        unsafe {
+            if !is_vlan(u16::from_be((*eth).h_proto)) {
+                return None;
+            }
+
+            let vlan: *const VlanHdr = self.ptr_after(eth).ok()?;
+            Some(u16::from_be((*vlan).h_vlan_tci) & 0x0fff)
+        }
+    }
+
+    /// Returns the EtherType of the packet's network header and its address.
+    ///
+    /// Up to `MAX_VLAN_TAGS` VLAN tags following the `Ethernet` header are
+    /// skipped.
+    #[inline]
+    fn network_header(&self) -> NetworkResult<(u16, usize)> {
+        let eth = self.eth()?;
+        let mut proto = u16::from_be(unsafe { (*eth).h_proto });
+        let mut addr = eth as usize + mem::size_of::<ethhdr>();
+        for _ in 0..MAX_VLAN_TAGS {
+            if !is_vlan(proto) {
+                break;
+            }
+            let vlan: *const VlanHdr = unsafe { self.ptr_at(addr)? };
+            proto = u16::from_be(unsafe { (*vlan).h_vlan_encapsulated_proto });
+            addr += mem::size_of::<VlanHdr>();
+        }
+
+        Ok((proto, addr))
+    }
+
     /// Returns the packet's `IP` header if present.
     #[inline]
     fn ip(&self) -> NetworkResult<*const iphdr> {
-        let eth = self.eth()?;
+        let (proto, addr) = self.network_header()?;
+        if proto != ETH_P_IP as u16 {
+            return Err(NetworkError::NoIPHeader);
+        }
+
+        unsafe { self.ptr_at(addr) }
+    }
+
+    /// Returns the packet's `IPv6` header if present.
+    #[inline]
+    fn ipv6(&self) -> NetworkResult<*const ipv6hdr> {
+        let (proto, addr) = self.network_header()?;
+        if proto != ETH_P_IPV6 as u16 {
+            return Err(NetworkError::NoIPHeader);
+        }
+
+        unsafe { self.ptr_at(addr) }
+    }
+
+    /// Returns the protocol of the packet's transport header and its address.
+    ///
+    /// Up to `MAX_IPV6_EXT_HEADERS` IPv6 extension headers are skipped. Fails
+    /// with `NetworkError::UnsupportedTransport` for IPv6 fragments other
+    /// than the first, which have no transport header.
+    #[inline]
+    fn transport_header(&self) -> NetworkResult<(u32, usize)> {
+        let (proto, addr) = self.network_header()?;
         unsafe {
-            if (*eth).h_proto != u16::from_be(ETH_P_IP as u16) {
-                return Err(NetworkError::NoIPHeader);
-            }
+            match proto as u32 {
+                ETH_P_IP => {
+                    let ip: *const iphdr = self.ptr_at(addr)?;
+                    Ok(((*ip).protocol as u32, addr + ((*ip).ihl() * 4) as usize))
+                }
+                ETH_P_IPV6 => {
+                    let ip6: *const ipv6hdr = self.ptr_at(addr)?;
+                    let mut proto = (*ip6).nexthdr as u32;
+                    let mut addr = addr + mem::size_of::<ipv6hdr>();
+                    for _ in 0..MAX_IPV6_EXT_HEADERS {
+                        let ext: *const Ipv6ExtHdr = self.ptr_at(addr)?;
+                        let len = match proto {
+                            IPPROTO_HOPOPTS | IPPROTO_ROUTING | IPPROTO_DSTOPTS | IPPROTO_MH => {
+                                ((*ext).hdrlen as usize + 1) * 8
+                            }
+                            IPPROTO_FRAGMENT => {
+                                if u16::from_be((*ext).frag_off) & IPV6_FRAG_OFFSET != 0 {
+                                    return Err(NetworkError::UnsupportedTransport(proto));
+                                }
+                                8
+                            }
+                            IPPROTO_AH => ((*ext).hdrlen as usize + 2) * 4,
+                            _ => break,
+                        };
+                        proto = (*ext).nexthdr as u32;
+                        addr += len;
+                    }
 
-            self.ptr_after(eth)
+                    Ok((proto, addr))
+                }
+                _ => Err(NetworkError::NoIPHeader),
+            }
         }
     }
 
     /// Returns the packet's transport header if present.
     #[inline]
     fn transport(&self) -> NetworkResult<Transport> {
+        let (proto, addr) = self.transport_header()?;
         unsafe {
-            let ip = self.ip()?;
-            let addr = ip as usize + ((*ip).ihl() * 4) as usize;
-            let transport = match (*ip).protocol as u32 {
-                IPPROTO_TCP => (Transport::TCP(self.ptr_at(addr)?)),
-                IPPROTO_UDP => (Transport::UDP(self.ptr_at(addr)?)),
+            let transport = match proto {
+                IPPROTO_TCP => Transport::TCP(self.ptr_at(addr)?),
+                IPPROTO_UDP => Transport::UDP(self.ptr_at(addr)?),
+                IPPROTO_ICMP => Transport::ICMP(self.ptr_at(addr)?),
+                IPPROTO_ICMPV6 => Transport::ICMPV6(self.ptr_at(addr)?),
                 t => return Err(NetworkError::UnsupportedTransport(t)),
             };
 
@@ -154,6 +366,195 @@ where
         }
     }
 
+    /// Returns the GRE tunnel the packet is encapsulated in.
+    #[inline]
+    fn gre(&self) -> NetworkResult<Tunnel> {
+        let (proto, addr) = self.transport_header()?;
+        if proto != IPPROTO_GRE {
+            return Err(NetworkError::UnsupportedTransport(proto));
+        }
+
+        unsafe {
+            let gre: *const GreHdr = self.ptr_at(addr)?;
+            let flags = u16::from_be((*gre).flags);
+            if flags & (GRE_ROUTING | GRE_VERSION) != 0 {
+                return Err(NetworkError::Other);
+            }
+
+            let mut addr = addr + mem::size_of::<GreHdr>();
+            if flags & GRE_CSUM != 0 {
+                addr += 4;
+            }
+            let mut id = 0;
+            if flags & GRE_KEY != 0 {
+                let key: *const u32 = self.ptr_at(addr)?;
+                id = u32::from_be(key.read_unaligned());
+                addr += 4;
+            }
+            if flags & GRE_SEQ != 0 {
+                addr += 4;
+            }
+
+            Ok(Tunnel {
+                proto: u16::from_be((*gre).protocol),
+                id,
+                offset: addr - self.data_start(),
+            })
+        }
+    }
+
+    /// Returns the VXLAN tunnel the packet is encapsulated in.
+    ///
+    /// Only packets sent to `VXLAN_PORT` are considered VXLAN packets.
+    #[inline]
+    fn vxlan(&self) -> NetworkResult<Tunnel> {
+        let addr = udp_payload(self, VXLAN_PORT)?;
+        unsafe {
+            let vxlan: *const VxlanHdr = self.ptr_at(addr)?;
+            if u32::from_be((*vxlan).vx_flags) & VXLAN_HF_VNI == 0 {
+                return Err(NetworkError::Other);
+            }
+
+            Ok(Tunnel {
+                proto: ETH_P_TEB as u16,
+                id: u32::from_be((*vxlan).vx_vni) >> 8,
+                offset: addr + mem::size_of::<VxlanHdr>() - self.data_start(),
+            })
+        }
+    }
+
+    /// Returns the Geneve tunnel the packet is encapsulated in.
+    ///
+    /// Only packets sent to `GENEVE_PORT` are considered Geneve packets. The
+    /// tunnel options are skipped.
+    #[inline]
+    fn geneve(&self) -> NetworkResult<Tunnel> {
+        let addr = udp_payload(self, GENEVE_PORT)?;
+        unsafe {
+            let geneve: *const GeneveHdr = self.ptr_at(addr)?;
+            let ver_opt_len = (*geneve).ver_opt_len;
+            if ver_opt_len >> 6 != 0 {
+                return Err(NetworkError::Other);
+            }
+
+            let vni = (*geneve).vni;
+            let opt_len = (ver_opt_len & 0x3f) as usize * 4;
+            Ok(Tunnel {
+                proto: u16::from_be((*geneve).proto_type),
+                id: u32::from(vni[0]) << 16 | u32::from(vni[1]) << 8 | u32::from(vni[2]),
+                offset: addr + mem::size_of::<GeneveHdr>() + opt_len - self.data_start(),
+            })
+        }
+    }
+
+    /// Returns the packet's `Ethernet` header for writing.
+    #[inline]
+    fn eth_mut(&self) -> NetworkResult<*mut ethhdr> {
+        Ok(self.eth()? as *mut ethhdr)
+    }
+
+    /// Returns the packet's `IP` header for writing.
+    ///
+    /// The IP checksum must be updated after changing any field, see
+    /// `l3_csum_replace`.
+    #[inline]
+    fn ip_mut(&self) -> NetworkResult<*mut iphdr> {
+        Ok(self.ip()? as *mut iphdr)
+    }
+
+    /// Returns the packet's `IPv6` header for writing.
+    ///
+    /// IPv6 has no header checksum, but the transport checksum must be
+    /// updated after changing the addresses, see `l4_csum_replace`.
+    #[inline]
+    fn ipv6_mut(&self) -> NetworkResult<*mut ipv6hdr> {
+        Ok(self.ipv6()? as *mut ipv6hdr)
+    }
+
+    /// Returns the packet's transport header for writing.
+    ///
+    /// The transport checksum must be updated after changing any field, see
+    /// `l4_csum_replace`.
+    #[inline]
+    fn transport_mut(&self) -> NetworkResult<TransportMut> {
+        let transport = match self.transport()? {
+            Transport::TCP(hdr) => TransportMut::TCP(hdr as *mut tcphdr),
+            Transport::UDP(hdr) => TransportMut::UDP(hdr as *mut udphdr),
+            Transport::ICMP(hdr) => TransportMut::ICMP(hdr as *mut icmphdr),
+            Transport::ICMPV6(hdr) => TransportMut::ICMPV6(hdr as *mut icmp6hdr),
+        };
+
+        Ok(transport)
+    }
+
+    /// Copies `bytes` into the packet at `offset`.
+    #[inline]
+    fn store_bytes(&mut self, offset: usize, bytes: &[u8]) -> NetworkResult<()> {
+        let addr = self.data_start() + offset;
+        self.check_bounds(addr, addr + bytes.len())?;
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), addr as *mut u8, bytes.len()) };
+
+        Ok(())
+    }
+
+    /// Updates the IP checksum at `offset` after a field it covers changed
+    /// from `from` to `to`.
+    ///
+    /// `size` is the size of the changed field, either 2 or 4 bytes. `from`
+    /// and `to` are the raw values of the field, in network byte order. This
+    /// mirrors `bpf_l3_csum_replace`, but computes the checksum in place so it
+    /// also works in XDP programs.
+    #[inline]
+    fn l3_csum_replace(
+        &mut self,
+        offset: usize,
+        from: u64,
+        to: u64,
+        size: u64,
+    ) -> NetworkResult<()> {
+        let csum = unsafe { self.ptr_at_mut::<u16>(self.data_start() + offset)? };
+        unsafe {
+            let new = csum_replace(csum.read_unaligned(), from, to, size)?;
+            csum.write_unaligned(new);
+        }
+
+        Ok(())
+    }
+
+    /// Updates the TCP or UDP checksum at `offset` after a field it covers
+    /// changed from `from` to `to`.
+    ///
+    /// The size of the changed field is stored in the lower bits of `flags`,
+    /// as for `bpf_l4_csum_replace`. If the field is part of the pseudo
+    /// header, eg. an IP address, `flags` should include `BPF_F_PSEUDO_HDR`.
+    /// UDP checksums need `BPF_F_MARK_MANGLED_0`, since 0 means the packet
+    /// has no checksum.
+    #[inline]
+    fn l4_csum_replace(
+        &mut self,
+        offset: usize,
+        from: u64,
+        to: u64,
+        flags: u64,
+    ) -> NetworkResult<()> {
+        let csum = unsafe { self.ptr_at_mut::<u16>(self.data_start() + offset)? };
+        unsafe {
+            let old = csum.read_unaligned();
+            let mangled_0 = flags & BPF_F_MARK_MANGLED_0 as u64 != 0;
+            if mangled_0 && old == 0 {
+                return Ok(());
+            }
+            let size = flags & BPF_F_HDR_FIELD_MASK as u64;
+            let mut new = csum_replace(old, from, to, size)?;
+            if mangled_0 && new == 0 {
+                new = CSUM_MANGLED_0;
+            }
+            csum.write_unaligned(new);
+        }
+
+        Ok(())
+    }
+
     /// Returns the packet's data starting after the transport headers.
     #[inline]
     fn data(&self) -> NetworkResult<Data<Self>> {
@@ -169,6 +570,8 @@ where
                     self.ptr_at(addr)
                 }
                 UDP(hdr) => self.ptr_after(hdr),
+                ICMP(hdr) => self.ptr_after(hdr),
+                ICMPV6(hdr) => self.ptr_after(hdr),
             }?;
 
             let ctx: Self = self.clone();
@@ -180,6 +583,28 @@ where
     }
 }
 
+#[inline]
+fn is_vlan(proto: u16) -> bool {
+    proto == ETH_P_8021Q as u16 || proto == ETH_P_8021AD as u16
+}
+
+/// Returns the address of the payload of a UDP packet sent to `port`.
+#[inline]
+fn udp_payload<T: NetworkBuffer>(buf: &T, port: u16) -> NetworkResult<usize> {
+    match buf.transport()? {
+        Transport::UDP(udp) => {
+            if unsafe { u16::from_be((*udp).dest) } != port {
+                return Err(NetworkError::Other);
+            }
+
+            Ok(udp as usize + mem::size_of::<udphdr>())
+        }
+        Transport::TCP(_) => Err(NetworkError::UnsupportedTransport(IPPROTO_TCP)),
+        Transport::ICMP(_) => Err(NetworkError::UnsupportedTransport(IPPROTO_ICMP)),
+        Transport::ICMPV6(_) => Err(NetworkError::UnsupportedTransport(IPPROTO_ICMPV6)),
+    }
+}
+
 /// Data type returned by calling `NetworkBuffer::data()`
 pub struct Data<T: NetworkBuffer> {
     ctx: T,
@@ -211,6 +636,16 @@ impl<T: NetworkBuffer> Data<T> {
         }
     }
 
+    /// Returns a mutable `slice` of `len` bytes from the data.
+    #[inline]
+    pub fn slice_mut(&mut self, len: usize) -> NetworkResult<&mut [u8]> {
+        unsafe {
+            self.ctx.check_bounds(self.base, self.base + len)?;
+            let s = slice::from_raw_parts_mut(self.base as *mut u8, len);
+            Ok(s)
+        }
+    }
+
     #[inline]
     pub fn read<U: NetworkBufferArray>(&self) -> NetworkResult<U> {
         unsafe {
@@ -221,5 +656,505 @@ impl<T: NetworkBuffer> Data<T> {
     }
 }
 
+/// The value a UDP checksum that computes to 0 is sent as, since 0 means the
+/// packet has no checksum.
+const CSUM_MANGLED_0: u16 = 0xffff;
+
+/// Incrementally updates the internet checksum `csum` after a 16 bit word it
+/// covers changed from `from` to `to`, as described in RFC 1624.
+///
+/// All values are in network byte order.
+#[inline]
+pub fn csum_replace2(csum: u16, from: u16, to: u16) -> u16 {
+    !csum_fold(u32::from(!csum) + u32::from(!from) + u32::from(to))
+}
+
+/// Incrementally updates the internet checksum `csum` after a 32 bit word it
+/// covers changed from `from` to `to`, as described in RFC 1624.
+///
+/// All values are in network byte order.
+#[inline]
+pub fn csum_replace4(csum: u16, from: u32, to: u32) -> u16 {
+    let from = !from;
+    !csum_fold(u32::from(!csum) + (from & 0xffff) + (from >> 16) + (to & 0xffff) + (to >> 16))
+}
+
+#[inline]
+fn csum_replace(csum: u16, from: u64, to: u64, size: u64) -> NetworkResult<u16> {
+    match size {
+        2 => Ok(csum_replace2(csum, from as u16, to as u16)),
+        4 => Ok(csum_replace4(csum, from as u32, to as u32)),
+        _ => Err(NetworkError::Other),
+    }
+}
+
+#[inline]
+fn csum_fold(mut sum: u32) -> u16 {
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum = (sum & 0xffff) + (sum >> 16);
+    sum as u16
+}
+
 pub trait NetworkBufferArray {}
 impl_network_buffer_array!();
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Backing storage for test packets, aligned like the packet buffers of
+    /// the kernel.
+    #[repr(C, align(8))]
+    struct Storage([u8; 256]);
+
+    /// A `NetworkBuffer` over a byte array.
+    #[derive(Clone)]
+    struct Packet {
+        start: usize,
+        end: usize,
+    }
+
+    impl Packet {
+        /// Copies `bytes` 2 bytes into `storage`, so that the headers
+        /// following the Ethernet header are 4 byte aligned.
+        fn new(storage: &mut Storage, bytes: &[u8]) -> Packet {
+            storage.0[2..2 + bytes.len()].copy_from_slice(bytes);
+            let start = storage.0.as_mut_ptr() as usize + 2;
+            Packet {
+                start,
+                end: start + bytes.len(),
+            }
+        }
+    }
+
+    /// Builds a packet out of its headers.
+    fn build(storage: &mut Storage, headers: &[&[u8]]) -> Packet {
+        let mut bytes = [0; 254];
+        let mut len = 0;
+        for hdr in headers {
+            bytes[len..len + hdr.len()].copy_from_slice(hdr);
+            len += hdr.len();
+        }
+        Packet::new(storage, &bytes[..len])
+    }
+
+    impl NetworkBuffer for Packet {
+        fn data_start(&self) -> usize {
+            self.start
+        }
+
+        fn data_end(&self) -> usize {
+            self.end
+        }
+    }
+
+    // an IPv4 header of a UDP packet, with the checksum at bytes 10 and 11
+    const IPV4_HDR: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    /// Computes the internet checksum of `bytes` from scratch.
+    fn checksum(bytes: &[u8]) -> u16 {
+        let sum = bytes
+            .chunks(2)
+            .map(|w| u32::from(u16::from_ne_bytes([w[0], w[1]])))
+            .fold(0u32, |sum, w| {
+                let sum = sum + w;
+                (sum & 0xffff) + (sum >> 16)
+            });
+        !csum_fold(sum)
+    }
+
+    fn word(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_ne_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn dword(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut hdr = IPV4_HDR;
+        assert_eq!(checksum(&hdr), 0);
+        hdr[10..12].copy_from_slice(&[0, 0]);
+        assert_eq!(checksum(&hdr), word(&IPV4_HDR, 10));
+    }
+
+    #[test]
+    fn test_csum_fold() {
+        assert_eq!(csum_fold(0), 0);
+        assert_eq!(csum_fold(0xffff), 0xffff);
+        assert_eq!(csum_fold(0x1_0000), 0x0001);
+        assert_eq!(csum_fold(0x1_fffe), 0xffff);
+        // the first fold carries into bit 16 again
+        assert_eq!(csum_fold(0xffff_ffff), 0xffff);
+        assert_eq!(csum_fold(0x2_fffe), 0x0001);
+    }
+
+    #[test]
+    fn test_csum_replace2() {
+        let mut hdr = IPV4_HDR;
+        let csum = word(&hdr, 10);
+        // decrement the TTL
+        let from = word(&hdr, 8);
+        hdr[8] -= 1;
+        let to = word(&hdr, 8);
+        let new = csum_replace2(csum, from, to);
+        hdr[10..12].copy_from_slice(&new.to_ne_bytes());
+        assert_eq!(checksum(&hdr), 0);
+        assert_eq!(csum_replace2(new, to, from), csum);
+        assert_eq!(csum_replace2(csum, from, from), csum);
+    }
+
+    #[test]
+    fn test_csum_replace2_rfc1624() {
+        // the example of RFC 1624 section 4: the sum of the other words is
+        // 0xcd7a, and replacing 0x5555 with 0x3285 must give 0x0000, not
+        // the 0xffff of RFC 1141
+        assert_eq!(csum_replace2(0xdd2f, 0x5555, 0x3285), 0x0000);
+        assert_eq!(csum_replace2(0x0000, 0x3285, 0x5555), 0xdd2f);
+        // 0xffff and 0x0000 are both zero in one's complement
+        assert_eq!(csum_replace2(0xffff, 0x1234, 0x1234), 0x0000);
+        assert_eq!(csum_replace2(0x0000, 0x1234, 0x1234), 0x0000);
+    }
+
+    #[test]
+    fn test_csum_replace4() {
+        let mut hdr = IPV4_HDR;
+        let csum = word(&hdr, 10);
+        // rewrite the destination address to 10.1.2.3
+        let from = dword(&hdr, 16);
+        hdr[16..20].copy_from_slice(&[10, 1, 2, 3]);
+        let to = dword(&hdr, 16);
+        let new = csum_replace4(csum, from, to);
+        hdr[10..12].copy_from_slice(&new.to_ne_bytes());
+        assert_eq!(checksum(&hdr), 0);
+        assert_eq!(csum_replace4(new, to, from), csum);
+        // same as replacing both halves
+        let halves = csum_replace2(
+            csum_replace2(csum, word(&IPV4_HDR, 16), word(&hdr, 16)),
+            word(&IPV4_HDR, 18),
+            word(&hdr, 18),
+        );
+        assert_eq!(new, halves);
+    }
+
+    #[test]
+    fn test_l3_csum_replace() {
+        let mut storage = Storage([0; 256]);
+        let mut pkt = Packet::new(&mut storage, &IPV4_HDR);
+        let mut hdr = IPV4_HDR;
+        hdr[16..20].copy_from_slice(&[10, 1, 2, 3]);
+        let from = u64::from(dword(&IPV4_HDR, 16));
+        let to = u64::from(dword(&hdr, 16));
+        assert!(pkt.store_bytes(16, &hdr[16..20]).is_ok());
+        assert!(pkt.l3_csum_replace(10, from, to, 4).is_ok());
+        assert_eq!(checksum(&storage.0[2..22]), 0);
+
+        let mut pkt = Packet::new(&mut storage, &IPV4_HDR);
+        assert!(matches!(
+            pkt.l3_csum_replace(10, from, to, 3),
+            Err(NetworkError::Other)
+        ));
+        assert!(matches!(
+            pkt.l3_csum_replace(19, from, to, 4),
+            Err(NetworkError::OutOfBounds)
+        ));
+        assert!(matches!(
+            pkt.store_bytes(18, &hdr[16..20]),
+            Err(NetworkError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn test_l4_csum_replace_mangled_0() {
+        let mut storage = Storage([0; 256]);
+        let size = 2;
+        let mangled_0 = BPF_F_MARK_MANGLED_0 as u64 | size;
+
+        // 0 means the packet has no checksum, which must be kept
+        let mut pkt = Packet::new(&mut storage, &[0, 0]);
+        assert!(pkt.l4_csum_replace(0, 0x5555, 0x3285, mangled_0).is_ok());
+        assert_eq!(word(&storage.0, 2), 0x0000);
+
+        // a checksum computing to 0 is sent as 0xffff
+        let mut pkt = Packet::new(&mut storage, &0xdd2fu16.to_ne_bytes());
+        assert!(pkt.l4_csum_replace(0, 0x5555, 0x3285, mangled_0).is_ok());
+        assert_eq!(word(&storage.0, 2), 0xffff);
+
+        // without the flag the result is kept as is
+        let mut pkt = Packet::new(&mut storage, &0xdd2fu16.to_ne_bytes());
+        assert!(pkt.l4_csum_replace(0, 0x5555, 0x3285, size).is_ok());
+        assert_eq!(word(&storage.0, 2), 0x0000);
+
+        let mut pkt = Packet::new(&mut storage, &0xdd2fu16.to_ne_bytes());
+        assert!(pkt.l4_csum_replace(0, 0x3285, 0x5555, mangled_0).is_ok());
+        assert_eq!(word(&storage.0, 2), csum_replace2(0xdd2f, 0x3285, 0x5555));
+    }
+
+    const ETH_IPV4: [u8; 14] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0x08, 0x00];
+    const ETH_IPV6: [u8; 14] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0x86, 0xdd];
+    const ETH_VLAN: [u8; 14] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0x81, 0x00];
+    const VLAN_100_IPV4: [u8; 4] = [0x00, 0x64, 0x08, 0x00];
+    const UDP_PORT: [u8; 8] = [0x30, 0x39, 0, 0, 0, 0, 0, 0];
+
+    fn ipv4(protocol: u8) -> [u8; 20] {
+        [
+            0x45, 0, 0, 0, 0, 0, 0, 0, 64, protocol, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        ]
+    }
+
+    fn ipv6(nexthdr: u8) -> [u8; 40] {
+        let mut hdr = [0; 40];
+        hdr[0] = 0x60;
+        hdr[6] = nexthdr;
+        hdr[7] = 64;
+        hdr
+    }
+
+    fn udp(dest: u16) -> [u8; 8] {
+        let mut hdr = UDP_PORT;
+        hdr[2..4].copy_from_slice(&dest.to_be_bytes());
+        hdr
+    }
+
+    #[test]
+    fn test_ipv6_ext_headers() {
+        let mut storage = Storage([0; 256]);
+        let tcp = [
+            0x30, 0x39, 0x00, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        // 8 bytes of hop-by-hop options, 16 bytes of destination options
+        let hop = [IPPROTO_DSTOPTS as u8, 0, 0, 0, 0, 0, 0, 0];
+        let mut dst = [0; 16];
+        dst[0] = IPPROTO_TCP as u8;
+        dst[1] = 1;
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV6, &ipv6(IPPROTO_HOPOPTS as u8), &hop, &dst, &tcp],
+        );
+        let (proto, addr) = pkt.transport_header().ok().unwrap();
+        assert_eq!(proto, IPPROTO_TCP);
+        assert_eq!(pkt.offset(addr as *const u8), 14 + 40 + 8 + 16);
+        let transport = pkt.transport().ok().unwrap();
+        assert!(matches!(transport, Transport::TCP(_)));
+        assert_eq!(transport.source(), 12345);
+        assert_eq!(transport.dest(), 80);
+
+        // the length of authentication headers is in 4 byte words, minus 2
+        let mut ah = [0; 24];
+        ah[0] = IPPROTO_UDP as u8;
+        ah[1] = 4;
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV6, &ipv6(IPPROTO_AH as u8), &ah, &UDP_PORT],
+        );
+        let (proto, addr) = pkt.transport_header().ok().unwrap();
+        assert_eq!(proto, IPPROTO_UDP);
+        assert_eq!(pkt.offset(addr as *const u8), 14 + 40 + 24);
+
+        // the transport header must fit in the packet
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV6, &ipv6(IPPROTO_HOPOPTS as u8), &hop, &dst],
+        );
+        assert!(matches!(pkt.transport(), Err(NetworkError::OutOfBounds)));
+    }
+
+    #[test]
+    fn test_ipv6_fragments() {
+        let mut storage = Storage([0; 256]);
+        // the first fragment, with more fragments following
+        let mut frag = [IPPROTO_UDP as u8, 0, 0x00, 0x01, 0, 0, 0, 1];
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV6, &ipv6(IPPROTO_FRAGMENT as u8), &frag, &UDP_PORT],
+        );
+        let (proto, addr) = pkt.transport_header().ok().unwrap();
+        assert_eq!(proto, IPPROTO_UDP);
+        assert_eq!(pkt.offset(addr as *const u8), 14 + 40 + 8);
+
+        // the fragment at offset 1480 has no transport header
+        frag[2..4].copy_from_slice(&(1480u16 | 1).to_be_bytes());
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV6, &ipv6(IPPROTO_FRAGMENT as u8), &frag, &UDP_PORT],
+        );
+        assert!(matches!(
+            pkt.transport_header(),
+            Err(NetworkError::UnsupportedTransport(IPPROTO_FRAGMENT))
+        ));
+    }
+
+    #[test]
+    fn test_gre() {
+        let mut storage = Storage([0; 256]);
+        let gre = [0x00, 0x00, 0x08, 0x00];
+        let pkt = build(&mut storage, &[&ETH_IPV4, &ipv4(IPPROTO_GRE as u8), &gre]);
+        let tunnel = pkt.gre().ok().unwrap();
+        assert_eq!(tunnel.proto, ETH_P_IP as u16);
+        assert_eq!(tunnel.id, 0);
+        assert_eq!(tunnel.offset, 14 + 20 + 4);
+
+        // checksum, key and sequence number, behind a VLAN tag
+        let gre = [
+            0xb0, 0x00, 0x65, 0x58, 0, 0, 0, 0, 0x00, 0x00, 0x12, 0x34, 0, 0, 0, 1,
+        ];
+        let pkt = build(
+            &mut storage,
+            &[&ETH_VLAN, &VLAN_100_IPV4, &ipv4(IPPROTO_GRE as u8), &gre],
+        );
+        assert_eq!(pkt.vlan_id(), Some(100));
+        let tunnel = pkt.gre().ok().unwrap();
+        assert_eq!(tunnel.proto, ETH_P_TEB as u16);
+        assert_eq!(tunnel.id, 0x1234);
+        assert_eq!(tunnel.offset, 14 + 4 + 20 + 16);
+
+        // source routing isn't supported
+        let gre = [0x40, 0x00, 0x08, 0x00];
+        let pkt = build(&mut storage, &[&ETH_IPV4, &ipv4(IPPROTO_GRE as u8), &gre]);
+        assert!(matches!(pkt.gre(), Err(NetworkError::Other)));
+
+        // the key must fit in the packet
+        let gre = [0x20, 0x00, 0x08, 0x00, 0x00, 0x00];
+        let pkt = build(&mut storage, &[&ETH_IPV4, &ipv4(IPPROTO_GRE as u8), &gre]);
+        assert!(matches!(pkt.gre(), Err(NetworkError::OutOfBounds)));
+
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV4, &ipv4(IPPROTO_UDP as u8), &UDP_PORT],
+        );
+        assert!(matches!(
+            pkt.gre(),
+            Err(NetworkError::UnsupportedTransport(IPPROTO_UDP))
+        ));
+    }
+
+    #[test]
+    fn test_vxlan() {
+        let mut storage = Storage([0; 256]);
+        let vxlan = [0x08, 0, 0, 0, 0x00, 0xab, 0xcd, 0x00];
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV4,
+                &ipv4(IPPROTO_UDP as u8),
+                &udp(VXLAN_PORT),
+                &vxlan,
+            ],
+        );
+        let tunnel = pkt.vxlan().ok().unwrap();
+        assert_eq!(tunnel.proto, ETH_P_TEB as u16);
+        assert_eq!(tunnel.id, 0xabcd);
+        assert_eq!(tunnel.offset, 14 + 20 + 8 + 8);
+
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV6,
+                &ipv6(IPPROTO_UDP as u8),
+                &udp(VXLAN_PORT),
+                &vxlan,
+            ],
+        );
+        assert_eq!(pkt.vxlan().ok().unwrap().offset, 14 + 40 + 8 + 8);
+
+        // the VNI flag must be set
+        let no_vni = [0, 0, 0, 0, 0x00, 0xab, 0xcd, 0x00];
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV4,
+                &ipv4(IPPROTO_UDP as u8),
+                &udp(VXLAN_PORT),
+                &no_vni,
+            ],
+        );
+        assert!(matches!(pkt.vxlan(), Err(NetworkError::Other)));
+
+        let pkt = build(
+            &mut storage,
+            &[&ETH_IPV4, &ipv4(IPPROTO_UDP as u8), &udp(53), &vxlan],
+        );
+        assert!(matches!(pkt.vxlan(), Err(NetworkError::Other)));
+
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV4,
+                &ipv4(IPPROTO_UDP as u8),
+                &udp(VXLAN_PORT),
+                &vxlan[..4],
+            ],
+        );
+        assert!(matches!(pkt.vxlan(), Err(NetworkError::OutOfBounds)));
+    }
+
+    #[test]
+    fn test_geneve() {
+        let mut storage = Storage([0; 256]);
+        let geneve = [0x00, 0x00, 0x65, 0x58, 0x12, 0x34, 0x56, 0x00];
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV4,
+                &ipv4(IPPROTO_UDP as u8),
+                &udp(GENEVE_PORT),
+                &geneve,
+            ],
+        );
+        let tunnel = pkt.geneve().ok().unwrap();
+        assert_eq!(tunnel.proto, ETH_P_TEB as u16);
+        assert_eq!(tunnel.id, 0x123456);
+        assert_eq!(tunnel.offset, 14 + 20 + 8 + 8);
+
+        // 8 bytes of options
+        let mut geneve = geneve;
+        geneve[0] = 2;
+        let options = [0; 8];
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV6,
+                &ipv6(IPPROTO_UDP as u8),
+                &udp(GENEVE_PORT),
+                &geneve,
+                &options,
+            ],
+        );
+        let tunnel = pkt.geneve().ok().unwrap();
+        assert_eq!(tunnel.id, 0x123456);
+        assert_eq!(tunnel.offset, 14 + 40 + 8 + 8 + 8);
+
+        // only version 0 is supported
+        geneve[0] = 0x40;
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV4,
+                &ipv4(IPPROTO_UDP as u8),
+                &udp(GENEVE_PORT),
+                &geneve,
+            ],
+        );
+        assert!(matches!(pkt.geneve(), Err(NetworkError::Other)));
+
+        let pkt = build(
+            &mut storage,
+            &[
+                &ETH_IPV4,
+                &ipv4(IPPROTO_UDP as u8),
+                &udp(VXLAN_PORT),
+                &geneve,
+            ],
+        );
+        assert!(matches!(pkt.geneve(), Err(NetworkError::Other)));
+    }
+}
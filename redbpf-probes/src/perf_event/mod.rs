@@ -0,0 +1,79 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Perf event programs.
+
+`perf_event` programs are attached to perf events and run every time the
+event is sampled, with the registers of the interrupted task. Attached to a
+CPU clock, they can be used to build sampling profilers.
+
+# Example
+
+Count how many times each kernel stack is sampled:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::perf_event::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+#[map]
+static mut STACKS: StackTrace = StackTrace::with_max_entries(10240);
+
+#[map]
+static mut COUNTS: HashMap<i32, u64> = HashMap::with_max_entries(10240);
+
+#[perf_event]
+fn sample(ctx: PerfEventContext) {
+    unsafe {
+        if let Ok(id) = STACKS.stack_id(ctx.regs().ctx, 0) {
+            let count = COUNTS.get(&id).copied().unwrap_or(0);
+            COUNTS.set(&id, &(count + 1));
+        }
+    }
+}
+```
+*/
+pub mod prelude;
+
+use crate::bindings::*;
+use crate::registers::Registers;
+
+/// Context object provided to `perf_event` programs.
+pub struct PerfEventContext {
+    /// The `struct bpf_perf_event_data` passed by the kernel.
+    pub ctx: *mut bpf_perf_event_data,
+}
+
+impl PerfEventContext {
+    /// Returns the registers of the task that was running when the event
+    /// was sampled.
+    ///
+    /// The registers can be passed to `StackTrace::stack_id` to get the
+    /// stack of the task.
+    #[inline]
+    pub fn regs(&self) -> Registers {
+        Registers {
+            ctx: self.ctx as *mut pt_regs,
+        }
+    }
+
+    /// Returns the number of events since the previous sample.
+    #[inline]
+    pub fn sample_period(&self) -> u64 {
+        unsafe { (*self.ctx).sample_period }
+    }
+
+    /// Returns the address the event happened at, for events that record
+    /// one.
+    #[inline]
+    pub fn addr(&self) -> u64 {
+        unsafe { (*self.ctx).addr }
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Raw tracepoints.
+
+Raw tracepoints attach to the same hooks as tracepoints, but skip the
+conversion of the arguments to a tracepoint record: programs receive the
+arguments of the `TP_PROTO` of the tracepoint as is, which makes them cheaper
+to run.
+
+Raw tracepoints are identified by name only, without a category. The
+arguments for a tracepoint can be found in the `TP_PROTO` of its definition in
+the kernel sources.
+
+BTF tracepoints, defined with `#[tp_btf]`, get the same arguments, but the
+tracepoint is looked up in the BTF of the running kernel when the program is
+loaded. This requires a kernel built with `CONFIG_DEBUG_INFO_BTF`, and lets
+the verifier type check the arguments, so that pointers to kernel structures
+can be read directly instead of with `bpf_probe_read`.
+
+# Example
+
+Do something every time a process is scheduled out:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::raw_tracepoint::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+// TP_PROTO(bool preempt, struct task_struct *prev, struct task_struct *next)
+#[raw_tracepoint("sched_switch")]
+fn sched_switch(ctx: RawTracePointContext) {
+    let prev = ctx.arg(1) as *const c_void;
+    // do something with prev
+}
+
+#[tp_btf("sched_switch")]
+fn sched_switch_btf(ctx: RawTracePointContext) {
+    let next = ctx.arg(2) as *const c_void;
+    // do something with next
+}
+```
+ */
+pub mod prelude;
+
+use cty::*;
+
+/// Context object provided to raw tracepoint and BTF tracepoint programs.
+pub struct RawTracePointContext {
+    /// The `struct bpf_raw_tracepoint_args` passed by the kernel.
+    pub ctx: *mut c_void,
+}
+
+impl RawTracePointContext {
+    #[inline]
+    pub fn new(ctx: *mut c_void) -> Self {
+        RawTracePointContext { ctx }
+    }
+
+    /// Returns the raw arguments passed by the kernel.
+    #[inline]
+    pub fn inner(&self) -> *mut c_void {
+        self.ctx
+    }
+
+    /// Returns the `n`-th argument of the tracepoint.
+    ///
+    /// Arguments are passed as 64 bit integers, pointers need to be cast to
+    /// the right type and read with `bpf_probe_read`.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u64 {
+        unsafe { *(self.ctx as *const u64).add(n) }
+    }
+}
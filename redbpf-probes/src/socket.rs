@@ -1,8 +1,12 @@
 //! Socket related type and functions
 
 use crate::bindings::*;
-use crate::helpers::bpf_skb_load_bytes;
+use crate::helpers::{
+    bpf_l3_csum_replace, bpf_l4_csum_replace, bpf_skb_load_bytes, bpf_skb_store_bytes,
+};
+use crate::net::NetworkError;
 use core::mem::{size_of, MaybeUninit};
+use core::ptr;
 
 pub trait FromBe {
     fn from_be(&self) -> Self;
@@ -26,9 +30,23 @@ impl_from_be!(u32);
 pub enum SocketError {
     /// Loading data from the socket buffer failed.
     LoadFailed,
+    /// Storing data in the socket buffer or updating its checksums failed.
+    StoreFailed,
+    /// Parsing the packet with `NetworkBuffer` failed.
+    Network(NetworkError),
+}
+
+impl From<NetworkError> for SocketError {
+    fn from(error: NetworkError) -> SocketError {
+        SocketError::Network(error)
+    }
 }
 
 /// Context object provided to Socket-related programs.
+///
+/// `tc` programs can also access the packet directly through
+/// [`TcContext`](../tc/struct.TcContext.html).
+#[derive(Clone)]
 pub struct SkBuff {
     /// The low level skb instance.
     pub skb: *const __sk_buff,
@@ -73,4 +91,83 @@ impl SkBuff {
             Ok(data.assume_init().from_be())
         }
     }
+
+    /// Stores `bytes` in the socket buffer at `offset`.
+    ///
+    /// `flags` can contain `BPF_F_RECOMPUTE_CSUM` to update the checksum of
+    /// the whole packet, and `BPF_F_INVALIDATE_HASH` to reset the packet hash.
+    /// Only `tc` programs can write to the socket buffer.
+    #[inline]
+    pub fn store_bytes(&self, offset: usize, bytes: &[u8], flags: u64) -> Result<(), SocketError> {
+        let ret = unsafe {
+            bpf_skb_store_bytes(
+                self.skb as *mut _,
+                offset as u32,
+                bytes.as_ptr() as *const _,
+                bytes.len() as u32,
+                flags,
+            )
+        };
+        check_store(ret)
+    }
+
+    /// Updates the IP checksum at `offset` after a field it covers changed
+    /// from `from` to `to`.
+    ///
+    /// `size` is the size of the changed field, either 2 or 4 bytes. `from`
+    /// and `to` are the raw values of the field, in network byte order.
+    #[inline]
+    pub fn l3_csum_replace(
+        &self,
+        offset: usize,
+        from: u64,
+        to: u64,
+        size: u64,
+    ) -> Result<(), SocketError> {
+        let ret = unsafe { bpf_l3_csum_replace(self.skb as *mut _, offset as u32, from, to, size) };
+        check_store(ret)
+    }
+
+    /// Updates the TCP or UDP checksum at `offset` after a field it covers
+    /// changed from `from` to `to`.
+    ///
+    /// The size of the changed field is stored in the lower bits of `flags`.
+    /// If the field is part of the pseudo header, eg. an IP address, `flags`
+    /// should include `BPF_F_PSEUDO_HDR`. UDP checksums need
+    /// `BPF_F_MARK_MANGLED_0`, since 0 means the packet has no checksum.
+    #[inline]
+    pub fn l4_csum_replace(
+        &self,
+        offset: usize,
+        from: u64,
+        to: u64,
+        flags: u64,
+    ) -> Result<(), SocketError> {
+        let ret =
+            unsafe { bpf_l4_csum_replace(self.skb as *mut _, offset as u32, from, to, flags) };
+        check_store(ret)
+    }
+}
+
+#[inline]
+fn check_store(ret: i32) -> Result<(), SocketError> {
+    if ret < 0 {
+        return Err(SocketError::StoreFailed);
+    }
+
+    Ok(())
+}
+
+/// Reads an IPv6 address from a program context.
+///
+/// The verifier only allows 4 byte accesses to the addresses in contexts,
+/// volatile reads keep LLVM from merging them.
+#[inline]
+pub(crate) unsafe fn read_ip6(ip6: &[u32; 4]) -> [u32; 4] {
+    [
+        ptr::read_volatile(&ip6[0]),
+        ptr::read_volatile(&ip6[1]),
+        ptr::read_volatile(&ip6[2]),
+        ptr::read_volatile(&ip6[3]),
+    ]
 }
@@ -0,0 +1,237 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Socket redirection programs.
+
+These programs are used together with a `SockMap` or `SockHash` to move data
+directly between sockets, bypassing the TCP/IP stack. There are three kinds:
+
+* `#[sock_ops]` programs are attached to a cgroup and run on TCP events,
+  like a connection being established. They get a `SockOpsContext` and
+  usually add the sockets to a `SockMap` or `SockHash`.
+* `#[sk_msg]` programs are attached to a sockmap and run for every
+  `sendmsg()` on the sockets in the map. They get a `SkMsgContext` and can
+  redirect the message to another socket in the map.
+* `#[sk_skb(stream_parser|stream_verdict)]` programs are attached to a
+  sockmap and run for the data received by the sockets in the map. Parsers
+  return the length of the next message, verdicts can redirect the message
+  to another socket in the map.
+
+# Example
+
+Redirect the messages sent by local connections to their peer:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::sockmap::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+#[repr(C)]
+struct SockKey {
+    local_ip4: u32,
+    remote_ip4: u32,
+    local_port: u32,
+    remote_port: u32,
+}
+
+#[map]
+static mut SOCKETS: SockHash<SockKey> = SockHash::with_max_entries(65535);
+
+#[sock_ops]
+fn add_sockets(ops: SockOpsContext) {
+    match ops.op() {
+        BPF_SOCK_OPS_ACTIVE_ESTABLISHED_CB | BPF_SOCK_OPS_PASSIVE_ESTABLISHED_CB => {
+            let key = SockKey {
+                local_ip4: ops.local_ip4(),
+                remote_ip4: ops.remote_ip4(),
+                local_port: ops.local_port() as u32,
+                remote_port: ops.remote_port() as u32,
+            };
+            let _ = unsafe { SOCKETS.update(&ops, &key, BPF_ANY as u64) };
+        }
+        _ => {}
+    }
+}
+
+#[sk_msg]
+fn redirect(msg: SkMsgContext) -> SkMsgResult {
+    let key = SockKey {
+        local_ip4: msg.remote_ip4(),
+        remote_ip4: msg.local_ip4(),
+        local_port: msg.remote_port() as u32,
+        remote_port: msg.local_port() as u32,
+    };
+    Ok(unsafe { SOCKETS.redirect_msg(&msg, &key, BPF_F_INGRESS as u64) })
+}
+```
+*/
+pub mod prelude;
+
+use crate::bindings::*;
+use crate::helpers::*;
+use crate::socket::{read_ip6, SocketError};
+
+/// The return type of `sk_msg` and `sk_skb` verdict programs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkAction {
+    /// Let the data through, or redirect it if it was redirected.
+    Pass,
+    /// Drop the data.
+    Drop,
+}
+
+/// Result type for `sk_msg` programs.
+///
+/// Errors are treated as `SkAction::Pass`.
+pub type SkMsgResult = Result<SkAction, SocketError>;
+
+/// Context object provided to `sock_ops` programs.
+pub struct SockOpsContext {
+    /// The socket and the TCP event.
+    pub ops: *mut bpf_sock_ops,
+}
+
+impl SockOpsContext {
+    /// Returns the TCP event, one of the `BPF_SOCK_OPS_*` constants.
+    #[inline]
+    pub fn op(&self) -> u32 {
+        unsafe { (*self.ops).op }
+    }
+
+    /// Returns the argument `n` of the event.
+    #[inline]
+    pub fn arg(&self, n: usize) -> u32 {
+        unsafe { (*self.ops).__bindgen_anon_1.args[n] }
+    }
+
+    /// Sets the value returned to the kernel for events that expect one, like
+    /// `BPF_SOCK_OPS_TIMEOUT_INIT`.
+    #[inline]
+    pub fn set_reply(&mut self, reply: u32) {
+        unsafe { (*self.ops).__bindgen_anon_1.reply = reply }
+    }
+
+    /// Enables the callbacks for the events in `flags`, a combination of the
+    /// `BPF_SOCK_OPS_*_CB_FLAG` constants.
+    #[inline]
+    pub fn set_cb_flags(&mut self, flags: i32) -> Result<(), i32> {
+        let ret = unsafe { bpf_sock_ops_cb_flags_set(self.ops, flags) };
+        if ret < 0 {
+            return Err(ret);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn family(&self) -> u32 {
+        unsafe { (*self.ops).family }
+    }
+
+    /// Returns the remote IPv4 address, in network byte order.
+    #[inline]
+    pub fn remote_ip4(&self) -> u32 {
+        unsafe { (*self.ops).remote_ip4 }
+    }
+
+    /// Returns the local IPv4 address, in network byte order.
+    #[inline]
+    pub fn local_ip4(&self) -> u32 {
+        unsafe { (*self.ops).local_ip4 }
+    }
+
+    /// Returns the remote IPv6 address, in network byte order.
+    #[inline]
+    pub fn remote_ip6(&self) -> [u32; 4] {
+        unsafe { read_ip6(&(*self.ops).remote_ip6) }
+    }
+
+    /// Returns the local IPv6 address, in network byte order.
+    #[inline]
+    pub fn local_ip6(&self) -> [u32; 4] {
+        unsafe { read_ip6(&(*self.ops).local_ip6) }
+    }
+
+    #[inline]
+    pub fn remote_port(&self) -> u16 {
+        unsafe { u32::from_be((*self.ops).remote_port) as u16 }
+    }
+
+    #[inline]
+    pub fn local_port(&self) -> u16 {
+        unsafe { (*self.ops).local_port as u16 }
+    }
+}
+
+/// Context object provided to `sk_msg` programs.
+pub struct SkMsgContext {
+    /// The message being sent.
+    pub msg: *mut sk_msg_md,
+}
+
+impl SkMsgContext {
+    #[inline]
+    pub fn family(&self) -> u32 {
+        unsafe { (*self.msg).family }
+    }
+
+    /// Returns the remote IPv4 address, in network byte order.
+    #[inline]
+    pub fn remote_ip4(&self) -> u32 {
+        unsafe { (*self.msg).remote_ip4 }
+    }
+
+    /// Returns the local IPv4 address, in network byte order.
+    #[inline]
+    pub fn local_ip4(&self) -> u32 {
+        unsafe { (*self.msg).local_ip4 }
+    }
+
+    /// Returns the remote IPv6 address, in network byte order.
+    #[inline]
+    pub fn remote_ip6(&self) -> [u32; 4] {
+        unsafe { read_ip6(&(*self.msg).remote_ip6) }
+    }
+
+    /// Returns the local IPv6 address, in network byte order.
+    #[inline]
+    pub fn local_ip6(&self) -> [u32; 4] {
+        unsafe { read_ip6(&(*self.msg).local_ip6) }
+    }
+
+    #[inline]
+    pub fn remote_port(&self) -> u16 {
+        unsafe { u32::from_be((*self.msg).remote_port) as u16 }
+    }
+
+    #[inline]
+    pub fn local_port(&self) -> u16 {
+        unsafe { (*self.msg).local_port as u16 }
+    }
+
+    /// Returns the total size of the message.
+    #[inline]
+    pub fn size(&self) -> u32 {
+        unsafe { (*self.msg).size }
+    }
+
+    /// Applies the verdict of the program to the next `bytes` bytes only.
+    #[inline]
+    pub fn apply_bytes(&mut self, bytes: u32) {
+        unsafe { bpf_msg_apply_bytes(self.msg, bytes) };
+    }
+
+    /// Buffers the data until `bytes` bytes are available before running
+    /// the program again.
+    #[inline]
+    pub fn cork_bytes(&mut self, bytes: u32) {
+        unsafe { bpf_msg_cork_bytes(self.msg, bytes) };
+    }
+}
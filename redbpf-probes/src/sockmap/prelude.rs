@@ -0,0 +1,22 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+//! The sockmap Prelude
+//!
+//! The purpose of this module is to alleviate imports of the common socket
+//! redirection program types by adding a glob import to the top of
+//! `sock_ops`, `sk_msg` and `sk_skb` programs:
+//!
+//! ```
+//! use redbpf_probes::sockmap::prelude::*;
+//! ```
+pub use crate::bindings::*;
+pub use crate::helpers::*;
+pub use crate::maps::*;
+pub use crate::socket::*;
+pub use crate::sockmap::*;
+pub use cty::*;
+pub use redbpf_macros::{map, program, sk_msg, sk_skb, sock_ops};
@@ -1,4 +1,6 @@
-use crate::socket::SocketError;
+use crate::helpers::bpf_skb_pull_data;
+use crate::net::{NetworkBuffer, NetworkError, NetworkResult};
+use crate::socket::{SkBuff, SocketError};
 
 /// Possible actions in tc programs
 pub enum TcAction {
@@ -18,12 +20,128 @@ pub enum TcAction {
 /// Result type for tc action programs.
 pub type TcActionResult = Result<TcAction, SocketError>;
 
+/// Context object giving `tc` programs direct access to the packet.
+///
+/// `tc_action` programs can take a `TcContext` instead of a `SkBuff` to
+/// parse and rewrite the packet through `NetworkBuffer`, like XDP programs
+/// do.
+///
+/// Only the linear part of the socket buffer can be accessed directly, and
+/// it may not include all the headers. `NetworkBuffer` methods fail with
+/// `NetworkError::OutOfBounds` past its end, so programs must call
+/// `TcContext::pull_data` first to make the headers they need accessible.
+///
+/// Writes and checksum updates go through `bpf_skb_store_bytes`,
+/// `bpf_l3_csum_replace` and `bpf_l4_csum_replace`, which keep the checksum
+/// state of the socket buffer consistent. They invalidate all the pointers
+/// into the packet.
+#[derive(Clone)]
+pub struct TcContext {
+    pub skb: SkBuff,
+}
+
+impl TcContext {
+    /// Returns the length of the whole packet, including the part that
+    /// can't be accessed directly.
+    #[inline]
+    pub fn packet_len(&self) -> usize {
+        unsafe { (*self.skb.skb).len as usize }
+    }
+
+    /// Makes the first `len` bytes of the packet accessible through
+    /// `NetworkBuffer`.
+    ///
+    /// Fails if the packet is shorter than `len` bytes. Pulling the data
+    /// invalidates all the pointers into the packet.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf_probes::tc::prelude::*;
+    ///
+    /// #[tc_action]
+    /// fn block_ssh(ctx: TcContext) -> TcActionResult {
+    ///     let len = ctx.packet_len().min(128);
+    ///     if ctx.len() < len {
+    ///         ctx.pull_data(len)?;
+    ///     }
+    ///     let transport = ctx.transport()?;
+    ///     if let Transport::TCP(_) = transport {
+    ///         if transport.dest() == 22 {
+    ///             return Ok(TcAction::Shot);
+    ///         }
+    ///     }
+    ///     Ok(TcAction::Ok)
+    /// }
+    /// ```
+    #[inline]
+    pub fn pull_data(&self, len: usize) -> Result<(), SocketError> {
+        let ret = unsafe { bpf_skb_pull_data(self.skb.skb as *mut _, len as u32) };
+        if ret < 0 {
+            return Err(SocketError::LoadFailed);
+        }
+
+        Ok(())
+    }
+}
+
+impl From<SkBuff> for TcContext {
+    fn from(skb: SkBuff) -> TcContext {
+        TcContext { skb }
+    }
+}
+
+impl NetworkBuffer for TcContext {
+    #[inline]
+    fn data_start(&self) -> usize {
+        unsafe { (*self.skb.skb).data as usize }
+    }
+
+    #[inline]
+    fn data_end(&self) -> usize {
+        unsafe { (*self.skb.skb).data_end as usize }
+    }
+
+    #[inline]
+    fn store_bytes(&mut self, offset: usize, bytes: &[u8]) -> NetworkResult<()> {
+        self.skb
+            .store_bytes(offset, bytes, 0)
+            .map_err(|_| NetworkError::Other)
+    }
+
+    #[inline]
+    fn l3_csum_replace(
+        &mut self,
+        offset: usize,
+        from: u64,
+        to: u64,
+        size: u64,
+    ) -> NetworkResult<()> {
+        self.skb
+            .l3_csum_replace(offset, from, to, size)
+            .map_err(|_| NetworkError::Other)
+    }
+
+    #[inline]
+    fn l4_csum_replace(
+        &mut self,
+        offset: usize,
+        from: u64,
+        to: u64,
+        flags: u64,
+    ) -> NetworkResult<()> {
+        self.skb
+            .l4_csum_replace(offset, from, to, flags)
+            .map_err(|_| NetworkError::Other)
+    }
+}
+
 pub mod prelude {
     pub use super::*;
 
     pub use crate::bindings::*;
     pub use crate::helpers::*;
     pub use crate::maps::*;
+    pub use crate::net::*;
     pub use crate::socket::*;
     pub use redbpf_macros::{program, tc_action};
 }
@@ -0,0 +1,114 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/*!
+Tracepoints.
+
+Tracepoints are static hooks placed in the kernel source code. Unlike kprobes,
+their arguments are part of a stable ABI described by the `format` file found
+under `/sys/kernel/debug/tracing/events/<category>/<name>/`.
+
+The argument types for a tracepoint can be generated from its `format` file
+with `cargo bpf bindgen --tracepoint <category>:<name>`, or from a build
+script using
+[`cargo_bpf_lib::bindgen::generate_tracepoint_bindings`](https://ingraind.org/api/cargo_bpf/bindgen/fn.generate_tracepoint_bindings.html).
+
+# Example
+
+Do something every time a process is scheduled out:
+
+```no_run
+#![no_std]
+#![no_main]
+use redbpf_probes::tracepoint::prelude::*;
+
+program!(0xFFFFFFFE, "GPL");
+
+// generated with `cargo bpf bindgen --tracepoint sched:sched_switch`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct trace_event_raw_sched_switch {
+    pub common_type: u16,
+    pub common_flags: u8,
+    pub common_preempt_count: u8,
+    pub common_pid: i32,
+    pub prev_comm: [i8; 16],
+    pub prev_pid: i32,
+    pub prev_prio: i32,
+    pub prev_state: i64,
+    pub next_comm: [i8; 16],
+    pub next_pid: i32,
+    pub next_prio: i32,
+}
+
+#[tracepoint("sched:sched_switch")]
+fn sched_switch(ctx: TracePointContext<trace_event_raw_sched_switch>) {
+    let args = ctx.args();
+    let prev_pid = args.prev_pid;
+    // do something with prev_pid
+}
+```
+ */
+pub mod prelude;
+
+use core::marker::PhantomData;
+use cty::*;
+
+/// The fields shared by all tracepoint records.
+///
+/// Every record passed to a tracepoint program starts with these fields,
+/// and so do the types generated by `cargo bpf bindgen --tracepoint`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TracePointCommon {
+    pub common_type: u16,
+    pub common_flags: u8,
+    pub common_preempt_count: u8,
+    pub common_pid: i32,
+}
+
+/// Context object provided to tracepoint programs.
+///
+/// `T` is the layout of the tracepoint record, as described by the
+/// tracepoint's `format` file.
+pub struct TracePointContext<T> {
+    /// The raw tracepoint record passed by the kernel.
+    pub ctx: *mut c_void,
+    _args: PhantomData<T>,
+}
+
+impl<T> TracePointContext<T> {
+    #[inline]
+    pub fn new(ctx: *mut c_void) -> Self {
+        TracePointContext {
+            ctx,
+            _args: PhantomData,
+        }
+    }
+
+    /// Returns the raw tracepoint record passed by the kernel.
+    #[inline]
+    pub fn inner(&self) -> *mut c_void {
+        self.ctx
+    }
+
+    /// Returns the fields common to all tracepoint records.
+    #[inline]
+    pub fn common(&self) -> &TracePointCommon {
+        unsafe { &*(self.ctx as *const TracePointCommon) }
+    }
+
+    /// Returns the tracepoint arguments.
+    ///
+    /// The record is read directly from the context, so `T` must match the
+    /// layout described in the tracepoint's `format` file or the verifier
+    /// will reject the program.
+    #[inline]
+    pub fn args(&self) -> &T {
+        unsafe { &*(self.ctx as *const T) }
+    }
+}
@@ -36,9 +36,15 @@ fn block_port_80(ctx: XdpContext) -> XdpResult {
  */
 pub mod prelude;
 
+use core::mem;
+use cty::*;
+
 use crate::bindings::*;
-use crate::maps::{PerfMap as PerfMapBase, PerfMapFlags};
-use crate::net::{NetworkBuffer, NetworkResult};
+use crate::helpers::{
+    bpf_redirect_map, bpf_xdp_adjust_head, bpf_xdp_adjust_meta, bpf_xdp_adjust_tail,
+};
+use crate::maps::{MapBtf, PerfMap as PerfMapBase, PerfMapFlags};
+use crate::net::{NetworkBuffer, NetworkError, NetworkResult, Tunnel};
 
 /// The result type for XDP programs.
 pub type XdpResult = NetworkResult<XdpAction>;
@@ -81,6 +87,89 @@ impl XdpContext {
     pub fn inner(&self) -> *mut xdp_md {
         self.ctx
     }
+
+    /// Returns the index of the receive queue the packet arrived on.
+    #[inline]
+    pub fn rx_queue_index(&self) -> u32 {
+        unsafe { (*self.ctx).rx_queue_index }
+    }
+
+    /// Moves the start of the packet by `delta` bytes.
+    ///
+    /// A negative `delta` grows the packet at the front, eg. to push an
+    /// encapsulation header, a positive one shrinks it. The packet must be
+    /// bounds checked again after this, since all the pointers into it are
+    /// invalidated.
+    #[inline]
+    pub fn adjust_head(&mut self, delta: i32) -> NetworkResult<()> {
+        check_adjust(unsafe { bpf_xdp_adjust_head(self.ctx, delta) })
+    }
+
+    /// Moves the end of the packet by `delta` bytes.
+    ///
+    /// A negative `delta` truncates the packet. Growing packets requires
+    /// Linux 5.8 or newer.
+    #[inline]
+    pub fn adjust_tail(&mut self, delta: i32) -> NetworkResult<()> {
+        check_adjust(unsafe { bpf_xdp_adjust_tail(self.ctx, delta) })
+    }
+
+    /// Moves the start of the metadata area in front of the packet by
+    /// `delta` bytes.
+    ///
+    /// A negative `delta` grows the area. The metadata can be used to pass
+    /// data to a `tc` program processing the packet later, and starts at
+    /// `data_meta` in the context returned by `inner`.
+    #[inline]
+    pub fn adjust_meta(&mut self, delta: i32) -> NetworkResult<()> {
+        check_adjust(unsafe { bpf_xdp_adjust_meta(self.ctx, delta) })
+    }
+
+    /// Strips the outer headers of a packet encapsulated in `tunnel`.
+    ///
+    /// If the encapsulated packet is an `Ethernet` frame, eg. for VXLAN, it
+    /// becomes the packet. Otherwise the outer `Ethernet` header is kept in
+    /// front of the encapsulated network header, without any VLAN tags.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf_probes::xdp::prelude::*;
+    ///
+    /// #[xdp]
+    /// fn decap_vxlan(mut ctx: XdpContext) -> XdpResult {
+    ///     if let Ok(tunnel) = ctx.vxlan() {
+    ///         ctx.decapsulate(&tunnel)?;
+    ///     }
+    ///     Ok(XdpAction::Pass)
+    /// }
+    /// ```
+    #[inline]
+    pub fn decapsulate(&mut self, tunnel: &Tunnel) -> NetworkResult<()> {
+        if tunnel.proto == ETH_P_TEB as u16 {
+            return self.adjust_head(tunnel.offset as i32);
+        }
+
+        let eth_len = mem::size_of::<ethhdr>();
+        if tunnel.offset < eth_len {
+            return Err(NetworkError::OutOfBounds);
+        }
+        let offset = tunnel.offset - eth_len;
+        unsafe {
+            let mut eth = self.eth()?.read();
+            eth.h_proto = tunnel.proto.to_be();
+            self.ptr_at_mut::<ethhdr>(self.data_start() + offset)?.write(eth);
+        }
+        self.adjust_head(offset as i32)
+    }
+}
+
+#[inline]
+fn check_adjust(ret: c_int) -> NetworkResult<()> {
+    if ret < 0 {
+        return Err(NetworkError::Other);
+    }
+
+    Ok(())
 }
 
 impl NetworkBuffer for XdpContext {
@@ -130,6 +219,11 @@ impl<T> MapData<T> {
 #[repr(transparent)]
 pub struct PerfMap<T>(PerfMapBase<MapData<T>>);
 
+impl<T> MapBtf for PerfMap<T> {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
 impl<T> PerfMap<T> {
     /// Creates a perf map with the specified maximum number of elements.
     pub const fn with_max_entries(max_entries: u32) -> Self {
@@ -164,3 +258,157 @@ impl<T> PerfMap<T> {
         self.0.insert_with_flags(ctx.inner(), data, flags)
     }
 }
+
+/// AF_XDP socket map.
+///
+/// Holds the AF_XDP sockets packets can be redirected to with `redirect`.
+/// The packets are written straight into memory shared with user-space
+/// instead of being copied through a `PerfMap`. Sockets are bound to a
+/// receive queue of an interface and register themselves in the map at the
+/// index of their queue, see
+/// [`redbpf::XskSocket`](../../redbpf/struct.XskSocket.html).
+///
+/// # Example
+///
+/// ```no_run
+/// #![no_std]
+/// #![no_main]
+/// use redbpf_probes::xdp::prelude::*;
+///
+/// program!(0xFFFFFFFE, "GPL");
+///
+/// #[map]
+/// static mut sockets: XskMap = XskMap::with_max_entries(64);
+///
+/// #[xdp]
+/// fn to_user_space(ctx: XdpContext) -> XdpResult {
+///     Ok(unsafe { sockets.redirect(ctx.rx_queue_index()) })
+/// }
+/// ```
+#[repr(transparent)]
+pub struct XskMap {
+    def: bpf_map_def,
+}
+
+impl MapBtf for XskMap {
+    type KeyType = u32;
+    type ValueType = u32;
+}
+
+impl XskMap {
+    /// Creates a socket map with the specified maximum number of sockets.
+    pub const fn with_max_entries(max_entries: u32) -> Self {
+        Self {
+            def: bpf_map_def {
+                type_: bpf_map_type_BPF_MAP_TYPE_XSKMAP,
+                key_size: mem::size_of::<u32>() as u32,
+                value_size: mem::size_of::<u32>() as u32,
+                max_entries,
+                map_flags: 0,
+            },
+        }
+    }
+
+    /// Redirects the packet to the socket bound to `queue_id`.
+    ///
+    /// Returns `XdpAction::Redirect`, or `XdpAction::Pass` if there's no
+    /// socket for `queue_id`. Kernels older than 5.3 don't check for the
+    /// socket up front and drop the packet instead.
+    #[inline]
+    pub fn redirect(&mut self, queue_id: u32) -> XdpAction {
+        redirect_map(&mut self.def, queue_id, xdp_action_XDP_PASS.into())
+    }
+}
+
+macro_rules! define_redirect_map {
+    ($(#[$attr:meta])* $name:ident, $map_type:expr) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        pub struct $name {
+            def: bpf_map_def,
+        }
+
+        impl MapBtf for $name {
+            type KeyType = u32;
+            type ValueType = u32;
+        }
+
+        impl $name {
+            /// Creates a map with the specified maximum number of entries.
+            pub const fn with_max_entries(max_entries: u32) -> Self {
+                Self {
+                    def: bpf_map_def {
+                        type_: $map_type,
+                        key_size: mem::size_of::<u32>() as u32,
+                        value_size: mem::size_of::<u32>() as u32,
+                        max_entries,
+                        map_flags: 0,
+                    },
+                }
+            }
+
+            /// Redirects the packet to the target at `key`.
+            ///
+            /// Returns `XdpAction::Redirect` on success. Since Linux 5.3, if
+            /// there's no entry for `key` the action in the lower two bits of
+            /// `flags` is returned instead, eg. passing
+            /// `xdp_action_XDP_PASS.into()` lets the packet through. Older
+            /// kernels ignore `flags` and drop the packet.
+            #[inline]
+            pub fn redirect(&mut self, key: u32, flags: u64) -> XdpAction {
+                redirect_map(&mut self.def, key, flags)
+            }
+        }
+    };
+}
+
+define_redirect_map!(
+    /// Device map.
+    ///
+    /// An array of network interface indexes packets can be redirected to
+    /// with `redirect`, which transmits them out of the interface. This is a
+    /// wrapper for `BPF_MAP_TYPE_DEVMAP`.
+    ///
+    /// To set the interfaces from user-space, see
+    /// [`redbpf::DevMap`](../../redbpf/struct.DevMap.html).
+    DevMap,
+    bpf_map_type_BPF_MAP_TYPE_DEVMAP
+);
+define_redirect_map!(
+    /// Hash table device map.
+    ///
+    /// Like `DevMap`, but the interfaces are stored by arbitrary keys instead
+    /// of array indexes, eg. by the interface index itself. This is a wrapper
+    /// for `BPF_MAP_TYPE_DEVMAP_HASH`, which requires Linux 5.4 or newer.
+    DevMapHash,
+    BPF_MAP_TYPE_DEVMAP_HASH
+);
+define_redirect_map!(
+    /// CPU map.
+    ///
+    /// Redirecting a packet with `redirect` queues it to be processed by
+    /// the network stack on another CPU, which allows spreading the load of
+    /// an interface over several CPUs. The map is indexed by CPU ID and holds
+    /// the size of the queue of each CPU. This is a wrapper for
+    /// `BPF_MAP_TYPE_CPUMAP`.
+    ///
+    /// To set the queue sizes from user-space, see
+    /// [`redbpf::CpuMap`](../../redbpf/struct.CpuMap.html).
+    CpuMap,
+    bpf_map_type_BPF_MAP_TYPE_CPUMAP
+);
+
+// Not present in the bindings generated from pre-5.4 kernel headers.
+const BPF_MAP_TYPE_DEVMAP_HASH: bpf_map_type = 25;
+
+#[inline]
+fn redirect_map(def: &mut bpf_map_def, key: u32, flags: u64) -> XdpAction {
+    let ret = unsafe { bpf_redirect_map(def as *mut _ as *mut c_void, key, flags) } as u32;
+    match ret {
+        xdp_action_XDP_DROP => XdpAction::Drop,
+        xdp_action_XDP_PASS => XdpAction::Pass,
+        xdp_action_XDP_TX => XdpAction::Tx,
+        xdp_action_XDP_REDIRECT => XdpAction::Redirect,
+        _ => XdpAction::Aborted,
+    }
+}
@@ -30,11 +30,14 @@ fn main() {
         // load the BPF programs and maps
         let mut loader = Loader::load(probe_code()).expect("error loading probe");
 
-        // attach the kprobes
+        // attach the kprobes, they stay attached as long as the links are alive
+        let mut links = Vec::new();
         for kprobe in loader.kprobes_mut() {
-            kprobe
-                .attach_kprobe(&kprobe.name(), 0)
-                .expect(&format!("error attaching program {}", kprobe.name()));
+            links.push(
+                kprobe
+                    .attach_kprobe(&kprobe.name(), 0)
+                    .expect(&format!("error attaching program {}", kprobe.name())),
+            );
         }
 
         tokio::spawn(async move {
@@ -0,0 +1,214 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Batched map operations
+//!
+//! The `BPF_MAP_*_BATCH` commands read or write many map entries with a
+//! single syscall, which is much faster than walking a large map one key at
+//! a time. They were added in Linux 5.6 and only some map types implement
+//! them, so when the kernel doesn't know the commands or the map type
+//! doesn't implement them, the functions here report it as unsupported and
+//! let the typed maps fall back to per-element operations.
+use std::io;
+use std::mem;
+use std::ptr::{null, null_mut};
+
+use libc::{EINVAL, ENOENT, EOPNOTSUPP};
+
+use crate::sys::bpf::{
+    map_batch, BPF_MAP_DELETE_BATCH, BPF_MAP_LOOKUP_AND_DELETE_BATCH, BPF_MAP_LOOKUP_BATCH,
+    BPF_MAP_UPDATE_BATCH,
+};
+use crate::{Error, Map, Result};
+
+// The kernel-internal error returned by map types without batch operations.
+const ENOTSUPP: i32 = 524;
+
+// The number of entries read by every `BPF_MAP_LOOKUP_BATCH` command.
+const LOOKUP_CHUNK: usize = 4096;
+
+lazy_static! {
+    static ref BATCH_SUPPORTED: bool = probe_batch_commands();
+}
+
+/// Entries read from a map by `lookup`.
+pub(crate) struct Entries {
+    keys: Vec<u8>,
+    values: Vec<u8>,
+    key_size: usize,
+    value_size: usize,
+    count: usize,
+}
+
+impl Entries {
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn key<K>(&self, i: usize) -> K {
+        assert!(i < self.count && mem::size_of::<K>() == self.key_size);
+        unsafe { (self.keys.as_ptr().add(i * self.key_size) as *const K).read_unaligned() }
+    }
+
+    pub(crate) fn value<V>(&self, i: usize) -> V {
+        assert!(mem::size_of::<V>() == self.value_size);
+        unsafe { (self.value_bytes(i).as_ptr() as *const V).read_unaligned() }
+    }
+
+    pub(crate) fn value_bytes(&self, i: usize) -> &[u8] {
+        assert!(i < self.count);
+        &self.values[i * self.value_size..(i + 1) * self.value_size]
+    }
+}
+
+/// Reads all the entries of `map`, deleting them if `delete` is set.
+///
+/// `value_size` is the size of a value as copied to user-space, which for
+/// per-CPU maps is the size of all the per-CPU values. Returns `None` if
+/// the kernel doesn't support batched lookups for the map.
+///
+/// When `delete` is set and a command fails after some entries were
+/// deleted, those entries are returned instead of the error.
+pub(crate) fn lookup(map: &Map, value_size: usize, delete: bool) -> Result<Option<Entries>> {
+    if !*BATCH_SUPPORTED {
+        return Ok(None);
+    }
+    let cmd = if delete {
+        BPF_MAP_LOOKUP_AND_DELETE_BATCH
+    } else {
+        BPF_MAP_LOOKUP_BATCH
+    };
+    let key_size = map.config.key_size as usize;
+    let max_entries = map.config.max_entries as usize;
+    // maps are often much emptier than `max_entries`, so the buffers grow as
+    // entries are read
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    // hash maps use a bucket index as batch token, other maps use a key
+    let mut batch = vec![0u8; key_size.max(mem::size_of::<u64>())];
+    let mut count = 0;
+    while count < max_entries {
+        // hash maps fail with ENOSPC if a bucket doesn't fit in the chunk,
+        // so don't shrink it when reading the last entries
+        let chunk = LOOKUP_CHUNK.min(max_entries);
+        keys.resize((count + chunk) * key_size, 0);
+        values.resize((count + chunk) * value_size, 0);
+        let in_batch = if count == 0 { null() } else { batch.as_ptr() };
+        let (n, ret) = map_batch(
+            cmd,
+            map.fd,
+            in_batch as *const _,
+            batch.as_mut_ptr() as *mut _,
+            keys[count * key_size..].as_mut_ptr() as *mut _,
+            values[count * value_size..].as_mut_ptr() as *mut _,
+            chunk as u32,
+        );
+        match ret {
+            Ok(()) if n > 0 => count += n as usize,
+            Ok(()) => break,
+            // ENOENT signals that there are no entries left
+            Err(e) if e.raw_os_error() == Some(ENOENT) => {
+                count += n as usize;
+                break;
+            }
+            Err(e) if count == 0 && is_unsupported(&e) => return Ok(None),
+            // the entries read so far are already gone from the map, so
+            // returning an error would lose them
+            Err(_) if delete && count + n as usize > 0 => {
+                count += n as usize;
+                break;
+            }
+            Err(_) => return Err(Error::Map),
+        }
+    }
+    keys.truncate(count * key_size);
+    values.truncate(count * value_size);
+
+    Ok(Some(Entries {
+        keys,
+        values,
+        key_size,
+        value_size,
+        count,
+    }))
+}
+
+/// Sets the values of `count` keys of `map`.
+///
+/// `keys` and `values` point to `count` keys and values laid out
+/// contiguously. Returns `false` if the kernel doesn't support batched
+/// updates for the map.
+pub(crate) fn update(map: &Map, keys: *const u8, values: *const u8, count: usize) -> Result<bool> {
+    if !*BATCH_SUPPORTED {
+        return Ok(false);
+    }
+    let (_, ret) = map_batch(
+        BPF_MAP_UPDATE_BATCH,
+        map.fd,
+        null(),
+        null_mut(),
+        keys as *mut _,
+        values as *mut _,
+        count as u32,
+    );
+    check_result(ret)
+}
+
+/// Deletes `count` keys of `map`.
+///
+/// Returns `false` if the kernel doesn't support batched deletes for the
+/// map.
+pub(crate) fn delete(map: &Map, keys: *const u8, count: usize) -> Result<bool> {
+    if !*BATCH_SUPPORTED {
+        return Ok(false);
+    }
+    let (_, ret) = map_batch(
+        BPF_MAP_DELETE_BATCH,
+        map.fd,
+        null(),
+        null_mut(),
+        keys as *mut _,
+        null_mut(),
+        count as u32,
+    );
+    check_result(ret)
+}
+
+fn check_result(ret: io::Result<()>) -> Result<bool> {
+    match ret {
+        Ok(()) => Ok(true),
+        Err(e) if is_unsupported(&e) => Ok(false),
+        Err(_) => Err(Error::Map),
+    }
+}
+
+/// Returns whether the error means that the map type doesn't implement the
+/// command, in which case no entry was processed.
+fn is_unsupported(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(EOPNOTSUPP) | Some(ENOTSUPP))
+}
+
+/// Returns whether the kernel knows the batch commands.
+///
+/// Kernels older than 5.6 fail unknown commands with `EINVAL`, which they
+/// also return for invalid arguments, so the commands are tried once without
+/// a map: kernels that know them fail with `EBADF` instead.
+fn probe_batch_commands() -> bool {
+    let (_, ret) = map_batch(
+        BPF_MAP_LOOKUP_BATCH,
+        -1,
+        null(),
+        null_mut(),
+        null_mut(),
+        null_mut(),
+        0,
+    );
+    match ret {
+        Err(e) => e.raw_os_error() != Some(EINVAL),
+        Ok(()) => true,
+    }
+}
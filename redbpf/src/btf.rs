@@ -0,0 +1,930 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! BPF Type Format handling.
+//!
+//! Probes built with `cargo bpf build --btf` carry the type information of
+//! their maps and programs in the `.BTF` section, and function and line
+//! information for their programs in the `.BTF.ext` section. The `.BTF`
+//! section is loaded in the kernel so that it can be referenced when creating
+//! maps and loading programs.
+//!
+//! The type information emitted by LLVM for rust code can't be loaded as is:
+//! rust type names such as `Option<u32>` aren't valid C identifiers, and
+//! `DATASEC` and `VAR` types are only understood by recent kernels. Before
+//! loading, names are sanitized and data sections are turned into plain
+//! structs, the same way libbpf does for older kernels.
+//!
+//! The type information of the running kernel is used to resolve CO-RE
+//! relocations: the accessors generated by
+//! `cargo_bpf::bindgen::generate_core_read_accessors` record the path of the
+//! fields they read in the `.redbpf.core` section, and use the address of
+//! each path as the offset of its field. Those addresses are relocated to the
+//! offsets of the fields in the running kernel.
+use goblin::elf::Elf;
+use std::collections::HashMap as RSHashMap;
+use std::fs;
+use std::os::unix::io::RawFd;
+
+use crate::sys::bpf::{bpf_btf_load_attr, sys_bpf, BPF_BTF_LOAD};
+use crate::{Error, Result};
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const BTF_HEADER_LEN: usize = 24;
+const BTF_EXT_HEADER_LEN: usize = 24;
+const BTF_TYPE_LEN: usize = 12;
+const KSYM_NAME_LEN: usize = 128;
+const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+const CORE_SECTION: &str = ".redbpf.core";
+
+const BTF_KIND_INT: u32 = 1;
+const BTF_KIND_ARRAY: u32 = 3;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_UNION: u32 = 5;
+const BTF_KIND_ENUM: u32 = 6;
+const BTF_KIND_TYPEDEF: u32 = 8;
+const BTF_KIND_VOLATILE: u32 = 9;
+const BTF_KIND_CONST: u32 = 10;
+const BTF_KIND_RESTRICT: u32 = 11;
+const BTF_KIND_FUNC: u32 = 12;
+const BTF_KIND_FUNC_PROTO: u32 = 13;
+const BTF_KIND_VAR: u32 = 14;
+const BTF_KIND_DATASEC: u32 = 15;
+const BTF_KIND_DECL_TAG: u32 = 17;
+const BTF_KIND_TYPE_TAG: u32 = 18;
+const BTF_KIND_ENUM64: u32 = 19;
+const BTF_KIND_MAX: u32 = 19;
+
+/// Type information loaded from the `.BTF` section of an ELF object.
+pub(crate) struct Btf {
+    data: Vec<u8>,
+    /// Offset of each type in `data`. Type ids start at 1, so the type with
+    /// id `n` is at `types[n - 1]`.
+    types: Vec<usize>,
+    strings: usize,
+    strings_len: usize,
+    fd: Option<RawFd>,
+}
+
+/// The function and line information of a program.
+pub(crate) struct ProgramBtf {
+    pub btf_fd: RawFd,
+    pub func_info_rec_size: u32,
+    pub func_info: Vec<u8>,
+    pub line_info_rec_size: u32,
+    pub line_info: Vec<u8>,
+}
+
+/// Function and line information loaded from the `.BTF.ext` section of an ELF
+/// object.
+pub(crate) struct BtfExt {
+    func_info: ExtInfo,
+    line_info: ExtInfo,
+}
+
+/// The records of one `.BTF.ext` subsection, indexed by program section.
+struct ExtInfo {
+    rec_size: u32,
+    sections: RSHashMap<String, Vec<u8>>,
+}
+
+/// The CO-RE relocations of an ELF object, resolved against the running
+/// kernel.
+pub(crate) struct CoreRelocations {
+    shndx: usize,
+    /// The offset of each field, indexed by the position of its path in the
+    /// `.redbpf.core` section. Fields that can't be found map to their path.
+    fields: RSHashMap<usize, std::result::Result<u32, String>>,
+}
+
+impl Btf {
+    /// Parses and sanitizes the `.BTF` section of `object`.
+    pub(crate) fn from_elf(object: &Elf, bytes: &[u8]) -> Result<Btf> {
+        let data = section_data(object, bytes, ".BTF")?;
+        let mut btf = Btf::parse(data)?;
+
+        let sections = object
+            .section_headers
+            .iter()
+            .filter_map(|shdr| {
+                let name = object.shdr_strtab.get_unsafe(shdr.sh_name)?;
+                Some((name.to_string(), shdr.sh_size as u32))
+            })
+            .collect();
+        let symbols = object
+            .syms
+            .iter()
+            .filter_map(|sym| {
+                let name = object.strtab.get_unsafe(sym.st_name)?;
+                Some((name.to_string(), sym.st_value as u32))
+            })
+            .collect();
+        btf.sanitize(&sections, &symbols);
+
+        Ok(btf)
+    }
+
+    /// Loads the type information of the running kernel.
+    pub(crate) fn kernel() -> Result<Btf> {
+        Btf::parse(&fs::read(VMLINUX_BTF)?)
+    }
+
+    fn parse(data: &[u8]) -> Result<Btf> {
+        let invalid = || Error::BTF("invalid BTF header".to_string());
+        if data.len() < BTF_HEADER_LEN || read_u16(data, 0) != BTF_MAGIC {
+            return Err(invalid());
+        }
+        let hdr_len = read_u32(data, 4) as usize;
+        let type_off = hdr_len + read_u32(data, 8) as usize;
+        let type_len = read_u32(data, 12) as usize;
+        let strings = hdr_len + read_u32(data, 16) as usize;
+        let strings_len = read_u32(data, 20) as usize;
+        if type_off + type_len > data.len() || strings + strings_len > data.len() {
+            return Err(invalid());
+        }
+
+        let mut types = Vec::new();
+        let mut offset = type_off;
+        while offset < type_off + type_len {
+            if offset + BTF_TYPE_LEN > type_off + type_len {
+                return Err(invalid());
+            }
+            let info = read_u32(data, offset + 4);
+            let extra = match extra_len(kind(info), vlen(info)) {
+                Some(len) => len,
+                None => return Err(Error::BTF(format!("unknown BTF kind {}", kind(info)))),
+            };
+            types.push(offset);
+            offset += BTF_TYPE_LEN + extra;
+        }
+
+        Ok(Btf {
+            data: data.to_vec(),
+            types,
+            strings,
+            strings_len,
+            fd: None,
+        })
+    }
+
+    /// Loads the type information in the kernel.
+    pub(crate) fn load(&mut self) -> Result<RawFd> {
+        if let Some(fd) = self.fd {
+            return Ok(fd);
+        }
+        let attr = bpf_btf_load_attr {
+            btf: self.data.as_ptr() as u64,
+            btf_size: self.data.len() as u32,
+            ..Default::default()
+        };
+        let fd = sys_bpf(BPF_BTF_LOAD, &attr)?;
+        self.fd = Some(fd);
+
+        Ok(fd)
+    }
+
+    pub(crate) fn fd(&self) -> Option<RawFd> {
+        self.fd
+    }
+
+    /// Returns the ids of the key and value types of the map called `name`.
+    ///
+    /// These are the types of the `key` and `value` fields of the
+    /// `____btf_map_<name>` struct generated by the `#[map]` attribute.
+    pub(crate) fn map_types(&self, name: &str) -> Option<(u32, u32)> {
+        let id = self.find_type(BTF_KIND_STRUCT, &format!("____btf_map_{}", name))?;
+        let offset = self.types[id as usize - 1];
+        let mut key = None;
+        let mut value = None;
+        for i in 0..vlen(self.info(id)) {
+            let member = offset + BTF_TYPE_LEN + i * 12;
+            match self.name(read_u32(&self.data, member)) {
+                Some("key") => key = Some(read_u32(&self.data, member + 4)),
+                Some("value") => value = Some(read_u32(&self.data, member + 4)),
+                _ => {}
+            }
+        }
+
+        Some((key?, value?))
+    }
+
+    /// Returns the id of the type of kind `kind` called `name`.
+    pub(crate) fn find_type(&self, kind_: u32, name: &str) -> Option<u32> {
+        (1..=self.types.len() as u32)
+            .find(|&id| kind(self.info(id)) == kind_ && self.name(self.name_off(id)) == Some(name))
+    }
+
+    /// Returns the id of the function called `name`.
+    pub(crate) fn find_function(&self, name: &str) -> Option<u32> {
+        self.find_type(BTF_KIND_FUNC, name)
+    }
+
+    /// Returns the id of the typedef called `name`.
+    pub(crate) fn find_typedef(&self, name: &str) -> Option<u32> {
+        self.find_type(BTF_KIND_TYPEDEF, name)
+    }
+
+    /// Returns the offset in bytes of the field at `path`.
+    ///
+    /// `path` is a struct or union name followed by field names, separated by
+    /// dots. Fields of anonymous members are looked up as if they were
+    /// members of the enclosing type.
+    pub(crate) fn field_offset(&self, path: &str) -> Option<u32> {
+        let mut names = path.split('.');
+        let name = names.next()?;
+        let mut id = (1..=self.types.len() as u32).find(|&id| {
+            let info = self.info(id);
+            matches!(kind(info), BTF_KIND_STRUCT | BTF_KIND_UNION)
+                && vlen(info) > 0
+                && self.name(self.name_off(id)) == Some(name)
+        })?;
+        let mut bits = 0;
+        for field in names {
+            // bindgen appends an underscore to fields named after rust keywords
+            let (member, offset) = self
+                .member(id, field)
+                .or_else(|| self.member(id, field.strip_suffix('_')?))?;
+            bits += offset;
+            id = self.skip_modifiers(member);
+        }
+
+        Some(bits / 8)
+    }
+
+    /// Returns the type and the offset in bits of the member called `name`.
+    fn member(&self, id: u32, name: &str) -> Option<(u32, u32)> {
+        let info = self.info(id);
+        if !matches!(kind(info), BTF_KIND_STRUCT | BTF_KIND_UNION) {
+            return None;
+        }
+        let offset = self.types[id as usize - 1];
+        for i in 0..vlen(info) {
+            let member = offset + BTF_TYPE_LEN + i * 12;
+            let ty = read_u32(&self.data, member + 4);
+            let mut bits = read_u32(&self.data, member + 8);
+            if info >> 31 == 1 {
+                // the upper 8 bits are the size of bitfields
+                bits &= 0xff_ffff;
+            }
+            match self.name(read_u32(&self.data, member)) {
+                Some(n) if n == name => return Some((ty, bits)),
+                Some(n) if n.is_empty() || n.starts_with("__bindgen_anon") => {
+                    if let Some((ty, inner)) = self.member(self.skip_modifiers(ty), name) {
+                        return Some((ty, bits + inner));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    /// Resolves typedefs and type qualifiers to the underlying type.
+    fn skip_modifiers(&self, mut id: u32) -> u32 {
+        while id != 0
+            && matches!(
+                kind(self.info(id)),
+                BTF_KIND_TYPEDEF
+                    | BTF_KIND_VOLATILE
+                    | BTF_KIND_CONST
+                    | BTF_KIND_RESTRICT
+                    | BTF_KIND_TYPE_TAG
+            )
+        {
+            id = read_u32(&self.data, self.types[id as usize - 1] + 8);
+        }
+
+        id
+    }
+
+    fn info(&self, id: u32) -> u32 {
+        read_u32(&self.data, self.types[id as usize - 1] + 4)
+    }
+
+    fn name_off(&self, id: u32) -> u32 {
+        read_u32(&self.data, self.types[id as usize - 1])
+    }
+
+    fn name(&self, offset: u32) -> Option<&str> {
+        let start = self.strings + offset as usize;
+        let end = self.strings + self.strings_len;
+        if start >= end {
+            return None;
+        }
+        let len = self.data[start..end].iter().position(|c| *c == 0)?;
+        std::str::from_utf8(&self.data[start..start + len]).ok()
+    }
+
+    /// Rewrites the types so they can be loaded by the kernel.
+    ///
+    /// `sections` and `symbols` are the sizes of the ELF sections and the
+    /// values of the ELF symbols, which LLVM leaves out of `DATASEC` types.
+    fn sanitize(&mut self, sections: &RSHashMap<String, u32>, symbols: &RSHashMap<String, u32>) {
+        let mut names = Vec::new();
+        for id in 1..=self.types.len() as u32 {
+            let offset = self.types[id as usize - 1];
+            let info = self.info(id);
+            names.push(self.name_off(id));
+            match kind(info) {
+                BTF_KIND_STRUCT | BTF_KIND_UNION => {
+                    for i in 0..vlen(info) {
+                        names.push(read_u32(&self.data, offset + BTF_TYPE_LEN + i * 12));
+                    }
+                }
+                BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => {
+                    for i in 0..vlen(info) {
+                        names.push(read_u32(&self.data, offset + BTF_TYPE_LEN + i * 8));
+                    }
+                }
+                BTF_KIND_FUNC => {
+                    // make functions static, global functions are verified
+                    // independently of their callers
+                    write_u32(&mut self.data, offset + 4, info & !0xffff);
+                }
+                BTF_KIND_DATASEC => self.datasec_to_struct(id, sections, symbols),
+                _ => {}
+            }
+        }
+
+        for id in 1..=self.types.len() as u32 {
+            if kind(self.info(id)) == BTF_KIND_VAR {
+                // VARs are only referenced by DATASECs, which are now structs
+                let offset = self.types[id as usize - 1];
+                write_u32(&mut self.data, offset + 4, BTF_KIND_INT << 24);
+                write_u32(&mut self.data, offset + 8, 1);
+                write_u32(&mut self.data, offset + BTF_TYPE_LEN, 8);
+            }
+        }
+
+        for name in names {
+            self.sanitize_name(name);
+        }
+    }
+
+    fn datasec_to_struct(
+        &mut self,
+        id: u32,
+        sections: &RSHashMap<String, u32>,
+        symbols: &RSHashMap<String, u32>,
+    ) {
+        let offset = self.types[id as usize - 1];
+        let info = self.info(id);
+        let mut size = self
+            .name(self.name_off(id))
+            .and_then(|name| sections.get(name))
+            .copied()
+            .unwrap_or(0);
+        for i in 0..vlen(info) {
+            let var_info = offset + BTF_TYPE_LEN + i * 12;
+            let var = read_u32(&self.data, var_info);
+            let var_name = self.name_off(var);
+            let var_offset = self
+                .name(var_name)
+                .and_then(|name| symbols.get(name))
+                .copied()
+                .unwrap_or_else(|| read_u32(&self.data, var_info + 4));
+            size = size.max(var_offset + 1);
+            // the member keeps pointing to the VAR, which becomes a 1 byte
+            // INT so that members never exceed the struct
+            write_u32(&mut self.data, var_info, var_name);
+            write_u32(&mut self.data, var_info + 4, var);
+            write_u32(&mut self.data, var_info + 8, var_offset * 8);
+        }
+        write_u32(
+            &mut self.data,
+            offset + 4,
+            BTF_KIND_STRUCT << 24 | vlen(info) as u32,
+        );
+        write_u32(&mut self.data, offset + 8, size);
+    }
+
+    /// Turns the string at `offset` into a valid C identifier.
+    fn sanitize_name(&mut self, offset: u32) {
+        let start = self.strings + offset as usize;
+        let end = self.strings + self.strings_len;
+        if offset == 0 || start >= end {
+            return;
+        }
+        for (i, c) in self.data[start..end].iter_mut().enumerate() {
+            if *c == 0 {
+                break;
+            }
+            if i == KSYM_NAME_LEN - 1 {
+                *c = 0;
+                break;
+            }
+            if !(c.is_ascii_alphanumeric() || *c == b'_') || (i == 0 && c.is_ascii_digit()) {
+                *c = b'_';
+            }
+        }
+    }
+}
+
+impl Drop for Btf {
+    fn drop(&mut self) {
+        if let Some(fd) = self.fd {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+impl BtfExt {
+    /// Parses the `.BTF.ext` section of `object`.
+    pub(crate) fn from_elf(object: &Elf, bytes: &[u8], btf: &Btf) -> Result<BtfExt> {
+        let data = section_data(object, bytes, ".BTF.ext")?;
+        if data.len() < BTF_EXT_HEADER_LEN || read_u16(data, 0) != BTF_MAGIC {
+            return Err(Error::BTF("invalid BTF.ext header".to_string()));
+        }
+        let hdr_len = read_u32(data, 4) as usize;
+        let func_info_off = hdr_len + read_u32(data, 8) as usize;
+        let func_info_len = read_u32(data, 12) as usize;
+        let line_info_off = hdr_len + read_u32(data, 16) as usize;
+        let line_info_len = read_u32(data, 20) as usize;
+
+        Ok(BtfExt {
+            func_info: ExtInfo::parse(data, func_info_off, func_info_len, btf)?,
+            line_info: ExtInfo::parse(data, line_info_off, line_info_len, btf)?,
+        })
+    }
+
+    /// Returns the function and line information of the program in `section`.
+    pub(crate) fn program(&self, section: &str, btf: &Btf) -> Option<ProgramBtf> {
+        let func_info = self.func_info.sections.get(section)?;
+        Some(ProgramBtf {
+            btf_fd: btf.fd()?,
+            func_info_rec_size: self.func_info.rec_size,
+            func_info: func_info.clone(),
+            line_info_rec_size: self.line_info.rec_size,
+            line_info: self
+                .line_info
+                .sections
+                .get(section)
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl ExtInfo {
+    fn parse(data: &[u8], offset: usize, len: usize, btf: &Btf) -> Result<ExtInfo> {
+        let invalid = || Error::BTF("invalid BTF.ext info".to_string());
+        let mut sections = RSHashMap::new();
+        if len == 0 {
+            return Ok(ExtInfo {
+                rec_size: 0,
+                sections,
+            });
+        }
+        if offset + len > data.len() || len < 4 {
+            return Err(invalid());
+        }
+
+        let end = offset + len;
+        let rec_size = read_u32(data, offset);
+        let mut offset = offset + 4;
+        while offset + 8 <= end {
+            let name = btf.name(read_u32(data, offset)).ok_or_else(invalid)?;
+            let count = read_u32(data, offset + 4) as usize;
+            let records_len = count * rec_size as usize;
+            offset += 8;
+            if rec_size < 4 || offset + records_len > end {
+                return Err(invalid());
+            }
+
+            // LLVM emits byte offsets, the kernel expects instruction indices
+            let mut records = data[offset..offset + records_len].to_vec();
+            for rec in records.chunks_mut(rec_size as usize) {
+                let insn_off = read_u32(rec, 0) / 8;
+                write_u32(rec, 0, insn_off);
+            }
+            sections.insert(name.to_string(), records);
+            offset += records_len;
+        }
+
+        Ok(ExtInfo { rec_size, sections })
+    }
+}
+
+impl CoreRelocations {
+    /// Returns whether `object` has CO-RE relocations.
+    pub(crate) fn in_elf(object: &Elf) -> bool {
+        section_index(object, CORE_SECTION).is_some()
+    }
+
+    /// Resolves the field paths recorded in the `.redbpf.core` section of
+    /// `object`.
+    ///
+    /// Fields are looked up in `kernel_btf`, the type information of the
+    /// running kernel or, if the kernel doesn't provide any, in the type
+    /// information of the object itself. Returns `None` if `object` has no
+    /// CO-RE relocations.
+    pub(crate) fn from_elf(
+        object: &Elf,
+        bytes: &[u8],
+        kernel_btf: Option<&Btf>,
+        btf: Option<&Btf>,
+    ) -> Result<Option<CoreRelocations>> {
+        let shndx = match section_index(object, CORE_SECTION) {
+            Some(shndx) => shndx,
+            None => return Ok(None),
+        };
+        let data = section_data(object, bytes, CORE_SECTION)?;
+        let target = kernel_btf
+            .or(btf)
+            .ok_or_else(|| Error::BTF("no BTF to resolve CO-RE relocations".to_string()))?;
+
+        Ok(Some(CoreRelocations::parse(shndx, data, target)))
+    }
+
+    /// Resolves the paths in `data`, the contents of the `.redbpf.core`
+    /// section, against `target`.
+    fn parse(shndx: usize, data: &[u8], target: &Btf) -> CoreRelocations {
+        let mut fields = RSHashMap::new();
+        let mut start = 0;
+        while start < data.len() {
+            let len = data[start..]
+                .iter()
+                .position(|c| *c == 0)
+                .unwrap_or(data.len() - start);
+            if len > 0 {
+                let path = String::from_utf8_lossy(&data[start..start + len]).into_owned();
+                fields.insert(start, target.field_offset(&path).ok_or(path));
+            }
+            start += len + 1;
+        }
+
+        CoreRelocations { shndx, fields }
+    }
+
+    /// Returns the index of the `.redbpf.core` section.
+    pub(crate) fn shndx(&self) -> usize {
+        self.shndx
+    }
+
+    /// Returns the offset of the field whose path is at `offset` in the
+    /// `.redbpf.core` section.
+    pub(crate) fn field_offset(&self, offset: usize) -> Result<u32> {
+        match self.fields.get(&offset) {
+            Some(Ok(field_offset)) => Ok(*field_offset),
+            Some(Err(path)) => Err(Error::BTF(format!("field `{}' not found", path))),
+            None => Err(Error::Reloc),
+        }
+    }
+}
+
+impl ProgramBtf {
+    pub(crate) fn func_info_cnt(&self) -> u32 {
+        (self.func_info.len() / self.func_info_rec_size as usize) as u32
+    }
+
+    pub(crate) fn line_info_cnt(&self) -> u32 {
+        if self.line_info_rec_size == 0 {
+            return 0;
+        }
+        (self.line_info.len() / self.line_info_rec_size as usize) as u32
+    }
+}
+
+fn section_index(object: &Elf, name: &str) -> Option<usize> {
+    object
+        .section_headers
+        .iter()
+        .position(|shdr| object.shdr_strtab.get_unsafe(shdr.sh_name) == Some(name))
+}
+
+fn section_data<'d>(object: &Elf, bytes: &'d [u8], name: &str) -> Result<&'d [u8]> {
+    let shdr = section_index(object, name)
+        .map(|shndx| &object.section_headers[shndx])
+        .ok_or_else(|| Error::Section(name.to_string()))?;
+    let start = shdr.sh_offset as usize;
+    let end = start + shdr.sh_size as usize;
+    bytes
+        .get(start..end)
+        .ok_or_else(|| Error::Section(name.to_string()))
+}
+
+fn kind(info: u32) -> u32 {
+    (info >> 24) & 0x1f
+}
+
+fn vlen(info: u32) -> usize {
+    (info & 0xffff) as usize
+}
+
+/// Returns the size of the data following a type of kind `kind`.
+fn extra_len(kind: u32, vlen: usize) -> Option<usize> {
+    Some(match kind {
+        BTF_KIND_INT => 4,
+        BTF_KIND_ARRAY => 12,
+        BTF_KIND_STRUCT | BTF_KIND_UNION => vlen * 12,
+        BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => vlen * 8,
+        BTF_KIND_VAR | BTF_KIND_DECL_TAG => 4,
+        BTF_KIND_DATASEC | BTF_KIND_ENUM64 => vlen * 12,
+        k if k <= BTF_KIND_MAX => 0,
+        _ => return None,
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    let mut bytes = [0u8; 2];
+    bytes.copy_from_slice(&data[offset..offset + 2]);
+    u16::from_ne_bytes(bytes)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_ne_bytes(bytes)
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Program, RelocationInfo};
+    use goblin::elf::Sym;
+
+    const BTF_KIND_FLAG: u32 = 1 << 31;
+
+    /// Encodes type information the way LLVM does.
+    struct Builder {
+        types: Vec<u8>,
+        strings: Vec<u8>,
+        count: u32,
+    }
+
+    impl Builder {
+        fn new() -> Builder {
+            Builder {
+                types: Vec::new(),
+                strings: vec![0],
+                count: 0,
+            }
+        }
+
+        fn string(&mut self, s: &str) -> u32 {
+            if s.is_empty() {
+                return 0;
+            }
+            let offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(s.as_bytes());
+            self.strings.push(0);
+            offset
+        }
+
+        /// Adds a type and returns its id.
+        fn push(&mut self, name: &str, info: u32, size_or_type: u32, extra: &[u32]) -> u32 {
+            let name = self.string(name);
+            for value in [name, info, size_or_type].iter().chain(extra) {
+                self.types.extend_from_slice(&value.to_ne_bytes());
+            }
+            self.count += 1;
+            self.count
+        }
+
+        fn int(&mut self, name: &str, size: u32) -> u32 {
+            self.push(name, BTF_KIND_INT << 24, size, &[size * 8])
+        }
+
+        /// Adds a struct or union with `members`, each a name, a
+        /// type and an offset.
+        fn composite(
+            &mut self,
+            info: u32,
+            name: &str,
+            size: u32,
+            members: &[(&str, u32, u32)],
+        ) -> u32 {
+            let mut extra = Vec::new();
+            for (name, ty, offset) in members {
+                extra.push(self.string(name));
+                extra.push(*ty);
+                extra.push(*offset);
+            }
+            self.push(name, info | members.len() as u32, size, &extra)
+        }
+
+        fn build(&self) -> Vec<u8> {
+            let mut data = Vec::new();
+            data.extend_from_slice(&BTF_MAGIC.to_ne_bytes());
+            data.extend_from_slice(&[1, 0]);
+            for value in &[
+                BTF_HEADER_LEN as u32,
+                0,
+                self.types.len() as u32,
+                self.types.len() as u32,
+                self.strings.len() as u32,
+            ] {
+                data.extend_from_slice(&value.to_ne_bytes());
+            }
+            data.extend_from_slice(&self.types);
+            data.extend_from_slice(&self.strings);
+            data
+        }
+    }
+
+    /// A `task_struct` with a field in an anonymous union, a field behind a
+    /// const typedef and a bitfield.
+    fn kernel_btf() -> Btf {
+        let mut b = Builder::new();
+        let int = b.int("int", 4);
+        // forward declarations have no members
+        b.composite(BTF_KIND_STRUCT << 24, "task_struct", 0, &[]);
+        let anon = b.composite(
+            BTF_KIND_UNION << 24,
+            "",
+            4,
+            &[("tgid", int, 0), ("id", int, 0)],
+        );
+        let flags = b.composite(
+            BTF_KIND_STRUCT << 24 | BTF_KIND_FLAG,
+            "flags",
+            8,
+            &[("lo", int, 1 << 24), ("type", int, 3 << 24 | 40)],
+        );
+        let typedef = b.push("flags_t", BTF_KIND_TYPEDEF << 24, flags, &[]);
+        let konst = b.push("", BTF_KIND_CONST << 24, typedef, &[]);
+        b.composite(
+            BTF_KIND_STRUCT << 24,
+            "task_struct",
+            24,
+            &[
+                ("state", int, 0),
+                ("pid", int, 32),
+                ("", anon, 64),
+                ("flags", konst, 128),
+            ],
+        );
+        Btf::parse(&b.build()).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut b = Builder::new();
+        b.int("u32", 4);
+        let data = b.build();
+        let btf = Btf::parse(&data).unwrap();
+        assert_eq!(btf.types, vec![BTF_HEADER_LEN]);
+        assert_eq!(btf.name(btf.name_off(1)), Some("u32"));
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] ^= 0xff;
+        assert!(Btf::parse(&bad_magic).is_err());
+        // the type section is cut in the middle of the INT
+        assert!(Btf::parse(&data[..BTF_HEADER_LEN + 8]).is_err());
+
+        let mut b = Builder::new();
+        b.push("", 31 << 24, 0, &[]);
+        assert!(matches!(Btf::parse(&b.build()), Err(Error::BTF(_))));
+    }
+
+    #[test]
+    fn test_map_types() {
+        // the struct generated by `#[map]` for a `HashMap<u32, u64>`
+        let mut b = Builder::new();
+        let key = b.int("u32", 4);
+        let value = b.int("u64", 8);
+        b.composite(
+            BTF_KIND_STRUCT << 24,
+            "____btf_map_counts",
+            16,
+            &[("key", key, 0), ("value", value, 64)],
+        );
+        b.composite(
+            BTF_KIND_STRUCT << 24,
+            "____btf_map_keyless",
+            8,
+            &[("value", value, 0)],
+        );
+        let btf = Btf::parse(&b.build()).unwrap();
+
+        assert_eq!(btf.map_types("counts"), Some((key, value)));
+        assert_eq!(btf.map_types("keyless"), None);
+        assert_eq!(btf.map_types("missing"), None);
+    }
+
+    #[test]
+    fn test_field_offset() {
+        let btf = kernel_btf();
+        assert_eq!(btf.field_offset("task_struct.pid"), Some(4));
+        // members of anonymous unions belong to the enclosing struct
+        assert_eq!(btf.field_offset("task_struct.tgid"), Some(8));
+        // the typedef and const are skipped, and the bitfield size masked
+        assert_eq!(btf.field_offset("task_struct.flags.type"), Some(16 + 5));
+        // as generated by bindgen for fields named after keywords
+        assert_eq!(btf.field_offset("task_struct.flags.type_"), Some(16 + 5));
+        assert_eq!(btf.field_offset("task_struct.comm"), None);
+        assert_eq!(btf.field_offset("task_struct.pid.value"), None);
+        assert_eq!(btf.field_offset("mm_struct.pid"), None);
+    }
+
+    #[test]
+    fn test_core_relocations() {
+        let btf = kernel_btf();
+        let data = b"task_struct.pid\0task_struct.comm\0\0task_struct.tgid\0";
+        let core = CoreRelocations::parse(7, data, &btf);
+        assert_eq!(core.shndx(), 7);
+        assert!(matches!(core.field_offset(0), Ok(4)));
+        assert!(matches!(core.field_offset(16), Err(Error::BTF(_))));
+        assert!(matches!(core.field_offset(34), Ok(8)));
+        // not the start of a path
+        assert!(matches!(core.field_offset(1), Err(Error::Reloc)));
+    }
+
+    #[test]
+    fn test_apply_core() {
+        let btf = kernel_btf();
+        let core = CoreRelocations::parse(7, b"task_struct.comm\0task_struct.pid\0", &btf);
+        // r1 = .redbpf.core + 17 ll; exit
+        let mut code = vec![0x18, 0x01, 0, 0, 17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        code.extend_from_slice(&[0x95, 0, 0, 0, 0, 0, 0, 0]);
+        let mut programs = RSHashMap::new();
+        programs.insert(3, Program::new("kprobe", "probe", &code).unwrap());
+        let symtab = [Sym {
+            st_shndx: 7,
+            ..Default::default()
+        }];
+        let rel = RelocationInfo {
+            target_sec_idx: 3,
+            offset: 0,
+            sym_idx: 0,
+        };
+
+        assert!(rel.apply_core(&mut programs, &core, &symtab).is_ok());
+        let code = &programs[&3].data().code;
+        assert_eq!(code[0].imm, 4);
+        assert_eq!(code[1].imm, 0);
+
+        // the immediate is the offset of the path, so must match it exactly
+        programs.insert(
+            3,
+            Program::new("kprobe", "probe", &code_with_imm(16))
+                .ok()
+                .unwrap(),
+        );
+        assert!(matches!(
+            rel.apply_core(&mut programs, &core, &symtab),
+            Err(Error::Reloc)
+        ));
+        programs.insert(
+            3,
+            Program::new("kprobe", "probe", &code_with_imm(0))
+                .ok()
+                .unwrap(),
+        );
+        assert!(matches!(
+            rel.apply_core(&mut programs, &core, &symtab),
+            Err(Error::BTF(_))
+        ));
+    }
+
+    fn code_with_imm(imm: i32) -> Vec<u8> {
+        let mut code = vec![0x18, 0x01, 0, 0];
+        code.extend_from_slice(&imm.to_ne_bytes());
+        code.extend_from_slice(&[0; 16]);
+        code
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let mut b = Builder::new();
+        let int = b.int("u32", 4);
+        let var = b.push("COUNT", BTF_KIND_VAR << 24, int, &[1]);
+        // LLVM leaves out the offsets of the variables and the size of the
+        // section
+        let datasec = b.push(".bss", BTF_KIND_DATASEC << 24 | 1, 0, &[var, 0, 4]);
+        let option = b.composite(
+            BTF_KIND_STRUCT << 24,
+            "Option<u32>",
+            4,
+            &[("core::v", int, 0)],
+        );
+        // a global function
+        let func = b.push("probe", BTF_KIND_FUNC << 24 | 1, 0, &[]);
+        let mut btf = Btf::parse(&b.build()).unwrap();
+
+        let sections = vec![(".bss".to_string(), 16)].into_iter().collect();
+        let symbols = vec![("COUNT".to_string(), 8)].into_iter().collect();
+        btf.sanitize(&sections, &symbols);
+
+        assert_eq!(btf.name(btf.name_off(option)), Some("Option_u32_"));
+        assert_eq!(btf.member(option, "core__v"), Some((int, 0)));
+        assert_eq!(btf.info(func), BTF_KIND_FUNC << 24);
+        // the data section is a struct with a member for each variable
+        assert_eq!(btf.find_type(BTF_KIND_STRUCT, "_bss"), Some(datasec));
+        assert_eq!(btf.info(datasec), BTF_KIND_STRUCT << 24 | 1);
+        assert_eq!(read_u32(&btf.data, btf.types[datasec as usize - 1] + 8), 16);
+        assert_eq!(btf.member(datasec, "COUNT"), Some((var, 64)));
+        // variables are 1 byte INTs
+        assert_eq!(btf.info(var), BTF_KIND_INT << 24);
+        assert_eq!(read_u32(&btf.data, btf.types[var as usize - 1] + 8), 1);
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! cgroup attachment.
+//!
+//! `cgroup_skb`, `cgroup_sock` and `cgroup_sock_addr` programs are attached
+//! to a cgroup v2 directory, and run for the sockets of all the processes in
+//! that cgroup and its descendants.
+use crate::sys::bpf::*;
+
+/// Flags controlling how programs attached to a cgroup combine with the
+/// programs attached to its descendants.
+#[derive(Debug, Clone, Copy)]
+#[repr(u32)]
+pub enum Flags {
+    /// Only one program can be attached, and descendants can't override it.
+    Unset = 0,
+    /// Programs attached to descendants replace this one.
+    AllowOverride = BPF_F_ALLOW_OVERRIDE,
+    /// More programs can be attached to this cgroup and its descendants, and
+    /// all of them are run.
+    AllowMulti = BPF_F_ALLOW_MULTI,
+}
+
+impl Default for Flags {
+    fn default() -> Self {
+        Flags::Unset
+    }
+}
+
+/// Returns the attach type of a `kind/attach_point/name` program section.
+pub(crate) fn attach_type(kind: &str, attach_point: &str) -> Option<u32> {
+    Some(match (kind, attach_point) {
+        ("cgroup_skb", "ingress") => BPF_CGROUP_INET_INGRESS,
+        ("cgroup_skb", "egress") => BPF_CGROUP_INET_EGRESS,
+        ("cgroup_sock", "sock_create") => BPF_CGROUP_INET_SOCK_CREATE,
+        ("cgroup_sock", "sock_release") => BPF_CGROUP_INET_SOCK_RELEASE,
+        ("cgroup_sock", "post_bind4") => BPF_CGROUP_INET4_POST_BIND,
+        ("cgroup_sock", "post_bind6") => BPF_CGROUP_INET6_POST_BIND,
+        ("cgroup_sock_addr", "bind4") => BPF_CGROUP_INET4_BIND,
+        ("cgroup_sock_addr", "bind6") => BPF_CGROUP_INET6_BIND,
+        ("cgroup_sock_addr", "connect4") => BPF_CGROUP_INET4_CONNECT,
+        ("cgroup_sock_addr", "connect6") => BPF_CGROUP_INET6_CONNECT,
+        ("cgroup_sock_addr", "sendmsg4") => BPF_CGROUP_UDP4_SENDMSG,
+        ("cgroup_sock_addr", "sendmsg6") => BPF_CGROUP_UDP6_SENDMSG,
+        ("cgroup_sock_addr", "recvmsg4") => BPF_CGROUP_UDP4_RECVMSG,
+        ("cgroup_sock_addr", "recvmsg6") => BPF_CGROUP_UDP6_RECVMSG,
+        ("cgroup_sock_addr", "getpeername4") => BPF_CGROUP_INET4_GETPEERNAME,
+        ("cgroup_sock_addr", "getpeername6") => BPF_CGROUP_INET6_GETPEERNAME,
+        ("cgroup_sock_addr", "getsockname4") => BPF_CGROUP_INET4_GETSOCKNAME,
+        ("cgroup_sock_addr", "getsockname6") => BPF_CGROUP_INET6_GETSOCKNAME,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attach_type() {
+        assert_eq!(
+            attach_type("cgroup_skb", "ingress"),
+            Some(BPF_CGROUP_INET_INGRESS)
+        );
+        assert_eq!(
+            attach_type("cgroup_skb", "egress"),
+            Some(BPF_CGROUP_INET_EGRESS)
+        );
+        assert_eq!(
+            attach_type("cgroup_sock", "sock_create"),
+            Some(BPF_CGROUP_INET_SOCK_CREATE)
+        );
+        assert_eq!(
+            attach_type("cgroup_sock_addr", "connect4"),
+            Some(BPF_CGROUP_INET4_CONNECT)
+        );
+        assert_eq!(
+            attach_type("cgroup_sock_addr", "recvmsg6"),
+            Some(BPF_CGROUP_UDP6_RECVMSG)
+        );
+        // attach points belong to a single kind of program
+        assert_eq!(attach_type("cgroup_skb", "connect4"), None);
+        assert_eq!(attach_type("cgroup_sock_addr", "ingress"), None);
+        assert_eq!(attach_type("cgroup_sock", "unknown"), None);
+    }
+}
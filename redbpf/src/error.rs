@@ -9,7 +9,21 @@
 pub enum Error {
     StringConversion,
     BPF,
+    BTF(String),
+    /// The verifier rejected `program`, see `log` for the reason.
+    Verifier {
+        program: String,
+        log: String,
+    },
     Map,
+    /// The map pinned for `map` doesn't match its definition in the ELF
+    /// object: its `field` is `pinned` instead of `expected`.
+    PinnedMapMismatch {
+        map: String,
+        field: &'static str,
+        pinned: u32,
+        expected: u32,
+    },
     Section(String),
     Parse(::goblin::error::Error),
     KernelRelease(String),
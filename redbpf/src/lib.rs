@@ -31,10 +31,14 @@ use redbpf::load::Loader;
 let mut loader = Loader::load_file("iotop.elf").expect("error loading probe");
 
 // attach all the kprobes defined in iotop.elf
+// the kprobes stay attached as long as the links are alive
+let mut links = Vec::new();
 for kprobe in loader.kprobes_mut() {
-    kprobe
-        .attach_kprobe(&kprobe.name(), 0)
-        .expect(&format!("error attaching program {}", kprobe.name()));
+    links.push(
+        kprobe
+            .attach_kprobe(&kprobe.name(), 0)
+            .expect(&format!("error attaching program {}", kprobe.name())),
+    );
 }
 ```
 */
@@ -44,14 +48,20 @@ for kprobe in loader.kprobes_mut() {
 #[macro_use]
 extern crate lazy_static;
 
+mod batch;
+mod btf;
+pub mod cgroup;
 pub mod cpus;
 mod error;
 #[cfg(feature = "load")]
 pub mod load;
 mod perf;
+mod ringbuf;
 mod symbols;
 pub mod sys;
+pub mod tc;
 pub mod xdp;
+mod xsk;
 
 pub use bpf_sys::uname;
 use bpf_sys::{
@@ -59,21 +69,53 @@ use bpf_sys::{
     bpf_probe_attach_type_BPF_PROBE_RETURN, bpf_prog_type,
 };
 use goblin::elf::{reloc::RelocSection, section_header as hdr, Elf, SectionHeader, Sym};
+use ipnet::{Ipv4Net, Ipv6Net};
 
-use libc::pid_t;
-use std::collections::HashMap as RSHashMap;
-use std::ffi::CString;
+use libc::{pid_t, E2BIG, EINVAL, ENOENT};
+use std::collections::{HashMap as RSHashMap, HashSet};
+use std::ffi::{CStr, CString};
 use std::fs;
 use std::io;
 use std::marker::PhantomData;
 use std::mem;
 use std::mem::MaybeUninit;
-use std::os::unix::io::RawFd;
-
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::btf::{Btf, BtfExt, CoreRelocations, ProgramBtf};
+use crate::cpus::CpuId;
 pub use crate::error::{Error, Result};
 pub use crate::perf::*;
+pub use crate::ringbuf::*;
 use crate::symbols::*;
+use crate::sys::bpf::{
+    bpf_map_create_attr, bpf_prog_load_attr, map_info, map_peek_elem, map_pop_elem, map_push_elem,
+    obj_get, obj_name, obj_pin, prog_attach, prog_detach, prog_test_run, raw_tracepoint_open,
+    sys_bpf, BPF_CGROUP_SOCK_OPS, BPF_EXIST, BPF_LSM_MAC, BPF_MAP_CREATE, BPF_PROG_LOAD,
+    BPF_PROG_TYPE_CGROUP_SKB, BPF_PROG_TYPE_CGROUP_SOCK, BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+    BPF_PROG_TYPE_LSM, BPF_PROG_TYPE_RAW_TRACEPOINT, BPF_PROG_TYPE_SK_MSG, BPF_PROG_TYPE_SK_SKB,
+    BPF_PROG_TYPE_SOCK_OPS, BPF_PROG_TYPE_TRACING, BPF_SK_MSG_VERDICT, BPF_SK_SKB_STREAM_PARSER,
+    BPF_SK_SKB_STREAM_VERDICT, BPF_TRACE_FENTRY, BPF_TRACE_FEXIT, BPF_TRACE_RAW_TP,
+};
 use crate::uname::get_kernel_internal_version;
+pub use crate::xsk::*;
+
+/// The directory maps declared with `#[map(pinning = "by_name")]` are pinned
+/// to.
+pub const BPF_FS_PATH: &str = "/sys/fs/bpf";
+
+/// The prefix of the symbols generated by `#[map(pinning = "by_name")]`.
+const PIN_MAP_PREFIX: &str = "____pin_map_";
+
+/// The prefix of the symbols generated by `#[map(inner_map = "...")]`. The
+/// symbol holds the name of the inner map template.
+const INNER_MAP_PREFIX: &str = "____inner_map_";
+
+/// The extra room given to `Program::test_run()` for the output packet.
+const TEST_RUN_HEADROOM: usize = 4096;
 
 #[cfg(target_arch = "aarch64")]
 pub type DataPtr = *const u8;
@@ -90,6 +132,17 @@ pub struct Module {
     pub maps: Vec<Map>,
     pub license: String,
     pub version: u32,
+    /// Why type information was dropped while parsing the module.
+    ///
+    /// BTF is optional: if the BTF of the probe can't be loaded, or the
+    /// kernel rejects the BTF types of a map, the programs and maps are
+    /// loaded without it and the reason is recorded here. So is the reason
+    /// the type information of the running kernel couldn't be loaded, when
+    /// it's needed for CO-RE relocations or attach targets.
+    pub btf_errors: Vec<Error>,
+    // referenced by the programs until they're loaded
+    #[allow(dead_code)]
+    btf: Option<Btf>,
 }
 /// A BPF program defined in a [Module](struct.Module.html).
 pub enum Program {
@@ -100,12 +153,57 @@ pub enum Program {
     SocketFilter(SocketFilter),
     TracePoint(TracePoint),
     XDP(XDP),
+    TcAction(TcAction),
+    RawTracePoint(RawTracePoint),
+    BtfTracePoint(BtfTracePoint),
+    FEntry(FEntry),
+    FExit(FEntry),
+    CgroupSkb(Cgroup),
+    CgroupSock(Cgroup),
+    CgroupSockAddr(Cgroup),
+    SockOps(Cgroup),
+    SkMsg(SkMsg),
+    SkSkb(SkSkb),
+    PerfEvent(PerfEvent),
+    Lsm(Lsm),
 }
 
 struct ProgramData {
     pub name: String,
     code: Vec<bpf_insn>,
     fd: Option<RawFd>,
+    btf: Option<ProgramBtf>,
+    verifier_log: VerifierLog,
+    btf_error: Option<Error>,
+    // the id of the kernel symbol the program attaches to, looked up when
+    // the module is parsed
+    attach_btf_id: Option<u32>,
+}
+
+/// Settings for the log of the BPF verifier.
+///
+/// When a program is rejected, the log is returned in
+/// `Error::Verifier`. See `Program::set_verifier_log()`.
+#[derive(Debug, Clone, Copy)]
+pub struct VerifierLog {
+    /// The verbosity of the log.
+    ///
+    /// `1` logs the instructions up to the one that was rejected, `2` logs
+    /// every instruction the verifier checks. With `0`, the program is
+    /// loaded again with `1` when it's rejected.
+    pub level: u32,
+    /// The size of the log buffer in bytes. Logs that don't fit are
+    /// truncated.
+    pub size: usize,
+}
+
+impl Default for VerifierLog {
+    fn default() -> VerifierLog {
+        VerifierLog {
+            level: 0,
+            size: 64 * 65535,
+        }
+    }
 }
 
 /// Type to work with `kprobes` or `kretprobes`.
@@ -125,15 +223,141 @@ pub struct SocketFilter {
     common: ProgramData,
 }
 
+/// Type to work with `tracepoints`.
 pub struct TracePoint {
     common: ProgramData,
 }
+
 /// Type to work with `XDP` programs.
 pub struct XDP {
     common: ProgramData,
     interfaces: Vec<String>,
 }
 
+/// Type to work with `tc` programs.
+///
+/// `tc_action` programs are loaded as `BPF_PROG_TYPE_SCHED_CLS` and attached
+/// as direct-action filters, so their return value is used as the `tc`
+/// action.
+pub struct TcAction {
+    common: ProgramData,
+    filters: Vec<tc::Filter>,
+}
+
+/// Type to work with raw tracepoints.
+pub struct RawTracePoint {
+    common: ProgramData,
+}
+
+/// Type to work with BTF tracepoints (`tp_btf`).
+///
+/// The tracepoint is looked up in `/sys/kernel/btf/vmlinux` when the module
+/// is parsed.
+pub struct BtfTracePoint {
+    common: ProgramData,
+}
+
+/// Type to work with `fentry` or `fexit` programs.
+///
+/// The kernel function is looked up in `/sys/kernel/btf/vmlinux` when the
+/// program is loaded.
+pub struct FEntry {
+    common: ProgramData,
+}
+
+/// Type to work with `cgroup_skb`, `cgroup_sock`, `cgroup_sock_addr` or
+/// `sock_ops` programs.
+///
+/// The attach point, like `ingress` or `connect4`, is set in the probe code
+/// and the program is loaded for it.
+pub struct Cgroup {
+    common: ProgramData,
+    attach_type: u32,
+}
+
+/// Type to work with `sk_msg` programs.
+///
+/// `sk_msg` programs are attached to a `SOCKMAP` or `SOCKHASH` map, and run
+/// for every message sent on the sockets in the map.
+pub struct SkMsg {
+    common: ProgramData,
+}
+
+/// Type to work with `sk_skb` programs.
+///
+/// `sk_skb` programs are attached to a `SOCKMAP` or `SOCKHASH` map, and run
+/// for the data received on the sockets in the map, either to split it into
+/// messages (`stream_parser`) or to decide what to do with each message
+/// (`stream_verdict`).
+pub struct SkSkb {
+    common: ProgramData,
+    attach_type: u32,
+}
+
+/// Type to work with `perf_event` programs.
+///
+/// `perf_event` programs are attached to perf events, and run every time the
+/// event is sampled.
+pub struct PerfEvent {
+    common: ProgramData,
+}
+
+/// Type to work with BPF LSM programs.
+///
+/// The LSM hook, like `file_open`, is the name of the program. Its
+/// `bpf_lsm_` function is looked up in `/sys/kernel/btf/vmlinux` when the
+/// program is loaded.
+pub struct Lsm {
+    common: ProgramData,
+}
+
+/// An attached `kprobe`, `uprobe`, `tracepoint`, raw tracepoint, BTF
+/// tracepoint, `fentry`, `fexit`, cgroup, `sk_msg`, `sk_skb`, `perf_event` or
+/// LSM program.
+///
+/// Returned by `KProbe::attach_kprobe`, `UProbe::attach_uprobe`,
+/// `TracePoint::attach_trace_point`, `RawTracePoint::attach_raw_trace_point`,
+/// `BtfTracePoint::attach_btf_trace_point`, `FEntry::attach_fentry`, `Cgroup::attach_cgroup`,
+/// `SkMsg::attach_sockmap`, `SkSkb::attach_sockmap`,
+/// `PerfEvent::attach_sampling` and `Lsm::attach_lsm`. The program is
+/// detached when the link is dropped, or explicitly with `detach()`.
+#[must_use = "the program is detached when the link is dropped"]
+pub struct Link {
+    pfd: RawFd,
+    kind: LinkKind,
+}
+
+enum LinkKind {
+    KProbe(CString),
+    UProbe(CString),
+    TracePoint,
+    PerfEvent,
+    // also used by BTF tracepoints, fentry, fexit and LSM programs, closing
+    // the fd detaches them
+    RawTracePoint,
+    // attached with BPF_PROG_ATTACH, the fd is the cgroup's or a duplicate
+    // of the map's
+    ProgAttach { prog_fd: RawFd, attach_type: u32 },
+}
+
+/// The result of running a program with `Program::test_run()`.
+#[derive(Debug, Clone)]
+pub struct TestRun {
+    /// The value returned by the program.
+    pub retval: u32,
+    /// The packet, as modified by the program.
+    pub data: Vec<u8>,
+    /// The average duration of a run.
+    pub duration: Duration,
+}
+
+impl TestRun {
+    /// Returns the action returned by an XDP program.
+    pub fn xdp_action(&self) -> Option<xdp::Action> {
+        xdp::Action::from_u32(self.retval)
+    }
+}
+
 pub struct Map {
     pub name: String,
     pub kind: u32,
@@ -142,12 +366,50 @@ pub struct Map {
     section_data: bool,
 }
 
+/// Hash table map.
+///
+/// This works with `BPF_MAP_TYPE_LRU_HASH` maps too, see
+/// [`redbpf_probes::maps::LruHashMap`](../../redbpf_probes/maps/struct.LruHashMap.html).
 pub struct HashMap<'a, K: Clone, V: Clone> {
     base: &'a Map,
     _k: PhantomData<K>,
     _v: PhantomData<V>,
 }
 
+/// Array map.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::Array`](../../redbpf_probes/maps/struct.Array.html).
+pub struct Array<'a, T: Clone> {
+    base: &'a Map,
+    _element: PhantomData<T>,
+}
+
+/// Per-CPU array map.
+///
+/// Every element holds one value for each possible CPU.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::PerCpuArray`](../../redbpf_probes/maps/struct.PerCpuArray.html).
+pub struct PerCpuArray<'a, T: Clone> {
+    base: &'a Map,
+    cpus: usize,
+    _element: PhantomData<T>,
+}
+
+/// Per-CPU hash table map.
+///
+/// Every key maps to one value for each possible CPU.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::PerCpuHashMap`](../../redbpf_probes/maps/struct.PerCpuHashMap.html).
+pub struct PerCpuHashMap<'a, K: Clone, V: Clone> {
+    base: &'a Map,
+    cpus: usize,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
 pub struct StackTrace<'a> {
     base: &'a Map,
 }
@@ -170,6 +432,133 @@ pub struct ProgramArray<'a> {
     base: &'a Map,
 }
 
+/// Array of maps.
+///
+/// The maps stored in the array must have the same type, key and value
+/// sizes as the inner map template the array was declared with. Replacing a
+/// map with `set` is atomic for the eBPF programs using the array.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::ArrayOfMaps`](../../redbpf_probes/maps/struct.ArrayOfMaps.html).
+pub struct ArrayOfMaps<'a> {
+    base: &'a Map,
+}
+
+/// Hash table of maps.
+///
+/// Like `ArrayOfMaps`, but the maps are stored by a key of type `K`.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::HashOfMaps`](../../redbpf_probes/maps/struct.HashOfMaps.html).
+pub struct HashOfMaps<'a, K: Clone> {
+    base: &'a Map,
+    _k: PhantomData<K>,
+}
+
+/// FIFO queue map.
+///
+/// Elements pushed by eBPF programs can be popped from user-space in the same
+/// order, and vice versa.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::Queue`](../../redbpf_probes/maps/struct.Queue.html).
+pub struct Queue<'a, T: Clone> {
+    base: &'a Map,
+    _element: PhantomData<T>,
+}
+
+/// LIFO stack map.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::Stack`](../../redbpf_probes/maps/struct.Stack.html).
+pub struct Stack<'a, T: Clone> {
+    base: &'a Map,
+    _element: PhantomData<T>,
+}
+
+/// The `BPF_MAP_TYPE_BLOOM_FILTER` map type, missing from pre-5.16 kernel
+/// headers.
+pub const BPF_MAP_TYPE_BLOOM_FILTER: u32 = 30;
+
+/// Bloom filter map.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::BloomFilter`](../../redbpf_probes/maps/struct.BloomFilter.html).
+pub struct BloomFilter<'a, T: Clone> {
+    base: &'a Map,
+    _element: PhantomData<T>,
+}
+
+/// The `BPF_MAP_TYPE_DEVMAP_HASH` map type, missing from pre-5.4 kernel
+/// headers.
+pub const BPF_MAP_TYPE_DEVMAP_HASH: u32 = 25;
+
+/// Device map.
+///
+/// Maps keys to the indexes of the network interfaces XDP programs redirect
+/// packets to. This works with both `BPF_MAP_TYPE_DEVMAP` and
+/// `BPF_MAP_TYPE_DEVMAP_HASH` maps.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::xdp::DevMap`](../../redbpf_probes/xdp/struct.DevMap.html)
+/// and
+/// [`redbpf_probes::xdp::DevMapHash`](../../redbpf_probes/xdp/struct.DevMapHash.html).
+pub struct DevMap<'a> {
+    base: &'a Map,
+}
+
+/// CPU map.
+///
+/// Maps CPU IDs to the size of the queue XDP programs redirect packets to
+/// on that CPU. A CPU without an entry can't be redirected to.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::xdp::CpuMap`](../../redbpf_probes/xdp/struct.CpuMap.html).
+pub struct CpuMap<'a> {
+    base: &'a Map,
+}
+
+/// Longest prefix match trie.
+///
+/// The prefixes are `Ipv4Net` or `Ipv6Net` networks from the `ipnet` crate,
+/// see `LpmPrefix`.
+///
+/// To use this from eBPF code, see
+/// [`redbpf_probes::maps::LpmTrie`](../../redbpf_probes/maps/struct.LpmTrie.html).
+pub struct LpmTrie<'a, K: LpmPrefix, V: Clone> {
+    base: &'a Map,
+    _k: PhantomData<K>,
+    _v: PhantomData<V>,
+}
+
+/// Raw key of a `LpmTrie`.
+///
+/// This has the same layout as
+/// [`redbpf_probes::maps::Key`](../../redbpf_probes/maps/struct.Key.html).
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct LpmKey<T> {
+    pub prefixlen: u32,
+    pub data: T,
+}
+
+/// Prefix types that can be stored in a `LpmTrie`.
+pub trait LpmPrefix: Sized {
+    /// The type of the addresses looked up in the trie.
+    type Addr;
+    /// The address bytes of the key, in network byte order.
+    type Data: Copy;
+
+    /// Returns the key for this prefix.
+    fn to_key(&self) -> LpmKey<Self::Data>;
+
+    /// Returns the prefix stored in `key`.
+    fn from_key(key: &LpmKey<Self::Data>) -> Self;
+
+    /// Returns the key used to look up the longest prefix matching `addr`.
+    fn addr_key(addr: &Self::Addr) -> LpmKey<Self::Data>;
+}
+
 #[allow(dead_code)]
 pub struct RelocationInfo {
     target_sec_idx: usize,
@@ -177,6 +566,22 @@ pub struct RelocationInfo {
     sym_idx: usize,
 }
 
+impl ProgramData {
+    fn test_run(&self, data: &[u8], repeat: u32) -> Result<TestRun> {
+        let fd = self.fd.ok_or(Error::ProgramNotLoaded)?;
+        // leave room for programs that grow the packet
+        let mut out = vec![0u8; data.len() + TEST_RUN_HEADROOM];
+        let attr = prog_test_run(fd, data, &mut out, repeat)?;
+        out.truncate(attr.data_size_out as usize);
+
+        Ok(TestRun {
+            retval: attr.retval,
+            data: out,
+            duration: Duration::from_nanos(attr.duration as u64),
+        })
+    }
+}
+
 impl Program {
     #[allow(clippy::unnecessary_wraps)]
     fn new(kind: &str, name: &str, code: &[u8]) -> Result<Program> {
@@ -187,6 +592,10 @@ impl Program {
             name,
             code,
             fd: None,
+            btf: None,
+            verifier_log: VerifierLog::default(),
+            btf_error: None,
+            attach_btf_id: None,
         };
 
         Ok(match kind {
@@ -212,6 +621,61 @@ impl Program {
                 common,
                 interfaces: Vec::new(),
             }),
+            "tc_action" => Program::TcAction(TcAction {
+                common,
+                filters: Vec::new(),
+            }),
+            "raw_tracepoint" => Program::RawTracePoint(RawTracePoint { common }),
+            "tp_btf" => Program::BtfTracePoint(BtfTracePoint { common }),
+            "fentry" => Program::FEntry(FEntry { common }),
+            "fexit" => Program::FExit(FEntry { common }),
+            "cgroup_skb" | "cgroup_sock" | "cgroup_sock_addr" => {
+                // the section name is `kind/attach_point/name`
+                let section = common.name.clone();
+                let mut names = section.splitn(2, '/');
+                let attach_type = names
+                    .next()
+                    .and_then(|point| cgroup::attach_type(kind, point))
+                    .ok_or_else(|| Error::Section(format!("{}/{}", kind, section)))?;
+                let common = ProgramData {
+                    name: names.next().unwrap_or(&section).to_string(),
+                    ..common
+                };
+                let prog = Cgroup {
+                    common,
+                    attach_type,
+                };
+                match kind {
+                    "cgroup_skb" => Program::CgroupSkb(prog),
+                    "cgroup_sock" => Program::CgroupSock(prog),
+                    _ => Program::CgroupSockAddr(prog),
+                }
+            }
+            "sock_ops" => Program::SockOps(Cgroup {
+                common,
+                attach_type: BPF_CGROUP_SOCK_OPS,
+            }),
+            "sk_msg" => Program::SkMsg(SkMsg { common }),
+            "sk_skb" => {
+                // the section name is `sk_skb/stream_parser|stream_verdict/name`
+                let section = common.name.clone();
+                let mut names = section.splitn(2, '/');
+                let attach_type = match names.next() {
+                    Some("stream_parser") => BPF_SK_SKB_STREAM_PARSER,
+                    Some("stream_verdict") => BPF_SK_SKB_STREAM_VERDICT,
+                    _ => return Err(Error::Section(format!("{}/{}", kind, section))),
+                };
+                let common = ProgramData {
+                    name: names.next().unwrap_or(&section).to_string(),
+                    ..common
+                };
+                Program::SkSkb(SkSkb {
+                    common,
+                    attach_type,
+                })
+            }
+            "perf_event" => Program::PerfEvent(PerfEvent { common }),
+            "lsm" => Program::Lsm(Lsm { common }),
             _ => return Err(Error::Section(kind.to_string())),
         })
     }
@@ -226,6 +690,17 @@ impl Program {
             XDP(_) => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_XDP,
             SocketFilter(_) => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SOCKET_FILTER,
             TracePoint(_) => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_TRACEPOINT,
+            TcAction(_) => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_SCHED_CLS,
+            RawTracePoint(_) => BPF_PROG_TYPE_RAW_TRACEPOINT,
+            BtfTracePoint(_) | FEntry(_) | FExit(_) => BPF_PROG_TYPE_TRACING,
+            CgroupSkb(_) => BPF_PROG_TYPE_CGROUP_SKB,
+            CgroupSock(_) => BPF_PROG_TYPE_CGROUP_SOCK,
+            CgroupSockAddr(_) => BPF_PROG_TYPE_CGROUP_SOCK_ADDR,
+            SockOps(_) => BPF_PROG_TYPE_SOCK_OPS,
+            SkMsg(_) => BPF_PROG_TYPE_SK_MSG,
+            SkSkb(_) => BPF_PROG_TYPE_SK_SKB,
+            PerfEvent(_) => bpf_sys::bpf_prog_type_BPF_PROG_TYPE_PERF_EVENT,
+            Lsm(_) => BPF_PROG_TYPE_LSM,
         }
     }
 
@@ -238,6 +713,15 @@ impl Program {
             XDP(p) => &p.common,
             SocketFilter(p) => &p.common,
             TracePoint(p) => &p.common,
+            TcAction(p) => &p.common,
+            RawTracePoint(p) => &p.common,
+            BtfTracePoint(p) => &p.common,
+            FEntry(p) | FExit(p) => &p.common,
+            CgroupSkb(p) | CgroupSock(p) | CgroupSockAddr(p) | SockOps(p) => &p.common,
+            SkMsg(p) => &p.common,
+            SkSkb(p) => &p.common,
+            PerfEvent(p) => &p.common,
+            Lsm(p) => &p.common,
         }
     }
 
@@ -250,6 +734,15 @@ impl Program {
             XDP(p) => &mut p.common,
             SocketFilter(p) => &mut p.common,
             TracePoint(p) => &mut p.common,
+            TcAction(p) => &mut p.common,
+            RawTracePoint(p) => &mut p.common,
+            BtfTracePoint(p) => &mut p.common,
+            FEntry(p) | FExit(p) => &mut p.common,
+            CgroupSkb(p) | CgroupSock(p) | CgroupSockAddr(p) | SockOps(p) => &mut p.common,
+            SkMsg(p) => &mut p.common,
+            SkSkb(p) => &mut p.common,
+            PerfEvent(p) => &mut p.common,
+            Lsm(p) => &mut p.common,
         }
     }
 
@@ -261,10 +754,57 @@ impl Program {
         &self.data().fd
     }
 
+    /// Pin the program to `path` on a bpffs filesystem.
+    ///
+    /// A pinned program stays loaded after the process exits, and can be
+    /// retrieved by other processes.
+    pub fn pin<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let fd = self.data().fd.ok_or(Error::ProgramNotLoaded)?;
+        obj_pin(fd, &path_cstring(path.as_ref())?)?;
+        Ok(())
+    }
+
+    /// Run the program on `data` in the kernel, without attaching it.
+    ///
+    /// The program is run `repeat` times on a copy of `data`, using
+    /// `BPF_PROG_TEST_RUN`. This is supported by `XDP`, `SocketFilter`,
+    /// `TcAction` and `cgroup_skb` programs, and is meant for testing them
+    /// without having to send real traffic.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{Module, xdp};
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let program = &mut module.programs[0];
+    /// program.load(module.version, module.license.clone()).unwrap();
+    /// let packet = [0u8; 64];
+    /// let run = program.test_run(&packet, 1).unwrap();
+    /// assert_eq!(run.xdp_action(), Some(xdp::Action::Pass));
+    /// ```
+    pub fn test_run(&self, data: &[u8], repeat: u32) -> Result<TestRun> {
+        self.data().test_run(data, repeat)
+    }
+
+    /// Set the verbosity and size of the verifier log used by `load()`.
+    pub fn set_verifier_log(&mut self, log: VerifierLog) {
+        self.data_mut().verifier_log = log;
+    }
+
+    /// Returns why the program was loaded without its BTF, if it was.
+    pub fn btf_error(&self) -> Option<&Error> {
+        self.data().btf_error.as_ref()
+    }
+
     /// Load the BPF program.
     ///
     /// BPF programs need to be loaded before they can be attached. Loading will fail if the BPF verifier rejects the code.
     ///
+    /// If the verifier rejects the program, `Error::Verifier` is returned
+    /// along with the verifier log.
+    ///
+    /// Programs whose BTF the kernel rejects are loaded again without it, see
+    /// `btf_error()`.
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -280,11 +820,43 @@ impl Program {
             return Err(Error::ProgramAlreadyLoaded);
         }
         let clicense = CString::new(license)?;
-        let cname = CString::new(self.data_mut().name.clone())?;
-        let log_buffer: MutDataPtr =
-            unsafe { libc::malloc(mem::size_of::<i8>() * 16 * 65535) as MutDataPtr };
-        let buf_size = 64 * 65535_u32;
+        let VerifierLog { level, size } = self.data().verifier_log;
+        let mut log = vec![0u8; size];
+        let attach_btf_id = self.attach_btf_id()?;
+        if self.expected_attach_type().is_some() {
+            // bcc can't load programs for a given attach type
+            let mut ret = self.load_raw(kernel_version, &clicense, attach_btf_id, level, &mut log);
+            if ret.is_err() && level == 0 {
+                // load the program again to fill the log
+                ret = self.load_raw(kernel_version, &clicense, attach_btf_id, 1, &mut log);
+            }
+            let fd = ret.map_err(|_| self.load_error(&log))?;
+            self.data_mut().fd = Some(fd);
+            return Ok(());
+        }
+        if self.data().btf.is_some() {
+            let mut ret = self.load_raw(kernel_version, &clicense, None, level, &mut log);
+            if ret.is_err() && level == 0 {
+                // load the program again to fill the log
+                ret = self.load_raw(kernel_version, &clicense, None, 1, &mut log);
+            }
+            match ret {
+                Ok(fd) => {
+                    self.data_mut().fd = Some(fd);
+                    return Ok(());
+                }
+                // only retry without BTF if the BTF was the problem
+                Err(e) if is_btf_error(&e, &log) => {
+                    self.data_mut().btf_error =
+                        Some(Error::BTF(format!("program loaded without BTF: {:?}", e)))
+                }
+                Err(_) => return Err(self.load_error(&log)),
+            }
+        }
 
+        // the program is loaded again if rejected, so this also fills the
+        // log when the BTF load above failed
+        let cname = CString::new(self.data_mut().name.clone())?;
         let fd = unsafe {
             bpf_sys::bcc_prog_load(
                 self.to_prog_type(),
@@ -293,19 +865,155 @@ impl Program {
                 (self.data_mut().code.len() * mem::size_of::<bpf_insn>()) as i32,
                 clicense.as_ptr() as DataPtr,
                 kernel_version as u32,
-                0_i32,
-                log_buffer,
-                buf_size,
+                level as i32,
+                log.as_mut_ptr() as MutDataPtr,
+                log.len() as u32,
             )
         };
 
         if fd < 0 {
-            Err(Error::BPF)
+            Err(self.load_error(&log))
         } else {
             self.data_mut().fd = Some(fd);
             Ok(())
         }
     }
+
+    /// Returns the error for a program that failed to load with `log`.
+    fn load_error(&self, log: &[u8]) -> Error {
+        let len = log
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or_else(|| log.len());
+        if len == 0 {
+            return Error::BPF;
+        }
+        Error::Verifier {
+            program: self.data().name.clone(),
+            log: String::from_utf8_lossy(&log[..len]).into_owned(),
+        }
+    }
+
+    /// Returns the kernel symbol BTF tracepoints, `fentry`, `fexit` and LSM
+    /// programs are attached to.
+    fn attach_target(&self) -> Option<String> {
+        match self {
+            // the arguments of BTF tracepoints are described by the
+            // `btf_trace_` typedef of the tracepoint
+            Program::BtfTracePoint(p) => Some(format!("btf_trace_{}", p.common.name)),
+            Program::FEntry(p) | Program::FExit(p) => Some(p.common.name.clone()),
+            // every LSM hook has a `bpf_lsm_` function the programs attach to
+            Program::Lsm(p) => Some(format!("bpf_lsm_{}", p.common.name)),
+            _ => None,
+        }
+    }
+
+    /// Looks up the attach target of the program in `kernel_btf`.
+    fn resolve_attach_target(&mut self, kernel_btf: Option<&Btf>) {
+        let id = match (self.attach_target(), kernel_btf) {
+            (Some(target), Some(btf)) => match self {
+                Program::BtfTracePoint(_) => btf.find_typedef(&target),
+                _ => btf.find_function(&target),
+            },
+            _ => None,
+        };
+        self.data_mut().attach_btf_id = id;
+    }
+
+    /// Returns the id of the attach target of the program, if it has one.
+    fn attach_btf_id(&self) -> Result<Option<u32>> {
+        match self.attach_target() {
+            Some(target) => self
+                .data()
+                .attach_btf_id
+                .map(Some)
+                .ok_or(Error::SymbolNotFound(target)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the attach type the program is loaded for, which the kernel
+    /// requires for some program types.
+    fn expected_attach_type(&self) -> Option<u32> {
+        match self {
+            Program::BtfTracePoint(_) => Some(BPF_TRACE_RAW_TP),
+            Program::FEntry(_) => Some(BPF_TRACE_FENTRY),
+            Program::FExit(_) => Some(BPF_TRACE_FEXIT),
+            Program::Lsm(_) => Some(BPF_LSM_MAC),
+            Program::CgroupSkb(p)
+            | Program::CgroupSock(p)
+            | Program::CgroupSockAddr(p)
+            | Program::SockOps(p) => Some(p.attach_type),
+            _ => None,
+        }
+    }
+
+    /// Loads the program with `bpf(2)`, along with its function and line
+    /// information if any.
+    fn load_raw(
+        &self,
+        kernel_version: u32,
+        license: &CStr,
+        attach_btf_id: Option<u32>,
+        log_level: u32,
+        log: &mut [u8],
+    ) -> io::Result<RawFd> {
+        let data = self.data();
+        // the kernel rejects a log buffer without a log level
+        let (log_buf, log_size) = if log_level > 0 {
+            (log.as_mut_ptr() as u64, log.len() as u32)
+        } else {
+            (0, 0)
+        };
+        let mut attr = bpf_prog_load_attr {
+            prog_type: self.to_prog_type(),
+            insn_cnt: data.code.len() as u32,
+            insns: data.code.as_ptr() as u64,
+            license: license.as_ptr() as u64,
+            log_level,
+            log_size,
+            log_buf,
+            kern_version: kernel_version,
+            prog_name: obj_name(&data.name),
+            ..Default::default()
+        };
+        if let Some(btf) = &data.btf {
+            attr.prog_btf_fd = btf.btf_fd as u32;
+            attr.func_info_rec_size = btf.func_info_rec_size;
+            attr.func_info = btf.func_info.as_ptr() as u64;
+            attr.func_info_cnt = btf.func_info_cnt();
+            attr.line_info_rec_size = btf.line_info_rec_size;
+            attr.line_info = btf.line_info.as_ptr() as u64;
+            attr.line_info_cnt = btf.line_info_cnt();
+        }
+        if let Some(id) = attach_btf_id {
+            attr.attach_btf_id = id;
+        }
+        if let Some(attach_type) = self.expected_attach_type() {
+            attr.expected_attach_type = attach_type;
+        }
+
+        sys_bpf(BPF_PROG_LOAD, &attr)
+    }
+}
+
+/// Returns whether loading a program failed because of its BTF.
+///
+/// Kernels that don't know the BTF fields of `bpf_prog_load_attr` fail with
+/// `E2BIG`, and invalid function or line information is rejected with
+/// `EINVAL` and a log mentioning it.
+fn is_btf_error(error: &io::Error, log: &[u8]) -> bool {
+    match error.raw_os_error() {
+        Some(E2BIG) => true,
+        Some(EINVAL) => {
+            let len = log.iter().position(|&c| c == 0).unwrap_or(log.len());
+            let log = String::from_utf8_lossy(&log[..len]);
+            ["BTF", "btf", "func_info", "func info", "line_info"]
+                .iter()
+                .any(|s| log.contains(s))
+        }
+        _ => false,
+    }
 }
 
 impl KProbe {
@@ -315,15 +1023,19 @@ impl KProbe {
     /// is given, the probe will be attached at that byte offset inside the
     /// function.
     ///
+    /// The probe stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
     /// # Example
     /// ```no_run
     /// use redbpf::Module;
     /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
     /// for kprobe in module.kprobes_mut() {
-    ///     kprobe.attach_kprobe(&kprobe.name(), 0).unwrap();
+    ///     links.push(kprobe.attach_kprobe(&kprobe.name(), 0).unwrap());
     /// }
     /// ```
-    pub fn attach_kprobe(&mut self, fn_name: &str, offset: u64) -> Result<()> {
+    pub fn attach_kprobe(&mut self, fn_name: &str, offset: u64) -> Result<Link> {
         let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
         let ev_name = CString::new(format!("{}{}", fn_name, self.attach_type)).unwrap();
         let cname = CString::new(fn_name).unwrap();
@@ -341,7 +1053,10 @@ impl KProbe {
         if pfd < 0 {
             Err(Error::BPF)
         } else {
-            Ok(())
+            Ok(Link {
+                pfd,
+                kind: LinkKind::KProbe(ev_name),
+            })
         }
     }
 
@@ -368,12 +1083,16 @@ impl UProbe {
     ///
     /// If a `pid` is passed, only the corresponding process is traced.
     ///
+    /// The probe stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
     /// # Example
     /// ```no_run
     /// use redbpf::Module;
     /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
     /// for uprobe in module.uprobes_mut() {
-    ///     uprobe.attach_uprobe(Some(&uprobe.name()), 0, "/lib/x86_64-linux-gnu/libc-2.30.so", None).unwrap();
+    ///     links.push(uprobe.attach_uprobe(Some(&uprobe.name()), 0, "/lib/x86_64-linux-gnu/libc-2.30.so", None).unwrap());
     /// }
     /// ```
     pub fn attach_uprobe(
@@ -382,7 +1101,7 @@ impl UProbe {
         offset: u64,
         target: &str,
         pid: Option<pid_t>,
-    ) -> Result<()> {
+    ) -> Result<Link> {
         let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
 
         let path = if let Some(pid) = pid {
@@ -428,7 +1147,10 @@ impl UProbe {
         if pfd < 0 {
             Err(Error::BPF)
         } else {
-            Ok(())
+            Ok(Link {
+                pfd,
+                kind: LinkKind::UProbe(ev_name),
+            })
         }
     }
 
@@ -438,11 +1160,32 @@ impl UProbe {
 }
 
 impl TracePoint {
-    pub fn attach_trace_point(&mut self, category: &str, name: &str) -> Result<()> {
+    /// Attach the `tracepoint`.
+    ///
+    /// Attach the program to the tracepoint `name` in the given `category`,
+    /// as listed under `/sys/kernel/debug/tracing/events`.
+    ///
+    /// Programs defined with `#[tracepoint("category:name")]` are named after
+    /// the tracepoint they target, see `category()` and `tracepoint_name()`.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::Module;
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for tp in module.trace_points_mut() {
+    ///     let (category, name) = (tp.category().unwrap(), tp.tracepoint_name().unwrap());
+    ///     links.push(tp.attach_trace_point(&category, &name).unwrap());
+    /// }
+    /// ```
+    pub fn attach_trace_point(&mut self, category: &str, name: &str) -> Result<Link> {
         let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
         let category = CString::new(category)?;
         let name = CString::new(name)?;
-        let res = unsafe {
+        let pfd = unsafe {
             bpf_sys::bpf_attach_tracepoint(
                 fd,
                 category.as_c_str().as_ptr(),
@@ -450,31 +1193,398 @@ impl TracePoint {
             )
         };
 
-        if res < 0 {
+        if pfd < 0 {
             Err(Error::BPF)
         } else {
-            Ok(())
+            Ok(Link {
+                pfd,
+                kind: LinkKind::TracePoint,
+            })
         }
     }
 
     pub fn name(&self) -> String {
         self.common.name.to_string()
     }
-}
 
-impl XDP {
-    /// Attach the XDP program.
+    /// Returns the category of the tracepoint named in the program section.
     ///
-    /// Attach the XDP program to the given network interface.
+    /// Returns `None` if the program name isn't in the `category:name` form.
+    pub fn category(&self) -> Option<String> {
+        self.split_name().map(|(category, _)| category.to_string())
+    }
+
+    /// Returns the name of the tracepoint named in the program section.
     ///
-    /// # Example
-    /// ```no_run
-    /// # use redbpf::{Module, xdp};
-    /// # let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
-    /// # for uprobe in module.xdps_mut() {
-    /// uprobe.attach_xdp("eth0", xdp::Flags::default()).unwrap();
-    /// # }
-    /// ```
+    /// Returns `None` if the program name isn't in the `category:name` form.
+    pub fn tracepoint_name(&self) -> Option<String> {
+        self.split_name().map(|(_, name)| name.to_string())
+    }
+
+    fn split_name(&self) -> Option<(&str, &str)> {
+        let mut parts = self.common.name.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(category), Some(name)) if !category.is_empty() && !name.is_empty() => {
+                Some((category, name))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Link {
+    /// Detach the program.
+    ///
+    /// Like dropping the link, but reports errors.
+    pub fn detach(mut self) -> Result<()> {
+        self.detach_probe()
+    }
+
+    /// Returns the file descriptor of the perf event the program is attached
+    /// to for `kprobe`, `uprobe`, `tracepoint` and `perf_event` programs, of
+    /// the raw tracepoint for raw tracepoints, BTF tracepoints, `fentry`,
+    /// `fexit` and LSM programs, of the cgroup for cgroup programs, or a duplicate of the
+    /// map's for `sk_msg` and `sk_skb` programs.
+    pub fn fd(&self) -> RawFd {
+        self.pfd
+    }
+
+    fn detach_probe(&mut self) -> Result<()> {
+        if self.pfd < 0 {
+            return Ok(());
+        }
+        // programs attached to cgroups and maps stay attached when the fd is
+        // closed
+        let detached = match self.kind {
+            LinkKind::ProgAttach {
+                prog_fd,
+                attach_type,
+            } => prog_detach(self.pfd, prog_fd, attach_type).is_ok(),
+            _ => true,
+        };
+        let closed = match self.kind {
+            LinkKind::RawTracePoint | LinkKind::ProgAttach { .. } => unsafe {
+                libc::close(self.pfd)
+            },
+            _ => unsafe { bpf_sys::bpf_close_perf_event_fd(self.pfd) },
+        } == 0;
+        self.pfd = -1;
+        // probes created through the legacy `[k,u]probe_events` interface must
+        // be removed explicitly
+        let removed = match &self.kind {
+            LinkKind::KProbe(ev_name) => unsafe { bpf_sys::bpf_detach_kprobe(ev_name.as_ptr()) },
+            LinkKind::UProbe(ev_name) => unsafe { bpf_sys::bpf_detach_uprobe(ev_name.as_ptr()) },
+            LinkKind::TracePoint
+            | LinkKind::PerfEvent
+            | LinkKind::RawTracePoint
+            | LinkKind::ProgAttach { .. } => 0,
+        } == 0;
+
+        if detached && closed && removed {
+            Ok(())
+        } else {
+            Err(Error::BPF)
+        }
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        let _ = self.detach_probe();
+    }
+}
+
+impl RawTracePoint {
+    /// Attach the raw tracepoint.
+    ///
+    /// Attach the program to the tracepoint `name`. Unlike
+    /// `TracePoint::attach_trace_point`, the name doesn't include the
+    /// category of the tracepoint.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::Module;
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for tp in module.raw_trace_points_mut() {
+    ///     links.push(tp.attach_raw_trace_point(&tp.name()).unwrap());
+    /// }
+    /// ```
+    pub fn attach_raw_trace_point(&mut self, name: &str) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        let name = CString::new(name)?;
+        let pfd = raw_tracepoint_open(Some(&name), fd)?;
+
+        Ok(Link {
+            pfd,
+            kind: LinkKind::RawTracePoint,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl BtfTracePoint {
+    /// Attach the BTF tracepoint.
+    ///
+    /// The program is attached to the tracepoint it was loaded for, which is
+    /// the name of the program.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::Module;
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for tp in module.btf_trace_points_mut() {
+    ///     links.push(tp.attach_btf_trace_point().unwrap());
+    /// }
+    /// ```
+    pub fn attach_btf_trace_point(&mut self) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        // the tracepoint was given when loading the program
+        let pfd = raw_tracepoint_open(None, fd)?;
+
+        Ok(Link {
+            pfd,
+            kind: LinkKind::RawTracePoint,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl FEntry {
+    /// Attach the `fentry` or `fexit` program.
+    ///
+    /// The program is attached to the kernel function it was loaded for,
+    /// which is the name of the program.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::Module;
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for prog in module.fentries_mut() {
+    ///     links.push(prog.attach_fentry().unwrap());
+    /// }
+    /// ```
+    pub fn attach_fentry(&mut self) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        let pfd = raw_tracepoint_open(None, fd)?;
+
+        Ok(Link {
+            pfd,
+            kind: LinkKind::RawTracePoint,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl Cgroup {
+    /// Attach the cgroup program.
+    ///
+    /// Attach the program to the cgroup v2 directory `path`, for the attach
+    /// point it was loaded for. Use `cgroup::Flags::AllowMulti` to let other
+    /// programs attach to the same cgroup.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{cgroup, Module};
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for prog in module.cgroups_mut() {
+    ///     links.push(
+    ///         prog.attach_cgroup("/sys/fs/cgroup/unified", cgroup::Flags::AllowMulti)
+    ///             .unwrap(),
+    ///     );
+    /// }
+    /// ```
+    pub fn attach_cgroup<P: AsRef<Path>>(&mut self, path: P, flags: cgroup::Flags) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        let cgroup = fs::File::open(path)?;
+        prog_attach(cgroup.as_raw_fd(), fd, self.attach_type, flags as u32)?;
+
+        Ok(Link {
+            pfd: cgroup.into_raw_fd(),
+            kind: LinkKind::ProgAttach {
+                prog_fd: fd,
+                attach_type: self.attach_type,
+            },
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+/// Attaches the program `prog_fd` to the `SOCKMAP` or `SOCKHASH` map `map`.
+fn attach_sockmap(prog_fd: RawFd, attach_type: u32, map: &Map) -> Result<Link> {
+    // the link owns its fd, so it can't be the map's
+    let map_fd = unsafe { libc::dup(map.fd) };
+    if map_fd < 0 {
+        return Err(Error::IO(io::Error::last_os_error()));
+    }
+    if let Err(e) = prog_attach(map_fd, prog_fd, attach_type, 0) {
+        unsafe { libc::close(map_fd) };
+        return Err(e.into());
+    }
+
+    Ok(Link {
+        pfd: map_fd,
+        kind: LinkKind::ProgAttach {
+            prog_fd,
+            attach_type,
+        },
+    })
+}
+
+impl SkMsg {
+    /// Attach the `sk_msg` program to the `SOCKMAP` or `SOCKHASH` map `map`.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{Module, Program};
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let sockmap = module.maps.iter().find(|m| m.name == "sockmap").unwrap();
+    /// let mut links = Vec::new();
+    /// for prog in module.programs.iter_mut() {
+    ///     if let Program::SkMsg(prog) = prog {
+    ///         links.push(prog.attach_sockmap(sockmap).unwrap());
+    ///     }
+    /// }
+    /// ```
+    pub fn attach_sockmap(&mut self, map: &Map) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        attach_sockmap(fd, BPF_SK_MSG_VERDICT, map)
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl SkSkb {
+    /// Attach the `sk_skb` program to the `SOCKMAP` or `SOCKHASH` map `map`,
+    /// as the stream parser or the stream verdict program it was loaded as.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    pub fn attach_sockmap(&mut self, map: &Map) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        attach_sockmap(fd, self.attach_type, map)
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl PerfEvent {
+    /// Attach the `perf_event` program to a CPU clock.
+    ///
+    /// The program is run `freq` times per second on the CPU `cpu`, with the
+    /// registers of the task that was interrupted. To sample every CPU,
+    /// attach the program once per CPU returned by `cpus::get_online()`.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{cpus, Module};
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for prog in module.perf_events_mut() {
+    ///     for cpu in cpus::get_online().unwrap() {
+    ///         links.push(prog.attach_sampling(99, cpu).unwrap());
+    ///     }
+    /// }
+    /// ```
+    pub fn attach_sampling(&mut self, freq: u64, cpu: CpuId) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        let pfd = unsafe { attach_sampling_event(fd, freq, cpu)? };
+
+        Ok(Link {
+            pfd,
+            kind: LinkKind::PerfEvent,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl Lsm {
+    /// Attach the LSM program.
+    ///
+    /// The program is attached to the LSM hook it was loaded for, which is
+    /// the name of the program. This requires a kernel booted with `bpf` in
+    /// its list of LSMs.
+    ///
+    /// The program stays attached until the returned `Link` is dropped or
+    /// detached.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::Module;
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// let mut links = Vec::new();
+    /// for prog in module.lsms_mut() {
+    ///     links.push(prog.attach_lsm().unwrap());
+    /// }
+    /// ```
+    pub fn attach_lsm(&mut self) -> Result<Link> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        let pfd = raw_tracepoint_open(None, fd)?;
+
+        Ok(Link {
+            pfd,
+            kind: LinkKind::RawTracePoint,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl XDP {
+    /// Attach the XDP program.
+    ///
+    /// Attach the XDP program to the given network interface.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use redbpf::{Module, xdp};
+    /// # let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// # for uprobe in module.xdps_mut() {
+    /// uprobe.attach_xdp("eth0", xdp::Flags::default()).unwrap();
+    /// # }
+    /// ```
     pub fn attach_xdp(&mut self, interface: &str, flags: xdp::Flags) -> Result<()> {
         let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
         self.interfaces.push(interface.to_string());
@@ -491,6 +1601,14 @@ impl XDP {
     pub fn name(&self) -> String {
         self.common.name.to_string()
     }
+
+    /// Run the XDP program on `data`.
+    ///
+    /// See `Program::test_run()`. The action returned by the program is
+    /// given by `TestRun::xdp_action()`.
+    pub fn test_run(&self, data: &[u8], repeat: u32) -> Result<TestRun> {
+        self.common.test_run(data, repeat)
+    }
 }
 
 impl Drop for XDP {
@@ -502,6 +1620,52 @@ impl Drop for XDP {
     }
 }
 
+impl TcAction {
+    /// Attach the `tc` program.
+    ///
+    /// Attach the program to the ingress or egress path of the given network
+    /// interface. A `clsact` qdisc is added to the interface if it doesn't
+    /// have one yet, and the program is installed as a direct-action `bpf`
+    /// filter with the given `priority`. Several programs can share a
+    /// priority.
+    ///
+    /// The filters are removed when the program is dropped, leaving the
+    /// filters installed by others in place.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{Module, tc};
+    /// let mut module = Module::parse(&std::fs::read("file.elf").unwrap()).unwrap();
+    /// for prog in module.tc_actions_mut() {
+    ///     prog.attach_tc("eth0", tc::AttachPoint::Ingress, 1).unwrap();
+    /// }
+    /// ```
+    pub fn attach_tc(
+        &mut self,
+        interface: &str,
+        point: tc::AttachPoint,
+        priority: u16,
+    ) -> Result<()> {
+        let fd = self.common.fd.ok_or(Error::ProgramNotLoaded)?;
+        let filter = tc::attach(interface, point, priority, fd, &self.common.name)?;
+        self.filters.push(filter);
+
+        Ok(())
+    }
+
+    pub fn name(&self) -> String {
+        self.common.name.to_string()
+    }
+}
+
+impl Drop for TcAction {
+    fn drop(&mut self) {
+        for filter in self.filters.iter() {
+            let _ = tc::detach(filter);
+        }
+    }
+}
+
 impl SocketFilter {
     /// Attach the socket filter program.
     ///
@@ -533,6 +1697,32 @@ impl SocketFilter {
     pub fn name(&self) -> String {
         self.common.name.to_string()
     }
+
+    /// Run the socket filter program on `data`.
+    ///
+    /// See `Program::test_run()`. The value returned by the program is the
+    /// number of bytes of the packet to keep.
+    pub fn test_run(&self, data: &[u8], repeat: u32) -> Result<TestRun> {
+        self.common.test_run(data, repeat)
+    }
+}
+
+/// Lifts the `RLIMIT_MEMLOCK` limit of the process.
+///
+/// Kernels older than 5.11 charge the memory of BPF maps and programs against
+/// `RLIMIT_MEMLOCK`, and the default limit is often too low to load a probe.
+/// This raises the limit for the whole process, so it's not done by
+/// `Module::parse`. `load::Loader` calls it once before loading a probe.
+pub fn raise_memlock_limit() -> Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) } < 0 {
+        return Err(Error::IO(io::Error::last_os_error()));
+    }
+
+    Ok(())
 }
 
 impl Module {
@@ -548,6 +1738,42 @@ impl Module {
         let mut license = String::new();
         let mut version = 0u32;
 
+        // BTF is optional: if it's missing or can't be loaded, maps and
+        // programs are loaded without type information
+        let mut btf_errors = Vec::new();
+        let btf = match Btf::from_elf(&object, bytes).and_then(|mut btf| {
+            btf.load()?;
+            Ok(btf)
+        }) {
+            Ok(btf) => Some(btf),
+            // the probe was built without BTF
+            Err(Error::Section(_)) => None,
+            Err(e) => {
+                btf_errors.push(Error::BTF(format!("couldn't load the BTF: {:?}", e)));
+                None
+            }
+        };
+        let btf_ext = btf
+            .as_ref()
+            .and_then(|btf| match BtfExt::from_elf(&object, bytes, btf) {
+                Ok(btf_ext) => Some(btf_ext),
+                Err(Error::Section(_)) => None,
+                Err(e) => {
+                    btf_errors.push(Error::BTF(format!("couldn't parse .BTF.ext: {:?}", e)));
+                    None
+                }
+            });
+
+        let pinned_maps: HashSet<&str> = object
+            .syms
+            .iter()
+            .filter_map(|sym| object.strtab.get_unsafe(sym.st_name))
+            .filter(|name| name.starts_with(PIN_MAP_PREFIX))
+            .map(|name| &name[PIN_MAP_PREFIX.len()..])
+            .collect();
+        let inner_maps = inner_map_templates(&object, bytes);
+        let mut outer_maps = Vec::new();
+
         for (shndx, shdr) in object.section_headers.iter().enumerate() {
             let (kind, name) = get_split_section_name(&object, &shdr, shndx)?;
 
@@ -580,26 +1806,92 @@ impl Module {
                         )?,
                     );
                 }
+                (hdr::SHT_PROGBITS, Some("maps"), Some(name)) if inner_maps.contains_key(name) => {
+                    // created once their inner map template is
+                    outer_maps.push((shndx, name, content));
+                }
                 (hdr::SHT_PROGBITS, Some("maps"), Some(name)) => {
                     // Maps are immediately bcc_create_map'd
-                    maps.insert(shndx, Map::load(name, &content)?);
+                    let map = if pinned_maps.contains(name) {
+                        Map::load_pinned(name, &content, btf.as_ref(), None, &mut btf_errors)?
+                    } else {
+                        Map::load_with_btf(name, &content, btf.as_ref(), &mut btf_errors)?
+                    };
+                    maps.insert(shndx, map);
                 }
                 (hdr::SHT_PROGBITS, Some(kind @ "kprobe"), Some(name))
                 | (hdr::SHT_PROGBITS, Some(kind @ "kretprobe"), Some(name))
                 | (hdr::SHT_PROGBITS, Some(kind @ "uprobe"), Some(name))
                 | (hdr::SHT_PROGBITS, Some(kind @ "uretprobe"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "tracepoint"), Some(name))
                 | (hdr::SHT_PROGBITS, Some(kind @ "xdp"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "tc_action"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "raw_tracepoint"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "tp_btf"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "fentry"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "fexit"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "cgroup_skb"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "cgroup_sock"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "cgroup_sock_addr"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "sock_ops"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "sk_msg"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "sk_skb"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "perf_event"), Some(name))
+                | (hdr::SHT_PROGBITS, Some(kind @ "lsm"), Some(name))
                 | (hdr::SHT_PROGBITS, Some(kind @ "socketfilter"), Some(name)) => {
-                    programs.insert(shndx, Program::new(kind, name, &content)?);
+                    let mut prog = Program::new(kind, name, &content)?;
+                    if let (Some(btf), Some(btf_ext)) = (&btf, &btf_ext) {
+                        let section = format!("{}/{}", kind, name);
+                        prog.data_mut().btf = btf_ext.program(&section, btf);
+                    }
+                    programs.insert(shndx, prog);
                 }
                 _ => {}
             }
         }
 
+        for (shndx, name, content) in outer_maps {
+            let template = &inner_maps[name];
+            let inner = maps
+                .values()
+                .find(|map: &&Map| &map.name == template)
+                .ok_or_else(|| Error::SymbolNotFound(template.clone()))?;
+            let map = if pinned_maps.contains(name) {
+                Map::load_pinned(name, &content, btf.as_ref(), Some(inner), &mut btf_errors)?
+            } else {
+                Map::with_inner_map(name, &content, inner)?
+            };
+            maps.insert(shndx, map);
+        }
+
+        // the kernel BTF is large, only parse it once and if needed
+        let kernel_btf = if CoreRelocations::in_elf(&object)
+            || programs.values().any(|p| p.attach_target().is_some())
+        {
+            match Btf::kernel() {
+                Ok(btf) => Some(btf),
+                Err(e) => {
+                    btf_errors.push(Error::BTF(format!("couldn't load the kernel BTF: {:?}", e)));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        for prog in programs.values_mut() {
+            prog.resolve_attach_target(kernel_btf.as_ref());
+        }
+        let core = CoreRelocations::from_elf(&object, bytes, kernel_btf.as_ref(), btf.as_ref())?;
+
         // Rewrite programs with relocation data
         for rel in rels.iter() {
             if programs.contains_key(&rel.target_sec_idx) {
-                rel.apply(&mut programs, &maps, &symtab)?;
+                match core {
+                    Some(ref core) if symtab[rel.sym_idx].st_shndx == core.shndx() => {
+                        rel.apply_core(&mut programs, core, &symtab)?
+                    }
+                    _ => rel.apply(&mut programs, &maps, &symtab)?,
+                }
             }
         }
 
@@ -610,6 +1902,8 @@ impl Module {
             maps,
             license,
             version,
+            btf_errors,
+            btf,
         })
     }
 
@@ -696,20 +1990,164 @@ impl Module {
             _ => None,
         })
     }
-}
-
-#[inline]
-fn get_split_section_name<'o>(
-    object: &'o Elf<'_>,
-    shdr: &'o SectionHeader,
-    shndx: usize,
-) -> Result<(Option<&'o str>, Option<&'o str>)> {
-    let name = object
-        .shdr_strtab
-        .get_unsafe(shdr.sh_name)
-        .ok_or_else(|| Error::Section(format!("Section name not found: {}", shndx)))?;
 
-    let mut names = name.splitn(2, '/');
+    pub fn tc_actions(&self) -> impl Iterator<Item = &TcAction> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            TcAction(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn tc_actions_mut(&mut self) -> impl Iterator<Item = &mut TcAction> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            TcAction(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn raw_trace_points(&self) -> impl Iterator<Item = &RawTracePoint> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            RawTracePoint(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn raw_trace_points_mut(&mut self) -> impl Iterator<Item = &mut RawTracePoint> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            RawTracePoint(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn btf_trace_points(&self) -> impl Iterator<Item = &BtfTracePoint> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            BtfTracePoint(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn btf_trace_points_mut(&mut self) -> impl Iterator<Item = &mut BtfTracePoint> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            BtfTracePoint(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn fentries(&self) -> impl Iterator<Item = &FEntry> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            FEntry(p) | FExit(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn fentries_mut(&mut self) -> impl Iterator<Item = &mut FEntry> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            FEntry(p) | FExit(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn cgroups(&self) -> impl Iterator<Item = &Cgroup> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            CgroupSkb(p) | CgroupSock(p) | CgroupSockAddr(p) | SockOps(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn cgroups_mut(&mut self) -> impl Iterator<Item = &mut Cgroup> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            CgroupSkb(p) | CgroupSock(p) | CgroupSockAddr(p) | SockOps(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn sk_msgs(&self) -> impl Iterator<Item = &SkMsg> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            SkMsg(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn sk_msgs_mut(&mut self) -> impl Iterator<Item = &mut SkMsg> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            SkMsg(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn sk_skbs(&self) -> impl Iterator<Item = &SkSkb> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            SkSkb(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn sk_skbs_mut(&mut self) -> impl Iterator<Item = &mut SkSkb> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            SkSkb(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn perf_events(&self) -> impl Iterator<Item = &PerfEvent> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            PerfEvent(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn perf_events_mut(&mut self) -> impl Iterator<Item = &mut PerfEvent> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            PerfEvent(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn lsms(&self) -> impl Iterator<Item = &Lsm> {
+        use Program::*;
+        self.programs.iter().filter_map(|prog| match prog {
+            Lsm(p) => Some(p),
+            _ => None,
+        })
+    }
+
+    pub fn lsms_mut(&mut self) -> impl Iterator<Item = &mut Lsm> {
+        use Program::*;
+        self.programs.iter_mut().filter_map(|prog| match prog {
+            Lsm(p) => Some(p),
+            _ => None,
+        })
+    }
+}
+
+#[inline]
+fn get_split_section_name<'o>(
+    object: &'o Elf<'_>,
+    shdr: &'o SectionHeader,
+    shndx: usize,
+) -> Result<(Option<&'o str>, Option<&'o str>)> {
+    let name = object
+        .shdr_strtab
+        .get_unsafe(shdr.sh_name)
+        .ok_or_else(|| Error::Section(format!("Section name not found: {}", shndx)))?;
+
+    let mut names = name.splitn(2, '/');
 
     let kind = names.next();
     let name = names.next();
@@ -748,6 +2186,30 @@ impl RelocationInfo {
         code[insn_idx].imm = map.fd;
         Ok(())
     }
+
+    /// Applies a CO-RE relocation.
+    ///
+    /// The instruction loads the address of the path of a field in the
+    /// `.redbpf.core` section. It's patched to load the offset of the field in
+    /// the running kernel instead.
+    #[inline]
+    pub(crate) fn apply_core(
+        &self,
+        programs: &mut RSHashMap<usize, Program>,
+        core: &CoreRelocations,
+        symtab: &[Sym],
+    ) -> Result<()> {
+        let prog = programs.get_mut(&self.target_sec_idx).ok_or(Error::Reloc)?;
+        let sym = symtab[self.sym_idx];
+        let insn_idx = (self.offset / std::mem::size_of::<bpf_insn>() as u64) as usize;
+        let code = &mut prog.data_mut().code;
+        // relocations against the section symbol carry the offset of the path
+        // in the instruction
+        let path = sym.st_value as usize + code[insn_idx].imm as usize;
+        code[insn_idx].imm = core.field_offset(path)? as i32;
+        code[insn_idx + 1].imm = 0;
+        Ok(())
+    }
 }
 
 impl Map {
@@ -756,6 +2218,225 @@ impl Map {
         Map::with_map_def(name, config)
     }
 
+    /// Load the map pinned at `path` on a bpffs filesystem.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use redbpf::{HashMap, Map};
+    ///
+    /// let map = Map::from_pin("/sys/fs/bpf/counts").unwrap();
+    /// let counts = HashMap::<u32, u64>::new(&map).unwrap();
+    /// ```
+    pub fn from_pin<P: AsRef<Path>>(path: P) -> Result<Map> {
+        let path = path.as_ref();
+        let fd = obj_get(&path_cstring(path)?)?;
+        let info = match map_info(fd) {
+            Ok(info) => info,
+            Err(e) => {
+                unsafe { libc::close(fd) };
+                return Err(Error::IO(e));
+            }
+        };
+        let name = match info.name.iter().position(|c| *c == 0) {
+            Some(0) | None => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Some(len) => String::from_utf8_lossy(&info.name[..len]).into_owned(),
+        };
+
+        Ok(Map {
+            name,
+            kind: info.type_,
+            fd,
+            config: bpf_map_def {
+                type_: info.type_,
+                key_size: info.key_size,
+                value_size: info.value_size,
+                max_entries: info.max_entries,
+                map_flags: info.map_flags,
+            },
+            section_data: false,
+        })
+    }
+
+    /// Pin the map to `path` on a bpffs filesystem.
+    ///
+    /// A pinned map outlives the process, so its contents are preserved
+    /// across restarts. Use `Map::from_pin` to load it again.
+    pub fn pin<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        obj_pin(self.fd, &path_cstring(path.as_ref())?)?;
+        Ok(())
+    }
+
+    /// Returns the value of `key` as raw bytes.
+    ///
+    /// This is meant for tools that don't know the key and value types of
+    /// the map at compile time, like `cargo bpf test`. `key` must be
+    /// `key_size` bytes long. For per-CPU maps, the values of all the
+    /// possible CPUs are returned, each padded to a multiple of 8 bytes.
+    pub fn get_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if key.len() != self.config.key_size as usize {
+            return Err(Error::Map);
+        }
+        let value_size = self.config.value_size as usize;
+        let size = match self.config.type_ {
+            bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_HASH
+            | bpf_sys::bpf_map_type_BPF_MAP_TYPE_PERCPU_ARRAY
+            | bpf_sys::bpf_map_type_BPF_MAP_TYPE_LRU_PERCPU_HASH => {
+                ((value_size + 7) & !7) * cpus::get_possible()?.len()
+            }
+            _ => value_size,
+        };
+        let mut value = vec![0u8; size];
+        if unsafe {
+            bpf_sys::bpf_lookup_elem(
+                self.fd,
+                key.as_ptr() as *mut _,
+                value.as_mut_ptr() as *mut _,
+            )
+        } < 0
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Creates an empty map with the same type, key and value sizes, maximum
+    /// number of entries and flags as this one.
+    ///
+    /// This is meant for creating inner maps from the inner map template of
+    /// an `ArrayOfMaps` or `HashOfMaps`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{load::Loader, ArrayOfMaps, HashMap};
+    /// let loader = Loader::load_file("rules.elf").expect("error loading probe");
+    /// let rules = loader.map("rules_template").unwrap().new_like("rules").unwrap();
+    /// HashMap::<u32, u32>::new(&rules).unwrap().set(80, 1);
+    ///
+    /// // atomically replace the rules of tenant 0
+    /// let tenants = ArrayOfMaps::new(loader.map("tenants").unwrap()).unwrap();
+    /// tenants.set(0, &rules).unwrap();
+    /// ```
+    pub fn new_like(&self, name: &str) -> Result<Map> {
+        Map::with_map_def(name, self.config)
+    }
+
+    /// Loads the map pinned at `BPF_FS_PATH/<name>`, or creates and pins it if
+    /// it doesn't exist.
+    fn load_pinned(
+        name: &str,
+        code: &[u8],
+        btf: Option<&Btf>,
+        inner_map: Option<&Map>,
+        btf_errors: &mut Vec<Error>,
+    ) -> Result<Map> {
+        let path = Path::new(BPF_FS_PATH).join(name);
+        if !path.exists() {
+            let map = match inner_map {
+                Some(inner) => Map::with_inner_map(name, code, inner)?,
+                None => Map::load_with_btf(name, code, btf, btf_errors)?,
+            };
+            map.pin(&path)?;
+            return Ok(map);
+        }
+
+        let config: bpf_map_def = *zero::read(code);
+        let map = Map::from_pin(&path)?;
+        // the pinned map may have been created by an incompatible version of
+        // the probe
+        check_pinned_map(name, &map.config, &config)?;
+
+        Ok(Map {
+            name: name.to_string(),
+            ..map
+        })
+    }
+
+    /// Creates the map annotated with the key and value types found in `btf`.
+    ///
+    /// Not all map types support BTF, so if the kernel rejects the types the
+    /// map is created without and the reason is added to `btf_errors`.
+    ///
+    /// Maps without keys, like queues and stacks, are only annotated with the
+    /// type of their values, and maps without values, like ring buffers,
+    /// aren't annotated.
+    fn load_with_btf(
+        name: &str,
+        code: &[u8],
+        btf: Option<&Btf>,
+        btf_errors: &mut Vec<Error>,
+    ) -> Result<Map> {
+        let config: bpf_map_def = *zero::read(code);
+        let btf = match btf {
+            Some(btf) if config.value_size > 0 => btf,
+            _ => return Map::with_map_def(name, config),
+        };
+        let (fd, (key_type, value_type)) = match (btf.fd(), btf.map_types(name)) {
+            (Some(fd), Some(types)) => (fd, types),
+            _ => return Map::with_map_def(name, config),
+        };
+        // the kernel rejects a key type for maps with no keys
+        let key_type = if config.key_size == 0 { 0 } else { key_type };
+        let attr = bpf_map_create_attr {
+            map_type: config.type_,
+            key_size: config.key_size,
+            value_size: config.value_size,
+            max_entries: config.max_entries,
+            map_flags: config.map_flags,
+            map_name: obj_name(name),
+            btf_fd: fd as u32,
+            btf_key_type_id: key_type,
+            btf_value_type_id: value_type,
+            ..Default::default()
+        };
+        match sys_bpf(BPF_MAP_CREATE, &attr) {
+            Ok(fd) => Ok(Map {
+                name: name.to_string(),
+                kind: config.type_,
+                fd,
+                config,
+                section_data: false,
+            }),
+            Err(e) => {
+                btf_errors.push(Error::BTF(format!(
+                    "map `{}` created without BTF: {}",
+                    name, e
+                )));
+                Map::with_map_def(name, config)
+            }
+        }
+    }
+
+    /// Creates an array or hash of maps holding maps like `inner`.
+    fn with_inner_map(name: &str, code: &[u8], inner: &Map) -> Result<Map> {
+        let config: bpf_map_def = *zero::read(code);
+        let attr = bpf_map_create_attr {
+            map_type: config.type_,
+            key_size: config.key_size,
+            value_size: config.value_size,
+            max_entries: config.max_entries,
+            map_flags: config.map_flags,
+            inner_map_fd: inner.fd as u32,
+            map_name: obj_name(name),
+            ..Default::default()
+        };
+        // keep the errno, to tell an invalid inner map template (EINVAL) from
+        // the memlock limit (EPERM)
+        let fd = sys_bpf(BPF_MAP_CREATE, &attr)?;
+
+        Ok(Map {
+            name: name.to_string(),
+            kind: config.type_,
+            fd,
+            config,
+            section_data: false,
+        })
+    }
+
     fn with_section_data(name: &str, data: &[u8], flags: u32) -> Result<Map> {
         let mut map = Map::with_map_def(
             name,
@@ -864,6 +2545,75 @@ impl<'base, K: Clone, V: Clone> HashMap<'base, K, V> {
             key: None,
         }
     }
+
+    /// Returns all the entries of the map.
+    ///
+    /// The entries are read with as few syscalls as possible using
+    /// `BPF_MAP_LOOKUP_BATCH`, which makes this much faster than `iter` for
+    /// large maps. On kernels without batch operations, this falls back to
+    /// `iter`.
+    pub fn lookup_batch(&self) -> Result<Vec<(K, V)>> {
+        match batch::lookup(self.base, mem::size_of::<V>(), false)? {
+            Some(entries) => Ok((0..entries.len())
+                .map(|i| (entries.key(i), entries.value(i)))
+                .collect()),
+            None => Ok(self.iter().collect()),
+        }
+    }
+
+    /// Removes all the entries of the map and returns them.
+    ///
+    /// Like `lookup_batch`, but the entries are deleted as they're read. If
+    /// the kernel fails partway through, the entries removed until then are
+    /// returned rather than an error, so the map may not be empty
+    /// afterwards.
+    pub fn lookup_and_delete_batch(&self) -> Result<Vec<(K, V)>> {
+        match batch::lookup(self.base, mem::size_of::<V>(), true)? {
+            Some(entries) => Ok((0..entries.len())
+                .map(|i| (entries.key(i), entries.value(i)))
+                .collect()),
+            None => delete_entries(self.base, self.iter().collect()),
+        }
+    }
+
+    /// Sets the value of each of `keys` to the value at the same position in
+    /// `values`.
+    ///
+    /// Falls back to setting the keys one by one on kernels without batch
+    /// operations, stopping at the first error.
+    pub fn update_batch(&self, keys: &[K], values: &[V]) -> Result<()> {
+        if keys.len() != values.len() {
+            return Err(Error::Map);
+        }
+        let batched = batch::update(
+            self.base,
+            keys.as_ptr() as *const _,
+            values.as_ptr() as *const _,
+            keys.len(),
+        )?;
+        if !batched {
+            for (key, value) in keys.iter().zip(values) {
+                update_elem(self.base, key, value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the entries of `keys`.
+    ///
+    /// Falls back to deleting the keys one by one on kernels without batch
+    /// operations, stopping at the first error. Like the batch operation,
+    /// this fails if one of the keys isn't in the map.
+    pub fn delete_batch(&self, keys: &[K]) -> Result<()> {
+        if !batch::delete(self.base, keys.as_ptr() as *const _, keys.len())? {
+            for key in keys {
+                delete_elem(self.base, key).map_err(|_| Error::Map)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<'base> ProgramArray<'base> {
@@ -928,53 +2678,1091 @@ impl<'base> ProgramArray<'base> {
     }
 }
 
-pub struct MapIter<'a, 'b, K: Clone, V: Clone> {
-    map: &'a HashMap<'b, K, V>,
-    key: Option<K>,
-}
-
-impl<K: Clone, V: Clone> Iterator for MapIter<'_, '_, K, V> {
-    type Item = (K, V);
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let key = self.key.take();
-        self.key = match key {
-            Some(mut key) => {
-                let mut next_key = MaybeUninit::<K>::zeroed();
-                let ret = unsafe {
-                    bpf_sys::bpf_get_next_key(
-                        self.map.base.fd,
-                        &mut key as *mut _ as *mut _,
-                        &mut next_key as *mut _ as *mut _,
-                    )
-                };
-                if ret < 0 {
-                    None
-                } else {
-                    Some(unsafe { next_key.assume_init() })
-                }
-            }
-            None => {
-                let mut key = MaybeUninit::<K>::zeroed();
-                if unsafe {
-                    bpf_sys::bpf_get_first_key(
-                        self.map.base.fd,
-                        &mut key as *mut _ as *mut _,
-                        self.map.base.config.key_size.into(),
-                    )
-                } < 0
-                {
-                    None
-                } else {
-                    Some(unsafe { key.assume_init() })
-                }
-            }
-        };
+impl<'base> ArrayOfMaps<'base> {
+    pub fn new(base: &Map) -> Result<ArrayOfMaps> {
+        if base.kind != bpf_sys::bpf_map_type_BPF_MAP_TYPE_ARRAY_OF_MAPS
+            || mem::size_of::<u32>() != base.config.key_size as usize
+        {
+            return Err(Error::Map);
+        }
 
-        let key = self.key.as_ref()?.clone();
-        Some((key.clone(), self.map.get(key).unwrap()))
+        Ok(ArrayOfMaps { base })
     }
-}
+
+    /// Set the `index` entry to the map `map`.
+    ///
+    /// `map` must have the same type, key and value sizes as the inner map
+    /// template, see `Map::new_like`.
+    pub fn set(&self, mut index: u32, map: &Map) -> Result<()> {
+        let mut fd = map.fd;
+        let ret = unsafe {
+            bpf_sys::bpf_update_elem(
+                self.base.fd,
+                &mut index as *mut _ as *mut _,
+                &mut fd as *mut _ as *mut _,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Remove the map at `index`.
+    pub fn delete(&self, mut index: u32) -> Result<()> {
+        let ret = unsafe { bpf_sys::bpf_delete_elem(self.base.fd, &mut index as *mut _ as *mut _) };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'base, K: Clone> HashOfMaps<'base, K> {
+    pub fn new(base: &Map) -> Result<HashOfMaps<K>> {
+        if base.kind != bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH_OF_MAPS
+            || mem::size_of::<K>() != base.config.key_size as usize
+        {
+            return Err(Error::Map);
+        }
+
+        Ok(HashOfMaps {
+            base,
+            _k: PhantomData,
+        })
+    }
+
+    /// Set the entry for `key` to the map `map`.
+    ///
+    /// `map` must have the same type, key and value sizes as the inner map
+    /// template, see `Map::new_like`.
+    pub fn set(&self, mut key: K, map: &Map) -> Result<()> {
+        let mut fd = map.fd;
+        let ret = unsafe {
+            bpf_sys::bpf_update_elem(
+                self.base.fd,
+                &mut key as *mut _ as *mut _,
+                &mut fd as *mut _ as *mut _,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Remove the map for `key`.
+    pub fn delete(&self, mut key: K) -> Result<()> {
+        let ret = unsafe { bpf_sys::bpf_delete_elem(self.base.fd, &mut key as *mut _ as *mut _) };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+}
+
+impl<'base, T: Clone> Queue<'base, T> {
+    pub fn new(base: &Map) -> Result<Queue<T>> {
+        check_value_map::<T>(base, bpf_sys::bpf_map_type_BPF_MAP_TYPE_QUEUE)?;
+
+        Ok(Queue {
+            base,
+            _element: PhantomData,
+        })
+    }
+
+    /// Push `value` at the back of the queue.
+    ///
+    /// Fails if the queue is full.
+    pub fn push(&self, value: T) -> Result<()> {
+        push_elem(self.base, value, 0)
+    }
+
+    /// Push `value` at the back of the queue, evicting the element at the
+    /// front if the queue is full.
+    pub fn force_push(&self, value: T) -> Result<()> {
+        push_elem(self.base, value, BPF_EXIST)
+    }
+
+    /// Remove the element at the front of the queue and return it.
+    pub fn pop(&self) -> Option<T> {
+        pop_elem(self.base)
+    }
+
+    /// Return the element at the front of the queue without removing it.
+    pub fn peek(&self) -> Option<T> {
+        peek_elem(self.base)
+    }
+}
+
+impl<'base, T: Clone> Stack<'base, T> {
+    pub fn new(base: &Map) -> Result<Stack<T>> {
+        check_value_map::<T>(base, bpf_sys::bpf_map_type_BPF_MAP_TYPE_STACK)?;
+
+        Ok(Stack {
+            base,
+            _element: PhantomData,
+        })
+    }
+
+    /// Push `value` on top of the stack.
+    ///
+    /// Fails if the stack is full.
+    pub fn push(&self, value: T) -> Result<()> {
+        push_elem(self.base, value, 0)
+    }
+
+    /// Push `value` on top of the stack, evicting the element at the bottom if
+    /// the stack is full.
+    pub fn force_push(&self, value: T) -> Result<()> {
+        push_elem(self.base, value, BPF_EXIST)
+    }
+
+    /// Remove the element on top of the stack and return it.
+    pub fn pop(&self) -> Option<T> {
+        pop_elem(self.base)
+    }
+
+    /// Return the element on top of the stack without removing it.
+    pub fn peek(&self) -> Option<T> {
+        peek_elem(self.base)
+    }
+}
+
+impl<'base, T: Clone> BloomFilter<'base, T> {
+    pub fn new(base: &Map) -> Result<BloomFilter<T>> {
+        check_value_map::<T>(base, BPF_MAP_TYPE_BLOOM_FILTER)?;
+
+        Ok(BloomFilter {
+            base,
+            _element: PhantomData,
+        })
+    }
+
+    /// Add `value` to the filter.
+    pub fn insert(&self, value: T) -> Result<()> {
+        push_elem(self.base, value, 0)
+    }
+
+    /// Returns `false` if `value` is definitely not in the filter, `true` if
+    /// it may be.
+    pub fn contains(&self, mut value: T) -> bool {
+        map_peek_elem(self.base.fd, &mut value as *mut _ as *mut _).is_ok()
+    }
+}
+
+/// Checks that `map` is a map of type `kind` without keys and with values of
+/// type `T`.
+/// Checks that the map `pinned` for `name` matches its definition `config`.
+fn check_pinned_map(name: &str, pinned: &bpf_map_def, config: &bpf_map_def) -> Result<()> {
+    let fields = [
+        ("type", pinned.type_, config.type_),
+        ("key_size", pinned.key_size, config.key_size),
+        ("value_size", pinned.value_size, config.value_size),
+        ("max_entries", pinned.max_entries, config.max_entries),
+        ("map_flags", pinned.map_flags, config.map_flags),
+    ];
+    match fields
+        .iter()
+        .find(|(_, pinned, expected)| pinned != expected)
+    {
+        Some(&(field, pinned, expected)) => Err(Error::PinnedMapMismatch {
+            map: name.to_string(),
+            field,
+            pinned,
+            expected,
+        }),
+        None => Ok(()),
+    }
+}
+
+fn check_value_map<T>(map: &Map, kind: u32) -> Result<()> {
+    if map.kind != kind
+        || map.config.key_size != 0
+        || mem::size_of::<T>() != map.config.value_size as usize
+    {
+        return Err(Error::Map);
+    }
+
+    Ok(())
+}
+
+fn update_elem<K, V>(map: &Map, key: &K, value: &V) -> Result<()> {
+    let ret = unsafe {
+        bpf_sys::bpf_update_elem(
+            map.fd,
+            key as *const _ as *mut _,
+            value as *const _ as *mut _,
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Map);
+    }
+
+    Ok(())
+}
+
+fn delete_elem<K>(map: &Map, key: &K) -> io::Result<()> {
+    let ret = unsafe { bpf_sys::bpf_delete_elem(map.fd, key as *const _ as *mut _) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Deletes the entries read by `iter` from `map`, and returns those that
+/// weren't deleted by someone else in the meantime.
+fn delete_entries<K, V>(map: &Map, entries: Vec<(K, V)>) -> Result<Vec<(K, V)>> {
+    let mut deleted = Vec::with_capacity(entries.len());
+    for (key, value) in entries {
+        match delete_elem(map, &key) {
+            Ok(()) => deleted.push((key, value)),
+            Err(e) if e.raw_os_error() == Some(ENOENT) => {}
+            Err(_) => return Err(Error::Map),
+        }
+    }
+
+    Ok(deleted)
+}
+
+fn push_elem<T>(map: &Map, value: T, flags: u64) -> Result<()> {
+    map_push_elem(map.fd, &value as *const _ as *const _, flags).map_err(|_| Error::Map)
+}
+
+fn pop_elem<T>(map: &Map) -> Option<T> {
+    let mut value = MaybeUninit::<T>::zeroed();
+    map_pop_elem(map.fd, value.as_mut_ptr() as *mut _).ok()?;
+    Some(unsafe { value.assume_init() })
+}
+
+fn peek_elem<T>(map: &Map) -> Option<T> {
+    let mut value = MaybeUninit::<T>::zeroed();
+    map_peek_elem(map.fd, value.as_mut_ptr() as *mut _).ok()?;
+    Some(unsafe { value.assume_init() })
+}
+
+impl LpmPrefix for Ipv4Net {
+    type Addr = Ipv4Addr;
+    type Data = [u8; 4];
+
+    fn to_key(&self) -> LpmKey<[u8; 4]> {
+        LpmKey {
+            prefixlen: self.prefix_len().into(),
+            data: self.network().octets(),
+        }
+    }
+
+    fn from_key(key: &LpmKey<[u8; 4]>) -> Self {
+        // the kernel doesn't store prefixes longer than the address
+        Ipv4Net::new(key.data.into(), key.prefixlen as u8).unwrap()
+    }
+
+    fn addr_key(addr: &Ipv4Addr) -> LpmKey<[u8; 4]> {
+        LpmKey {
+            prefixlen: 32,
+            data: addr.octets(),
+        }
+    }
+}
+
+impl LpmPrefix for Ipv6Net {
+    type Addr = Ipv6Addr;
+    type Data = [u8; 16];
+
+    fn to_key(&self) -> LpmKey<[u8; 16]> {
+        LpmKey {
+            prefixlen: self.prefix_len().into(),
+            data: self.network().octets(),
+        }
+    }
+
+    fn from_key(key: &LpmKey<[u8; 16]>) -> Self {
+        Ipv6Net::new(key.data.into(), key.prefixlen as u8).unwrap()
+    }
+
+    fn addr_key(addr: &Ipv6Addr) -> LpmKey<[u8; 16]> {
+        LpmKey {
+            prefixlen: 128,
+            data: addr.octets(),
+        }
+    }
+}
+
+impl<'base, K: LpmPrefix, V: Clone> LpmTrie<'base, K, V> {
+    pub fn new(base: &Map) -> Result<LpmTrie<K, V>> {
+        if base.kind != bpf_sys::bpf_map_type_BPF_MAP_TYPE_LPM_TRIE
+            || mem::size_of::<LpmKey<K::Data>>() != base.config.key_size as usize
+            || mem::size_of::<V>() != base.config.value_size as usize
+        {
+            return Err(Error::Map);
+        }
+
+        Ok(LpmTrie {
+            base,
+            _k: PhantomData,
+            _v: PhantomData,
+        })
+    }
+
+    /// Set the `value` for the prefix `net`.
+    ///
+    /// The host bits of `net` are ignored, so `10.1.2.3/8` is stored as
+    /// `10.0.0.0/8`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use ipnet::Ipv4Net;
+    /// use redbpf::{load::Loader, LpmTrie};
+    /// let loader = Loader::load_file("firewall.elf").expect("error loading probe");
+    /// let blocklist = LpmTrie::<Ipv4Net, u8>::new(loader.map("blocklist").unwrap()).unwrap();
+    ///
+    /// blocklist.set("10.0.0.0/8".parse().unwrap(), 1).unwrap();
+    /// assert_eq!(blocklist.lookup(&"10.1.2.3".parse().unwrap()), Some(1));
+    /// ```
+    pub fn set(&self, net: K, mut value: V) -> Result<()> {
+        let mut key = net.to_key();
+        let ret = unsafe {
+            bpf_sys::bpf_update_elem(
+                self.base.fd,
+                &mut key as *mut _ as *mut _,
+                &mut value as *mut _ as *mut _,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the value of the longest prefix containing `addr`.
+    pub fn lookup(&self, addr: &K::Addr) -> Option<V> {
+        self.get_key(K::addr_key(addr))
+    }
+
+    /// Remove the prefix `net`.
+    pub fn delete(&self, net: K) -> Result<()> {
+        let mut key = net.to_key();
+        let ret = unsafe { bpf_sys::bpf_delete_elem(self.base.fd, &mut key as *mut _ as *mut _) };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Returns an iterator over the prefixes in the trie and their values.
+    ///
+    /// The iteration stops early if the prefix it reached is deleted
+    /// meanwhile, as the kernel can't tell where to continue from.
+    pub fn iter<'a>(&'a self) -> LpmTrieIter<'a, 'base, K, V> {
+        LpmTrieIter {
+            map: self,
+            key: None,
+            done: false,
+        }
+    }
+
+    fn get_key(&self, mut key: LpmKey<K::Data>) -> Option<V> {
+        let mut value = MaybeUninit::zeroed();
+        if unsafe {
+            bpf_sys::bpf_lookup_elem(
+                self.base.fd,
+                &mut key as *mut _ as *mut _,
+                &mut value as *mut _ as *mut _,
+            )
+        } < 0
+        {
+            return None;
+        }
+        Some(unsafe { value.assume_init() })
+    }
+}
+
+impl<'base> DevMap<'base> {
+    pub fn new(base: &Map) -> Result<DevMap> {
+        check_u32_map(
+            base,
+            &[
+                bpf_sys::bpf_map_type_BPF_MAP_TYPE_DEVMAP,
+                BPF_MAP_TYPE_DEVMAP_HASH,
+            ],
+        )?;
+
+        Ok(DevMap { base })
+    }
+
+    /// Set the index of the interface `key` redirects to.
+    pub fn set(&self, key: u32, ifindex: u32) -> Result<()> {
+        update_u32(self.base, key, ifindex)
+    }
+
+    /// Set the interface `key` redirects to by name.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{load::Loader, DevMap};
+    /// let loader = Loader::load_file("balancer.elf").expect("error loading probe");
+    /// let backends = DevMap::new(loader.map("backends").unwrap()).unwrap();
+    ///
+    /// for (i, interface) in ["veth1", "veth2"].iter().enumerate() {
+    ///     backends.set_interface(i as u32, interface).unwrap();
+    /// }
+    /// ```
+    pub fn set_interface(&self, key: u32, interface: &str) -> Result<()> {
+        self.set(key, tc::ifindex(interface)? as u32)
+    }
+
+    /// Returns the index of the interface `key` redirects to.
+    pub fn get(&self, key: u32) -> Option<u32> {
+        lookup_u32(self.base, key)
+    }
+
+    /// Remove the interface at `key`.
+    pub fn delete(&self, key: u32) -> Result<()> {
+        delete_u32(self.base, key)
+    }
+}
+
+impl<'base> CpuMap<'base> {
+    pub fn new(base: &Map) -> Result<CpuMap> {
+        check_u32_map(base, &[bpf_sys::bpf_map_type_BPF_MAP_TYPE_CPUMAP])?;
+
+        Ok(CpuMap { base })
+    }
+
+    /// Enable redirecting to `cpu`, queueing up to `queue_size` packets.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use redbpf::{cpus, load::Loader, CpuMap};
+    /// let loader = Loader::load_file("balancer.elf").expect("error loading probe");
+    /// let cpu_map = CpuMap::new(loader.map("cpus").unwrap()).unwrap();
+    ///
+    /// for cpu in cpus::get_online().unwrap() {
+    ///     cpu_map.set(cpu, 2048).unwrap();
+    /// }
+    /// ```
+    pub fn set(&self, cpu: CpuId, queue_size: u32) -> Result<()> {
+        update_u32(self.base, cpu as u32, queue_size)
+    }
+
+    /// Returns the queue size of `cpu`.
+    pub fn get(&self, cpu: CpuId) -> Option<u32> {
+        lookup_u32(self.base, cpu as u32)
+    }
+
+    /// Disable redirecting to `cpu`.
+    pub fn delete(&self, cpu: CpuId) -> Result<()> {
+        delete_u32(self.base, cpu as u32)
+    }
+}
+
+/// Checks that `map` is a map of one of the types in `kinds` with `u32`
+/// keys and values.
+fn check_u32_map(map: &Map, kinds: &[u32]) -> Result<()> {
+    if !kinds.contains(&map.kind)
+        || mem::size_of::<u32>() != map.config.key_size as usize
+        || mem::size_of::<u32>() != map.config.value_size as usize
+    {
+        return Err(Error::Map);
+    }
+
+    Ok(())
+}
+
+fn update_u32(map: &Map, mut key: u32, mut value: u32) -> Result<()> {
+    let ret = unsafe {
+        bpf_sys::bpf_update_elem(
+            map.fd,
+            &mut key as *mut _ as *mut _,
+            &mut value as *mut _ as *mut _,
+            0,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::Map);
+    }
+
+    Ok(())
+}
+
+fn lookup_u32(map: &Map, mut key: u32) -> Option<u32> {
+    let mut value = 0u32;
+    if unsafe {
+        bpf_sys::bpf_lookup_elem(
+            map.fd,
+            &mut key as *mut _ as *mut _,
+            &mut value as *mut _ as *mut _,
+        )
+    } < 0
+    {
+        return None;
+    }
+    Some(value)
+}
+
+fn delete_u32(map: &Map, mut key: u32) -> Result<()> {
+    let ret = unsafe { bpf_sys::bpf_delete_elem(map.fd, &mut key as *mut _ as *mut _) };
+    if ret < 0 {
+        return Err(Error::Map);
+    }
+
+    Ok(())
+}
+
+impl<'base, T: Clone> Array<'base, T> {
+    pub fn new(base: &Map) -> Result<Array<T>> {
+        if mem::size_of::<u32>() != base.config.key_size as usize
+            || mem::size_of::<T>() != base.config.value_size as usize
+        {
+            return Err(Error::Map);
+        }
+
+        Ok(Array {
+            base,
+            _element: PhantomData,
+        })
+    }
+
+    /// Returns the number of elements in the array.
+    pub fn len(&self) -> usize {
+        self.base.config.max_entries as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the element at `index`.
+    ///
+    /// Returns `None` if `index` is out of bounds.
+    pub fn get(&self, mut index: u32) -> Option<T> {
+        let mut value = MaybeUninit::zeroed();
+        if unsafe {
+            bpf_sys::bpf_lookup_elem(
+                self.base.fd,
+                &mut index as *mut _ as *mut _,
+                &mut value as *mut _ as *mut _,
+            )
+        } < 0
+        {
+            return None;
+        }
+        Some(unsafe { value.assume_init() })
+    }
+
+    /// Set the element at `index` to `value`.
+    pub fn set(&self, mut index: u32, mut value: T) -> Result<()> {
+        let ret = unsafe {
+            bpf_sys::bpf_update_elem(
+                self.base.fd,
+                &mut index as *mut _ as *mut _,
+                &mut value as *mut _ as *mut _,
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Returns all the elements of the array.
+    ///
+    /// See `HashMap::lookup_batch`.
+    pub fn lookup_batch(&self) -> Result<Vec<T>> {
+        match batch::lookup(self.base, mem::size_of::<T>(), false)? {
+            Some(entries) => Ok((0..entries.len()).map(|i| entries.value(i)).collect()),
+            // every index of an array has a value
+            None => (0..self.len() as u32)
+                .map(|i| self.get(i).ok_or(Error::Map))
+                .collect(),
+        }
+    }
+
+    /// Sets the element at each of `indices` to the value at the same
+    /// position in `values`.
+    ///
+    /// See `HashMap::update_batch`.
+    pub fn update_batch(&self, indices: &[u32], values: &[T]) -> Result<()> {
+        if indices.len() != values.len() {
+            return Err(Error::Map);
+        }
+        let batched = batch::update(
+            self.base,
+            indices.as_ptr() as *const _,
+            values.as_ptr() as *const _,
+            indices.len(),
+        )?;
+        if !batched {
+            for (index, value) in indices.iter().zip(values) {
+                self.set(*index, value.clone())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'base, T: Clone> PerCpuArray<'base, T> {
+    pub fn new(base: &Map) -> Result<PerCpuArray<T>> {
+        if mem::size_of::<u32>() != base.config.key_size as usize
+            || mem::size_of::<T>() != base.config.value_size as usize
+        {
+            return Err(Error::Map);
+        }
+
+        Ok(PerCpuArray {
+            base,
+            cpus: cpus::get_possible()?.len(),
+            _element: PhantomData,
+        })
+    }
+
+    /// Returns the number of elements in the array.
+    pub fn len(&self) -> usize {
+        self.base.config.max_entries as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the values of the element at `index`, indexed by CPU ID.
+    ///
+    /// Returns `None` if `index` is out of bounds.
+    pub fn get(&self, mut index: u32) -> Option<Vec<T>> {
+        let mut values = PerCpuValues::<T>::new(self.cpus);
+        if unsafe {
+            bpf_sys::bpf_lookup_elem(
+                self.base.fd,
+                &mut index as *mut _ as *mut _,
+                values.as_mut_ptr(),
+            )
+        } < 0
+        {
+            return None;
+        }
+        Some(values.to_vec())
+    }
+
+    /// Set the values of the element at `index`.
+    ///
+    /// `values` must hold one value for every possible CPU, indexed by CPU
+    /// ID.
+    pub fn set(&self, mut index: u32, values: &[T]) -> Result<()> {
+        let mut values = PerCpuValues::from_slice(self.cpus, values)?;
+        let ret = unsafe {
+            bpf_sys::bpf_update_elem(
+                self.base.fd,
+                &mut index as *mut _ as *mut _,
+                values.as_mut_ptr(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the values of all the elements of the array, indexed by CPU
+    /// ID.
+    ///
+    /// See `HashMap::lookup_batch`.
+    pub fn lookup_batch(&self) -> Result<Vec<Vec<T>>> {
+        let value_size = PerCpuValues::<T>::stride() * self.cpus;
+        match batch::lookup(self.base, value_size, false)? {
+            Some(entries) => Ok((0..entries.len())
+                .map(|i| PerCpuValues::<T>::from_bytes(self.cpus, entries.value_bytes(i)).to_vec())
+                .collect()),
+            // every index of an array has a value
+            None => (0..self.len() as u32)
+                .map(|i| self.get(i).ok_or(Error::Map))
+                .collect(),
+        }
+    }
+
+    /// Sets the values of the element at each of `indices` to the values at
+    /// the same position in `values`.
+    ///
+    /// See `PerCpuHashMap::update_batch`.
+    pub fn update_batch(&self, indices: &[u32], values: &[Vec<T>]) -> Result<()> {
+        if indices.len() != values.len() {
+            return Err(Error::Map);
+        }
+        let mut buf = Vec::new();
+        for cpu_values in values {
+            buf.extend(PerCpuValues::from_slice(self.cpus, cpu_values)?.buf);
+        }
+        if !batch::update(
+            self.base,
+            indices.as_ptr() as *const _,
+            buf.as_ptr(),
+            indices.len(),
+        )? {
+            for (index, cpu_values) in indices.iter().zip(values) {
+                self.set(*index, cpu_values)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'base, K: Clone, V: Clone> PerCpuHashMap<'base, K, V> {
+    pub fn new(base: &Map) -> Result<PerCpuHashMap<K, V>> {
+        if mem::size_of::<K>() != base.config.key_size as usize
+            || mem::size_of::<V>() != base.config.value_size as usize
+        {
+            return Err(Error::Map);
+        }
+
+        Ok(PerCpuHashMap {
+            base,
+            cpus: cpus::get_possible()?.len(),
+            _k: PhantomData,
+            _v: PhantomData,
+        })
+    }
+
+    /// Set the values for `key`.
+    ///
+    /// `values` must hold one value for every possible CPU, indexed by CPU
+    /// ID.
+    pub fn set(&self, mut key: K, values: &[V]) -> Result<()> {
+        let mut values = PerCpuValues::from_slice(self.cpus, values)?;
+        let ret = unsafe {
+            bpf_sys::bpf_update_elem(
+                self.base.fd,
+                &mut key as *mut _ as *mut _,
+                values.as_mut_ptr(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Map);
+        }
+
+        Ok(())
+    }
+
+    /// Get the values for `key`, indexed by CPU ID.
+    pub fn get(&self, mut key: K) -> Option<Vec<V>> {
+        let mut values = PerCpuValues::<V>::new(self.cpus);
+        if unsafe {
+            bpf_sys::bpf_lookup_elem(
+                self.base.fd,
+                &mut key as *mut _ as *mut _,
+                values.as_mut_ptr(),
+            )
+        } < 0
+        {
+            return None;
+        }
+        Some(values.to_vec())
+    }
+
+    pub fn delete(&self, mut key: K) {
+        unsafe {
+            bpf_sys::bpf_delete_elem(self.base.fd, &mut key as *mut _ as *mut _);
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> PerCpuMapIter<'a, '_, K, V> {
+        PerCpuMapIter {
+            map: self,
+            key: None,
+        }
+    }
+
+    /// Returns all the entries of the map, with their values indexed by CPU
+    /// ID.
+    ///
+    /// See `HashMap::lookup_batch`.
+    pub fn lookup_batch(&self) -> Result<Vec<(K, Vec<V>)>> {
+        self.lookup_entries(false)
+    }
+
+    /// Removes all the entries of the map and returns them.
+    ///
+    /// See `HashMap::lookup_and_delete_batch`.
+    pub fn lookup_and_delete_batch(&self) -> Result<Vec<(K, Vec<V>)>> {
+        self.lookup_entries(true)
+    }
+
+    /// Sets the values of each of `keys` to the values at the same position
+    /// in `values`.
+    ///
+    /// Every element of `values` must hold one value for every possible CPU,
+    /// like in `set`. See `HashMap::update_batch`.
+    pub fn update_batch(&self, keys: &[K], values: &[Vec<V>]) -> Result<()> {
+        if keys.len() != values.len() {
+            return Err(Error::Map);
+        }
+        let mut buf = Vec::new();
+        for cpu_values in values {
+            buf.extend(PerCpuValues::from_slice(self.cpus, cpu_values)?.buf);
+        }
+        if !batch::update(
+            self.base,
+            keys.as_ptr() as *const _,
+            buf.as_ptr(),
+            keys.len(),
+        )? {
+            for (key, cpu_values) in keys.iter().zip(values) {
+                self.set(key.clone(), cpu_values)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the entries of `keys`.
+    ///
+    /// See `HashMap::delete_batch`.
+    pub fn delete_batch(&self, keys: &[K]) -> Result<()> {
+        if !batch::delete(self.base, keys.as_ptr() as *const _, keys.len())? {
+            for key in keys {
+                delete_elem(self.base, key).map_err(|_| Error::Map)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lookup_entries(&self, delete: bool) -> Result<Vec<(K, Vec<V>)>> {
+        let value_size = PerCpuValues::<V>::stride() * self.cpus;
+        match batch::lookup(self.base, value_size, delete)? {
+            Some(entries) => Ok((0..entries.len())
+                .map(|i| {
+                    let values = PerCpuValues::<V>::from_bytes(self.cpus, entries.value_bytes(i));
+                    (entries.key(i), values.to_vec())
+                })
+                .collect()),
+            None if delete => delete_entries(self.base, self.iter().collect()),
+            None => Ok(self.iter().collect()),
+        }
+    }
+}
+
+/// Buffer holding the values of a per-CPU map entry.
+///
+/// The kernel copies per-CPU values in and out of user-space as an array with
+/// one slot per possible CPU, each slot rounded up to 8 bytes.
+struct PerCpuValues<V> {
+    buf: Vec<u8>,
+    cpus: usize,
+    _v: PhantomData<V>,
+}
+
+impl<V: Clone> PerCpuValues<V> {
+    fn new(cpus: usize) -> Self {
+        PerCpuValues {
+            buf: vec![0; Self::stride() * cpus],
+            cpus,
+            _v: PhantomData,
+        }
+    }
+
+    fn from_slice(cpus: usize, values: &[V]) -> Result<Self> {
+        if values.len() != cpus {
+            return Err(Error::Map);
+        }
+        let mut ret = Self::new(cpus);
+        for (i, value) in values.iter().enumerate() {
+            unsafe {
+                let ptr = ret.buf.as_mut_ptr().add(i * Self::stride()) as *mut V;
+                ptr.write_unaligned(value.clone());
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn from_bytes(cpus: usize, bytes: &[u8]) -> Self {
+        PerCpuValues {
+            buf: bytes.to_vec(),
+            cpus,
+            _v: PhantomData,
+        }
+    }
+
+    fn stride() -> usize {
+        (mem::size_of::<V>() + 7) & !7
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut libc::c_void {
+        self.buf.as_mut_ptr() as *mut _
+    }
+
+    fn to_vec(&self) -> Vec<V> {
+        (0..self.cpus)
+            .map(|i| unsafe {
+                let ptr = self.buf.as_ptr().add(i * Self::stride()) as *const V;
+                ptr.read_unaligned()
+            })
+            .collect()
+    }
+}
+
+pub struct PerCpuMapIter<'a, 'b, K: Clone, V: Clone> {
+    map: &'a PerCpuHashMap<'b, K, V>,
+    key: Option<K>,
+}
+
+impl<K: Clone, V: Clone> Iterator for PerCpuMapIter<'_, '_, K, V> {
+    type Item = (K, Vec<V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.key.take();
+            self.key = next_key(self.map.base, key);
+
+            let key = self.key.as_ref()?.clone();
+            // skip entries deleted since `next_key` returned them
+            if let Some(value) = self.map.get(key.clone()) {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+/// Returns the key following `key` in the map, or the first key if `key` is
+/// `None`.
+fn next_key<K>(map: &Map, key: Option<K>) -> Option<K> {
+    match key {
+        Some(mut key) => {
+            let mut next_key = MaybeUninit::<K>::zeroed();
+            let ret = unsafe {
+                bpf_sys::bpf_get_next_key(
+                    map.fd,
+                    &mut key as *mut _ as *mut _,
+                    &mut next_key as *mut _ as *mut _,
+                )
+            };
+            if ret < 0 {
+                None
+            } else {
+                Some(unsafe { next_key.assume_init() })
+            }
+        }
+        None => {
+            let mut key = MaybeUninit::<K>::zeroed();
+            if unsafe {
+                bpf_sys::bpf_get_first_key(
+                    map.fd,
+                    &mut key as *mut _ as *mut _,
+                    map.config.key_size.into(),
+                )
+            } < 0
+            {
+                None
+            } else {
+                Some(unsafe { key.assume_init() })
+            }
+        }
+    }
+}
+
+pub struct MapIter<'a, 'b, K: Clone, V: Clone> {
+    map: &'a HashMap<'b, K, V>,
+    key: Option<K>,
+}
+
+impl<K: Clone, V: Clone> Iterator for MapIter<'_, '_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let key = self.key.take();
+            self.key = next_key(self.map.base, key);
+
+            let key = self.key.as_ref()?.clone();
+            // skip entries deleted since `next_key` returned them
+            if let Some(value) = self.map.get(key.clone()) {
+                return Some((key, value));
+            }
+        }
+    }
+}
+
+pub struct LpmTrieIter<'a, 'b, K: LpmPrefix, V: Clone> {
+    map: &'a LpmTrie<'b, K, V>,
+    /// The next prefix to return.
+    key: Option<LpmKey<K::Data>>,
+    done: bool,
+}
+
+impl<K: LpmPrefix, V: Clone> Iterator for LpmTrieIter<'_, '_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let base = self.map.base;
+        let key = match self.key.take().or_else(|| next_key(base, None)) {
+            Some(key) => key,
+            None => {
+                self.done = true;
+                return None;
+            }
+        };
+
+        // lookups return the value of the longest prefix matching `key`,
+        // which is another prefix if `key` was deleted
+        let value = self.map.get_key(key);
+        let after = next_key(base, Some(key));
+        let first = next_key(base, None);
+        match value {
+            Some(value) if in_lpm_trie(after.as_ref(), first.as_ref()) => {
+                self.key = after;
+                self.done = after.is_none();
+                Some((K::from_key(&key), value))
+            }
+            _ => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+/// Returns whether the key `next_key` was called with is in the trie, given
+/// the key it returned and the first key of the trie.
+///
+/// For keys that aren't in the trie, the kernel returns the first key, which
+/// never follows a key that is.
+fn in_lpm_trie<T>(after: Option<&LpmKey<T>>, first: Option<&LpmKey<T>>) -> bool {
+    match (after, first) {
+        (Some(after), Some(first)) => !lpm_key_eq(after, first),
+        // the key was the last one
+        (None, Some(_)) => true,
+        // the trie is empty
+        (_, None) => false,
+    }
+}
+
+fn lpm_key_eq<T>(a: &LpmKey<T>, b: &LpmKey<T>) -> bool {
+    let len = mem::size_of::<LpmKey<T>>();
+    unsafe {
+        std::slice::from_raw_parts(a as *const _ as *const u8, len)
+            == std::slice::from_raw_parts(b as *const _ as *const u8, len)
+    }
+}
 
 impl StackTrace<'_> {
     pub fn new(map: &Map) -> StackTrace<'_> {
@@ -1031,6 +3819,10 @@ fn add_relocation(
     }));
 }
 
+fn path_cstring(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
 #[inline]
 fn get_version(bytes: &[u8]) -> u32 {
     let version = zero::read::<u32>(bytes);
@@ -1040,6 +3832,28 @@ fn get_version(bytes: &[u8]) -> u32 {
     }
 }
 
+/// Returns the inner map templates of the maps declared with
+/// `#[map(inner_map = "...")]`, by outer map name.
+fn inner_map_templates<'o>(object: &'o Elf<'_>, bytes: &[u8]) -> RSHashMap<&'o str, String> {
+    object
+        .syms
+        .iter()
+        .filter_map(|sym| {
+            let name = object.strtab.get_unsafe(sym.st_name)?;
+            if !name.starts_with(INNER_MAP_PREFIX) {
+                return None;
+            }
+            let shdr = object.section_headers.get(sym.st_shndx)?;
+            let start = sym.st_value as usize;
+            let template = data(bytes, shdr).get(start..start + sym.st_size as usize)?;
+            Some((
+                &name[INNER_MAP_PREFIX.len()..],
+                String::from_utf8_lossy(template).into_owned(),
+            ))
+        })
+        .collect()
+}
+
 #[inline]
 fn data<'d>(bytes: &'d [u8], shdr: &SectionHeader) -> &'d [u8] {
     let offset = shdr.sh_offset as usize;
@@ -1047,3 +3861,131 @@ fn data<'d>(bytes: &'d [u8], shdr: &SectionHeader) -> &'d [u8] {
 
     &bytes[offset..end]
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lpm_prefix_ipv4() {
+        let net: Ipv4Net = "10.1.2.3/8".parse().unwrap();
+        let key = net.to_key();
+        // host bits are masked
+        assert_eq!(key.prefixlen, 8);
+        assert_eq!(key.data, [10, 0, 0, 0]);
+        assert_eq!(Ipv4Net::from_key(&key), "10.0.0.0/8".parse().unwrap());
+
+        let key = Ipv4Net::addr_key(&"192.168.1.7".parse().unwrap());
+        assert_eq!(key.prefixlen, 32);
+        assert_eq!(key.data, [192, 168, 1, 7]);
+        assert_eq!(Ipv4Net::from_key(&key), "192.168.1.7/32".parse().unwrap());
+    }
+
+    #[test]
+    fn test_lpm_prefix_ipv6() {
+        let net: Ipv6Net = "2001:db8:ff::1/33".parse().unwrap();
+        let key = net.to_key();
+        assert_eq!(key.prefixlen, 33);
+        assert_eq!(
+            key.data,
+            [0x20, 0x01, 0x0d, 0xb8, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(Ipv6Net::from_key(&key), "2001:db8::/33".parse().unwrap());
+
+        let addr = "2001:db8::1".parse().unwrap();
+        let key = Ipv6Net::addr_key(&addr);
+        assert_eq!(key.prefixlen, 128);
+        assert_eq!(Ipv6Net::from_key(&key), Ipv6Net::new(addr, 128).unwrap());
+    }
+
+    #[test]
+    fn test_sk_skb_section() {
+        match Program::new("sk_skb", "stream_parser/parse", &[]).unwrap() {
+            Program::SkSkb(prog) => {
+                assert_eq!(prog.attach_type, BPF_SK_SKB_STREAM_PARSER);
+                assert_eq!(prog.common.name, "parse");
+            }
+            _ => panic!("expected an sk_skb program"),
+        }
+        match Program::new("sk_skb", "stream_verdict/verdict", &[]).unwrap() {
+            Program::SkSkb(prog) => {
+                assert_eq!(prog.attach_type, BPF_SK_SKB_STREAM_VERDICT);
+                assert_eq!(prog.common.name, "verdict");
+            }
+            _ => panic!("expected an sk_skb program"),
+        }
+        assert!(matches!(
+            Program::new("sk_skb", "stream_unknown/prog", &[]),
+            Err(Error::Section(_))
+        ));
+        assert!(matches!(
+            Program::new("sk_skb", "prog", &[]),
+            Err(Error::Section(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_btf_error() {
+        let err = io::Error::from_raw_os_error;
+        assert!(is_btf_error(&err(E2BIG), b""));
+        assert!(is_btf_error(&err(EINVAL), b"Invalid line_info[0]\n\0"));
+        assert!(is_btf_error(
+            &err(EINVAL),
+            b"invalid func info rec size 0\0"
+        ));
+        assert!(!is_btf_error(&err(EINVAL), b"invalid insn idx 3\n\0"));
+        // the log is cut at the first nul byte
+        assert!(!is_btf_error(&err(EINVAL), b"\0BTF"));
+        // verifier rejections are never retried without BTF
+        assert!(!is_btf_error(
+            &err(libc::EACCES),
+            b"R0 !read_ok\nprocessed 1 insns\n\0"
+        ));
+        assert!(!is_btf_error(&err(libc::EACCES), b"func_info BTF\0"));
+    }
+
+    #[test]
+    fn test_check_pinned_map() {
+        let config = bpf_map_def {
+            type_: bpf_sys::bpf_map_type_BPF_MAP_TYPE_HASH,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 1024,
+            map_flags: 0,
+        };
+        assert!(check_pinned_map("counts", &config, &config).is_ok());
+
+        let pinned = bpf_map_def {
+            value_size: 16,
+            ..config
+        };
+        match check_pinned_map("counts", &pinned, &config) {
+            Err(Error::PinnedMapMismatch {
+                map,
+                field,
+                pinned,
+                expected,
+            }) => {
+                assert_eq!(map, "counts");
+                assert_eq!(field, "value_size");
+                assert_eq!(pinned, 16);
+                assert_eq!(expected, 8);
+            }
+            _ => panic!("expected a value_size mismatch"),
+        }
+    }
+
+    #[test]
+    fn test_in_lpm_trie() {
+        let key = |prefixlen, data| LpmKey::<[u8; 4]> { prefixlen, data };
+        let first = key(24, [10, 1, 2, 0]);
+        let covering = key(16, [10, 1, 0, 0]);
+        assert!(in_lpm_trie(Some(&covering), Some(&first)));
+        assert!(in_lpm_trie(None, Some(&first)));
+        // the kernel restarted from the first key
+        assert!(!in_lpm_trie(Some(&first), Some(&first)));
+        assert!(!in_lpm_trie::<[u8; 4]>(None, None));
+        assert!(lpm_key_eq(&first, &key(24, [10, 1, 2, 0])));
+        assert!(!lpm_key_eq(&first, &key(16, [10, 1, 2, 0])));
+    }
+}
@@ -12,9 +12,13 @@ use std::fs;
 use std::io;
 use std::path::Path;
 
-use crate::{Program, cpus};
-use crate::load::map_io::PerfMessageStream;
-use crate::{Error, KProbe, Map, Module, PerfMap, SocketFilter, UProbe, XDP};
+use crate::load::map_io::{PerfMessageStream, RingBufStream};
+use crate::{cpus, raise_memlock_limit, Program};
+use crate::{
+    BtfTracePoint, Cgroup, Error, FEntry, KProbe, Lsm, Map, Module, PerfEvent, PerfMap,
+    RawTracePoint, RingBuf, SkMsg, SkSkb, SocketFilter, TcAction, TracePoint, UProbe,
+    BPF_MAP_TYPE_RINGBUF, XDP,
+};
 
 #[derive(Debug)]
 pub enum LoaderError {
@@ -30,8 +34,14 @@ impl Loader {
     /// Loads the programs included in `data`.
     ///
     /// This will parse `data` with `Module::parse()` and load all the programs
-    /// present in the module.
+    /// present in the module. The `RLIMIT_MEMLOCK` limit of the process is
+    /// lifted first, see `raise_memlock_limit()`.
+    ///
+    /// Fails with `LoaderError::LoadError` naming the program that couldn't
+    /// be loaded, or the `RingBuf` map that couldn't be mapped.
     pub fn load(data: &[u8]) -> Result<Loaded, LoaderError> {
+        // without privileges loading fails anyway, with a clearer error
+        let _ = raise_memlock_limit();
         let mut module = Module::parse(&data).map_err(LoaderError::ParseError)?;
         for program in module.programs.iter_mut() {
             program
@@ -55,6 +65,24 @@ impl Loader {
             }
         }
 
+        for m in module
+            .maps
+            .iter()
+            .filter(|m| m.kind == BPF_MAP_TYPE_RINGBUF)
+        {
+            let name = m.name.clone();
+            let ringbuf = RingBuf::bind(m).map_err(|e| LoaderError::LoadError(name.clone(), e))?;
+            let stream = RingBufStream::new(ringbuf);
+            let mut s = sender.clone();
+            let fut = stream.for_each(move |events| {
+                if !events.is_empty() {
+                    s.start_send((name.clone(), events)).unwrap();
+                }
+                future::ready(())
+            });
+            tokio::spawn(fut);
+        }
+
         Ok(Loaded {
             module,
             events: receiver,
@@ -74,6 +102,9 @@ pub struct Loaded {
     pub module: Module,
     /// The stream of events emitted by the BPF programs.
     ///
+    /// Events sent to both `PerfMap` and `RingBuf` maps are delivered here,
+    /// tagged with the name of the map they were sent to.
+    ///
     /// # Example
     ///
     /// ```no_run
@@ -120,4 +151,44 @@ impl Loaded {
     pub fn socket_filters_mut(&mut self) -> impl Iterator<Item = &mut SocketFilter> {
         self.module.socket_filters_mut()
     }
+
+    pub fn trace_points_mut(&mut self) -> impl Iterator<Item = &mut TracePoint> {
+        self.module.trace_points_mut()
+    }
+
+    pub fn tc_actions_mut(&mut self) -> impl Iterator<Item = &mut TcAction> {
+        self.module.tc_actions_mut()
+    }
+
+    pub fn raw_trace_points_mut(&mut self) -> impl Iterator<Item = &mut RawTracePoint> {
+        self.module.raw_trace_points_mut()
+    }
+
+    pub fn btf_trace_points_mut(&mut self) -> impl Iterator<Item = &mut BtfTracePoint> {
+        self.module.btf_trace_points_mut()
+    }
+
+    pub fn fentries_mut(&mut self) -> impl Iterator<Item = &mut FEntry> {
+        self.module.fentries_mut()
+    }
+
+    pub fn cgroups_mut(&mut self) -> impl Iterator<Item = &mut Cgroup> {
+        self.module.cgroups_mut()
+    }
+
+    pub fn sk_msgs_mut(&mut self) -> impl Iterator<Item = &mut SkMsg> {
+        self.module.sk_msgs_mut()
+    }
+
+    pub fn sk_skbs_mut(&mut self) -> impl Iterator<Item = &mut SkSkb> {
+        self.module.sk_skbs_mut()
+    }
+
+    pub fn perf_events_mut(&mut self) -> impl Iterator<Item = &mut PerfEvent> {
+        self.module.perf_events_mut()
+    }
+
+    pub fn lsms_mut(&mut self) -> impl Iterator<Item = &mut Lsm> {
+        self.module.lsms_mut()
+    }
 }
@@ -15,7 +15,7 @@ use std::slice;
 use std::task::{Context, Poll};
 use tokio::io::PollEvented;
 
-use crate::{Event, PerfMap};
+use crate::{Event, PerfMap, RingBuf};
 
 pub struct MapIo(RawFd);
 
@@ -94,3 +94,42 @@ impl Stream for PerfMessageStream {
         Poll::Ready(Some(messages))
     }
 }
+
+pub struct RingBufStream {
+    poll: PollEvented<MapIo>,
+    ringbuf: RingBuf,
+}
+
+impl RingBufStream {
+    pub fn new(ringbuf: RingBuf) -> Self {
+        let io = MapIo(ringbuf.fd);
+        let poll = PollEvented::new(io).unwrap();
+        RingBufStream { poll, ringbuf }
+    }
+
+    fn read_messages(&mut self) -> Vec<Box<[u8]>> {
+        let mut ret = Vec::new();
+        while let Some(msg) = self.ringbuf.read() {
+            ret.push(msg);
+        }
+
+        ret
+    }
+}
+
+impl Stream for RingBufStream {
+    type Item = Vec<Box<[u8]>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let ready = Ready::readable();
+        if let Poll::Pending = self.poll.poll_read_ready(cx, ready) {
+            return Poll::Pending;
+        }
+
+        // clear readiness before draining, so records committed while
+        // reading trigger a new wakeup
+        self.poll.clear_read_ready(cx, ready).unwrap();
+        let messages = self.read_messages();
+        Poll::Ready(Some(messages))
+    }
+}
@@ -91,6 +91,38 @@ unsafe fn open_perf_buffer(pid: i32, cpu: i32, group: RawFd, flags: u32) -> Resu
     }
 }
 
+/// Opens a software CPU clock event sampling `freq` times per second on
+/// `cpu`, and attaches the program `prog_fd` to it.
+pub(crate) unsafe fn attach_sampling_event(prog_fd: RawFd, freq: u64, cpu: i32) -> Result<RawFd> {
+    let mut attr = mem::zeroed::<perf_event_attr>();
+
+    attr.config = perf_sw_ids_PERF_COUNT_SW_CPU_CLOCK as u64;
+    attr.size = mem::size_of::<perf_event_attr>() as u32;
+    attr.type_ = perf_type_id_PERF_TYPE_SOFTWARE;
+    attr.set_freq(1);
+    attr.__bindgen_anon_1.sample_freq = freq;
+
+    let pfd = syscall(
+        SYS_perf_event_open,
+        &attr as *const perf_event_attr,
+        -1,
+        cpu,
+        -1,
+        PERF_FLAG_FD_CLOEXEC,
+    ) as RawFd;
+    if pfd < 0 {
+        return Err(Error::IO(io::Error::last_os_error()));
+    }
+    if ioctl(pfd, PERF_EVENT_IOC_SET_BPF, prog_fd) != 0 || ioctl(pfd, PERF_EVENT_IOC_ENABLE, 0) != 0
+    {
+        let err = io::Error::last_os_error();
+        close(pfd);
+        return Err(Error::IO(err));
+    }
+
+    Ok(pfd)
+}
+
 #[repr(C)]
 pub struct Sample {
     header: perf_event_header,
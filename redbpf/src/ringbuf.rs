@@ -0,0 +1,162 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # Ring buffer handling
+//!
+//! `BPF_MAP_TYPE_RINGBUF` maps are a single buffer shared by all CPUs. The
+//! consumer position is mapped read-write, while the producer position and
+//! the data pages are mapped read-only. The data pages are mapped twice in a
+//! row so records that wrap around the end of the buffer can be read
+//! contiguously.
+//!
+//! ```no_run
+//! use redbpf::{Map, RingBuf};
+//!
+//! // maps are usually automatically loaded with ELF objects
+//! let map = Map::load("my_ring_buf", &vec![]).unwrap();
+//!
+//! let ringbuf = RingBuf::bind(&map).unwrap();
+//! while let Some(sample) = ringbuf.read() {
+//!     // do something with the sample
+//! }
+//! ```
+#![allow(clippy::cast_ptr_alignment)]
+
+use crate::{Error, Map, Result};
+use std::io;
+use std::os::unix::io::RawFd;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+use libc::{
+    c_void, mmap, munmap, sysconf, _SC_PAGESIZE, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE,
+};
+
+/// The `BPF_MAP_TYPE_RINGBUF` map type, missing from pre-5.8 kernel headers.
+pub const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HDR_SZ: usize = 8;
+
+pub struct RingBuf {
+    consumer: AtomicPtr<c_void>,
+    producer: AtomicPtr<c_void>,
+    mask: u64,
+    page_size: usize,
+    pub fd: RawFd,
+}
+
+impl RingBuf {
+    /// Map the ring buffer `map` in memory.
+    pub fn bind(map: &Map) -> Result<RingBuf> {
+        if map.kind != BPF_MAP_TYPE_RINGBUF {
+            return Err(Error::Map);
+        }
+        let size = map.config.max_entries as usize;
+        unsafe {
+            let page_size = sysconf(_SC_PAGESIZE) as usize;
+            let consumer = mmap(
+                null_mut(),
+                page_size,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                map.fd,
+                0,
+            );
+            if consumer == MAP_FAILED {
+                return Err(Error::IO(io::Error::last_os_error()));
+            }
+
+            let producer = mmap(
+                null_mut(),
+                page_size + 2 * size,
+                PROT_READ,
+                MAP_SHARED,
+                map.fd,
+                page_size as i64,
+            );
+            if producer == MAP_FAILED {
+                let err = io::Error::last_os_error();
+                munmap(consumer, page_size);
+                return Err(Error::IO(err));
+            }
+
+            Ok(RingBuf {
+                consumer: AtomicPtr::new(consumer),
+                producer: AtomicPtr::new(producer),
+                mask: size as u64 - 1,
+                page_size,
+                fd: map.fd,
+            })
+        }
+    }
+
+    /// Read the next record, if any.
+    ///
+    /// Records discarded by the BPF program are skipped. The record is copied
+    /// out of the buffer, so its space is released to the producers before
+    /// `read()` returns.
+    pub fn read(&self) -> Option<Box<[u8]>> {
+        unsafe {
+            let consumer = self.consumer.load(Ordering::SeqCst);
+            let producer = self.producer.load(Ordering::SeqCst);
+            let consumer_pos = &*(consumer as *const AtomicU64);
+            let producer_pos = &*(producer as *const AtomicU64);
+            let data = (producer as *const u8).add(self.page_size);
+            let mut cons = consumer_pos.load(Ordering::Acquire);
+            loop {
+                let prod = producer_pos.load(Ordering::Acquire);
+                if cons >= prod {
+                    return None;
+                }
+
+                let hdr = data.add((cons & self.mask) as usize);
+                let len = (*(hdr as *const AtomicU32)).load(Ordering::Acquire);
+                if len & BPF_RINGBUF_BUSY_BIT != 0 {
+                    // the record is still being written
+                    return None;
+                }
+
+                let size = (len & !BPF_RINGBUF_DISCARD_BIT) as usize;
+                let sample = if len & BPF_RINGBUF_DISCARD_BIT == 0 {
+                    let data = hdr.add(BPF_RINGBUF_HDR_SZ);
+                    Some(
+                        std::slice::from_raw_parts(data, size)
+                            .to_vec()
+                            .into_boxed_slice(),
+                    )
+                } else {
+                    None
+                };
+
+                cons += round_up(size + BPF_RINGBUF_HDR_SZ) as u64;
+                consumer_pos.store(cons, Ordering::Release);
+
+                if sample.is_some() {
+                    return sample;
+                }
+            }
+        }
+    }
+}
+
+fn round_up(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+impl Drop for RingBuf {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.consumer.load(Ordering::SeqCst), self.page_size);
+            munmap(
+                self.producer.load(Ordering::SeqCst),
+                self.page_size + 2 * (self.mask as usize + 1),
+            );
+        }
+    }
+}
@@ -0,0 +1,410 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Wrappers for the `bpf(2)` commands that aren't exposed by `bpf_sys`.
+//!
+//! The attribute structs mirror the corresponding members of `union
+//! bpf_attr`, up to the last field used by redbpf.
+#![allow(non_camel_case_types)]
+
+use std::ffi::CStr;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc::{c_void, syscall, SYS_bpf};
+
+pub const BPF_MAP_CREATE: u32 = 0;
+pub const BPF_MAP_LOOKUP_ELEM: u32 = 1;
+pub const BPF_MAP_UPDATE_ELEM: u32 = 2;
+pub const BPF_PROG_LOAD: u32 = 5;
+pub const BPF_OBJ_PIN: u32 = 6;
+pub const BPF_OBJ_GET: u32 = 7;
+pub const BPF_PROG_ATTACH: u32 = 8;
+pub const BPF_PROG_DETACH: u32 = 9;
+pub const BPF_PROG_TEST_RUN: u32 = 10;
+pub const BPF_OBJ_GET_INFO_BY_FD: u32 = 15;
+pub const BPF_RAW_TRACEPOINT_OPEN: u32 = 17;
+pub const BPF_BTF_LOAD: u32 = 18;
+pub const BPF_MAP_LOOKUP_AND_DELETE_ELEM: u32 = 21;
+pub const BPF_MAP_LOOKUP_BATCH: u32 = 24;
+pub const BPF_MAP_LOOKUP_AND_DELETE_BATCH: u32 = 25;
+pub const BPF_MAP_UPDATE_BATCH: u32 = 26;
+pub const BPF_MAP_DELETE_BATCH: u32 = 27;
+
+pub const BPF_PROG_TYPE_CGROUP_SKB: u32 = 8;
+pub const BPF_PROG_TYPE_CGROUP_SOCK: u32 = 9;
+pub const BPF_PROG_TYPE_SOCK_OPS: u32 = 13;
+pub const BPF_PROG_TYPE_SK_SKB: u32 = 14;
+pub const BPF_PROG_TYPE_SK_MSG: u32 = 16;
+pub const BPF_PROG_TYPE_RAW_TRACEPOINT: u32 = 17;
+pub const BPF_PROG_TYPE_CGROUP_SOCK_ADDR: u32 = 18;
+pub const BPF_PROG_TYPE_TRACING: u32 = 26;
+pub const BPF_PROG_TYPE_LSM: u32 = 29;
+
+pub const BPF_CGROUP_INET_INGRESS: u32 = 0;
+pub const BPF_CGROUP_INET_EGRESS: u32 = 1;
+pub const BPF_CGROUP_INET_SOCK_CREATE: u32 = 2;
+pub const BPF_CGROUP_SOCK_OPS: u32 = 3;
+pub const BPF_SK_SKB_STREAM_PARSER: u32 = 4;
+pub const BPF_SK_SKB_STREAM_VERDICT: u32 = 5;
+pub const BPF_SK_MSG_VERDICT: u32 = 7;
+pub const BPF_CGROUP_INET4_BIND: u32 = 8;
+pub const BPF_CGROUP_INET6_BIND: u32 = 9;
+pub const BPF_CGROUP_INET4_CONNECT: u32 = 10;
+pub const BPF_CGROUP_INET6_CONNECT: u32 = 11;
+pub const BPF_CGROUP_INET4_POST_BIND: u32 = 12;
+pub const BPF_CGROUP_INET6_POST_BIND: u32 = 13;
+pub const BPF_CGROUP_UDP4_SENDMSG: u32 = 14;
+pub const BPF_CGROUP_UDP6_SENDMSG: u32 = 15;
+pub const BPF_CGROUP_UDP4_RECVMSG: u32 = 19;
+pub const BPF_CGROUP_UDP6_RECVMSG: u32 = 20;
+pub const BPF_TRACE_RAW_TP: u32 = 23;
+pub const BPF_TRACE_FENTRY: u32 = 24;
+pub const BPF_TRACE_FEXIT: u32 = 25;
+pub const BPF_LSM_MAC: u32 = 27;
+pub const BPF_CGROUP_INET4_GETPEERNAME: u32 = 29;
+pub const BPF_CGROUP_INET6_GETPEERNAME: u32 = 30;
+pub const BPF_CGROUP_INET4_GETSOCKNAME: u32 = 31;
+pub const BPF_CGROUP_INET6_GETSOCKNAME: u32 = 32;
+pub const BPF_CGROUP_INET_SOCK_RELEASE: u32 = 34;
+
+pub const BPF_EXIST: u64 = 2;
+
+pub const BPF_F_ALLOW_OVERRIDE: u32 = 1;
+pub const BPF_F_ALLOW_MULTI: u32 = 2;
+
+pub const BPF_OBJ_NAME_LEN: usize = 16;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_map_create_attr {
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32,
+    pub inner_map_fd: u32,
+    pub numa_node: u32,
+    pub map_name: [u8; BPF_OBJ_NAME_LEN],
+    pub map_ifindex: u32,
+    pub btf_fd: u32,
+    pub btf_key_type_id: u32,
+    pub btf_value_type_id: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_prog_load_attr {
+    pub prog_type: u32,
+    pub insn_cnt: u32,
+    pub insns: u64,
+    pub license: u64,
+    pub log_level: u32,
+    pub log_size: u32,
+    pub log_buf: u64,
+    pub kern_version: u32,
+    pub prog_flags: u32,
+    pub prog_name: [u8; BPF_OBJ_NAME_LEN],
+    pub prog_ifindex: u32,
+    pub expected_attach_type: u32,
+    pub prog_btf_fd: u32,
+    pub func_info_rec_size: u32,
+    pub func_info: u64,
+    pub func_info_cnt: u32,
+    pub line_info_rec_size: u32,
+    pub line_info: u64,
+    pub line_info_cnt: u32,
+    pub attach_btf_id: u32,
+    pub attach_prog_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_btf_load_attr {
+    pub btf: u64,
+    pub btf_log_buf: u64,
+    pub btf_size: u32,
+    pub btf_log_size: u32,
+    pub btf_log_level: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_map_elem_attr {
+    pub map_fd: u32,
+    pub key: u64,
+    pub value: u64,
+    pub flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_map_batch_attr {
+    pub in_batch: u64,
+    pub out_batch: u64,
+    pub keys: u64,
+    pub values: u64,
+    pub count: u32,
+    pub map_fd: u32,
+    pub elem_flags: u64,
+    pub flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_obj_attr {
+    pub pathname: u64,
+    pub bpf_fd: u32,
+    pub file_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_prog_attach_attr {
+    pub target_fd: u32,
+    pub attach_bpf_fd: u32,
+    pub attach_type: u32,
+    pub attach_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_test_run_attr {
+    pub prog_fd: u32,
+    pub retval: u32,
+    pub data_size_in: u32,
+    pub data_size_out: u32,
+    pub data_in: u64,
+    pub data_out: u64,
+    pub repeat: u32,
+    pub duration: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_raw_tracepoint_attr {
+    pub name: u64,
+    pub prog_fd: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_info_attr {
+    pub bpf_fd: u32,
+    pub info_len: u32,
+    pub info: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct bpf_map_info {
+    pub type_: u32,
+    pub id: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32,
+    pub name: [u8; BPF_OBJ_NAME_LEN],
+}
+
+/// Issues the `bpf(2)` command `cmd` with the given attributes.
+pub fn sys_bpf<T>(cmd: u32, attr: &T) -> io::Result<RawFd> {
+    bpf(cmd, attr as *const T as *const c_void, mem::size_of::<T>())
+}
+
+/// Like `sys_bpf`, for the commands that write their results back to `attr`.
+pub fn sys_bpf_mut<T>(cmd: u32, attr: &mut T) -> io::Result<RawFd> {
+    bpf(cmd, attr as *mut T as *const c_void, mem::size_of::<T>())
+}
+
+fn bpf(cmd: u32, attr: *const c_void, size: usize) -> io::Result<RawFd> {
+    let ret = unsafe { syscall(SYS_bpf, cmd, attr, size as u32) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as RawFd)
+}
+
+/// Returns `name` as a BPF object name.
+///
+/// Names are truncated to fit `BPF_OBJ_NAME_LEN`. Since the kernel only
+/// accepts alphanumeric characters, `_` and `.` in names, an empty name is
+/// returned if `name` contains anything else.
+pub fn obj_name(name: &str) -> [u8; BPF_OBJ_NAME_LEN] {
+    let mut ret = [0u8; BPF_OBJ_NAME_LEN];
+    if name
+        .bytes()
+        .all(|c| c.is_ascii_alphanumeric() || c == b'_' || c == b'.')
+    {
+        let len = name.len().min(BPF_OBJ_NAME_LEN - 1);
+        ret[..len].copy_from_slice(&name.as_bytes()[..len]);
+    }
+
+    ret
+}
+
+/// Pushes `value` into the queue, stack or bloom filter map `fd`.
+pub fn map_push_elem(fd: RawFd, value: *const c_void, flags: u64) -> io::Result<()> {
+    let attr = bpf_map_elem_attr {
+        map_fd: fd as u32,
+        value: value as u64,
+        flags,
+        ..Default::default()
+    };
+    sys_bpf(BPF_MAP_UPDATE_ELEM, &attr).map(|_| ())
+}
+
+/// Copies the next element of the queue or stack map `fd` to `value`.
+///
+/// For bloom filters, `value` is the element to check for instead, and the
+/// call fails with `ENOENT` if it's not in the filter.
+pub fn map_peek_elem(fd: RawFd, value: *mut c_void) -> io::Result<()> {
+    let attr = bpf_map_elem_attr {
+        map_fd: fd as u32,
+        value: value as u64,
+        ..Default::default()
+    };
+    sys_bpf(BPF_MAP_LOOKUP_ELEM, &attr).map(|_| ())
+}
+
+/// Removes the next element of the queue or stack map `fd` and copies it to
+/// `value`.
+pub fn map_pop_elem(fd: RawFd, value: *mut c_void) -> io::Result<()> {
+    let attr = bpf_map_elem_attr {
+        map_fd: fd as u32,
+        value: value as u64,
+        ..Default::default()
+    };
+    sys_bpf(BPF_MAP_LOOKUP_AND_DELETE_ELEM, &attr).map(|_| ())
+}
+
+/// Runs the batch command `cmd` on up to `count` entries of the map `fd`.
+///
+/// The kernel reports how many entries it processed even when the command
+/// fails part way through, so the count is returned along with the result.
+/// The count is left untouched if the command is rejected outright.
+pub fn map_batch(
+    cmd: u32,
+    fd: RawFd,
+    in_batch: *const c_void,
+    out_batch: *mut c_void,
+    keys: *mut c_void,
+    values: *mut c_void,
+    count: u32,
+) -> (u32, io::Result<()>) {
+    let mut attr = bpf_map_batch_attr {
+        in_batch: in_batch as u64,
+        out_batch: out_batch as u64,
+        keys: keys as u64,
+        values: values as u64,
+        count,
+        map_fd: fd as u32,
+        ..Default::default()
+    };
+    let ret = sys_bpf_mut(cmd, &mut attr).map(|_| ());
+
+    (attr.count, ret)
+}
+
+/// Pins the object `fd` to `path` on a bpffs filesystem.
+pub fn obj_pin(fd: RawFd, path: &CStr) -> io::Result<()> {
+    let attr = bpf_obj_attr {
+        pathname: path.as_ptr() as u64,
+        bpf_fd: fd as u32,
+        ..Default::default()
+    };
+    sys_bpf(BPF_OBJ_PIN, &attr).map(|_| ())
+}
+
+/// Opens the object pinned at `path`.
+pub fn obj_get(path: &CStr) -> io::Result<RawFd> {
+    let attr = bpf_obj_attr {
+        pathname: path.as_ptr() as u64,
+        ..Default::default()
+    };
+    sys_bpf(BPF_OBJ_GET, &attr)
+}
+
+/// Returns the attributes of the map `fd`.
+pub fn map_info(fd: RawFd) -> io::Result<bpf_map_info> {
+    let mut info = bpf_map_info::default();
+    let mut attr = bpf_info_attr {
+        bpf_fd: fd as u32,
+        info_len: mem::size_of::<bpf_map_info>() as u32,
+        info: &mut info as *mut _ as u64,
+    };
+    sys_bpf_mut(BPF_OBJ_GET_INFO_BY_FD, &mut attr)?;
+
+    Ok(info)
+}
+
+/// Attaches the program `prog_fd` to the raw tracepoint `name`.
+///
+/// `fentry` and `fexit` programs are attached with no `name`, to the function
+/// they were loaded for. The program is detached when the returned file
+/// descriptor is closed.
+pub fn raw_tracepoint_open(name: Option<&CStr>, prog_fd: RawFd) -> io::Result<RawFd> {
+    let attr = bpf_raw_tracepoint_attr {
+        name: name.map(|name| name.as_ptr() as u64).unwrap_or(0),
+        prog_fd: prog_fd as u32,
+    };
+    sys_bpf(BPF_RAW_TRACEPOINT_OPEN, &attr)
+}
+
+/// Attaches the program `prog_fd` to `target_fd`, a cgroup or a map.
+pub fn prog_attach(
+    target_fd: RawFd,
+    prog_fd: RawFd,
+    attach_type: u32,
+    flags: u32,
+) -> io::Result<()> {
+    let attr = bpf_prog_attach_attr {
+        target_fd: target_fd as u32,
+        attach_bpf_fd: prog_fd as u32,
+        attach_type,
+        attach_flags: flags,
+    };
+    sys_bpf(BPF_PROG_ATTACH, &attr).map(|_| ())
+}
+
+/// Detaches the program `prog_fd` attached with `prog_attach`.
+pub fn prog_detach(target_fd: RawFd, prog_fd: RawFd, attach_type: u32) -> io::Result<()> {
+    let attr = bpf_prog_attach_attr {
+        target_fd: target_fd as u32,
+        attach_bpf_fd: prog_fd as u32,
+        attach_type,
+        ..Default::default()
+    };
+    sys_bpf(BPF_PROG_DETACH, &attr).map(|_| ())
+}
+
+/// Runs the program `prog_fd` `repeat` times on `data_in`.
+///
+/// The packet as modified by the program is written to `data_out`. The
+/// returned attributes hold the return value of the program, the size of the
+/// output packet and the average duration of a run in nanoseconds.
+pub fn prog_test_run(
+    prog_fd: RawFd,
+    data_in: &[u8],
+    data_out: &mut [u8],
+    repeat: u32,
+) -> io::Result<bpf_test_run_attr> {
+    let mut attr = bpf_test_run_attr {
+        prog_fd: prog_fd as u32,
+        data_size_in: data_in.len() as u32,
+        data_size_out: data_out.len() as u32,
+        data_in: data_in.as_ptr() as u64,
+        data_out: data_out.as_mut_ptr() as u64,
+        repeat,
+        ..Default::default()
+    };
+    sys_bpf_mut(BPF_PROG_TEST_RUN, &mut attr)?;
+
+    Ok(attr)
+}
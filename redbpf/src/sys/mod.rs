@@ -6,3 +6,4 @@
 // copied, modified, or distributed except according to those terms.
 
 pub mod perf;
+pub(crate) mod bpf;
@@ -0,0 +1,448 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Traffic control (`tc`) attachment.
+//!
+//! `tc` programs are attached as direct-action `bpf` filters of a `clsact`
+//! qdisc, the same way `tc filter add dev <iface> ingress bpf da obj ...`
+//! does. Qdiscs and filters are configured over rtnetlink.
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use libc::{
+    bind, c_void, close, if_nametoindex, nlmsgerr, nlmsghdr, recv, send, sockaddr, sockaddr_nl,
+    socket, AF_NETLINK, AF_UNSPEC, EEXIST, ETH_P_ALL, NETLINK_ROUTE, NLA_F_NESTED, NLMSG_ERROR,
+    NLM_F_ACK, NLM_F_CREATE, NLM_F_ECHO, NLM_F_EXCL, NLM_F_REQUEST, RTM_DELTFILTER, RTM_NEWQDISC,
+    RTM_NEWTFILTER, SOCK_CLOEXEC, SOCK_RAW,
+};
+
+use crate::{Error, Result};
+
+const TC_H_CLSACT: u32 = 0xFFFF_FFF1;
+const TC_H_MIN_INGRESS: u32 = 0xFFF2;
+const TC_H_MIN_EGRESS: u32 = 0xFFF3;
+const TC_H_MAJ_MASK: u32 = 0xFFFF_0000;
+const TC_H_MIN_MASK: u32 = 0x0000_FFFF;
+
+const TCA_KIND: u16 = 1;
+const TCA_OPTIONS: u16 = 2;
+const TCA_BPF_FD: u16 = 6;
+const TCA_BPF_NAME: u16 = 7;
+const TCA_BPF_FLAGS: u16 = 8;
+const TCA_BPF_FLAG_ACT_DIRECT: u32 = 1;
+
+const NLMSG_ALIGNTO: usize = 4;
+const RTA_ALIGNTO: usize = 4;
+
+/// Where to attach a `tc` program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttachPoint {
+    /// Run the program on packets received by the interface.
+    Ingress,
+    /// Run the program on packets sent by the interface.
+    Egress,
+}
+
+impl AttachPoint {
+    fn parent(self) -> u32 {
+        let minor = match self {
+            AttachPoint::Ingress => TC_H_MIN_INGRESS,
+            AttachPoint::Egress => TC_H_MIN_EGRESS,
+        };
+        tc_h_make(TC_H_CLSACT, minor)
+    }
+}
+
+/// A `bpf` filter installed by `attach`.
+#[derive(Debug)]
+pub(crate) struct Filter {
+    ifindex: i32,
+    point: AttachPoint,
+    priority: u16,
+    handle: u32,
+}
+
+/// Resolves the index of the network interface called `interface`.
+pub(crate) fn ifindex(interface: &str) -> Result<i32> {
+    let ciface = CString::new(interface)?;
+    let index = unsafe { if_nametoindex(ciface.as_ptr()) };
+    if index == 0 {
+        return Err(Error::IO(io::Error::last_os_error()));
+    }
+
+    Ok(index as i32)
+}
+
+/// Attaches the program `fd` to `interface` as a direct-action filter.
+///
+/// A `clsact` qdisc is added to the interface unless one already exists.
+/// The kernel picks the handle of the filter, so several programs can be
+/// attached with the same priority.
+pub(crate) fn attach(
+    interface: &str,
+    point: AttachPoint,
+    priority: u16,
+    fd: RawFd,
+    name: &str,
+) -> Result<Filter> {
+    let ifindex = ifindex(interface)?;
+    let sock = NetlinkSocket::open()?;
+
+    let mut msg = Message::new(RTM_NEWQDISC, NLM_F_CREATE | NLM_F_EXCL);
+    msg.tcmsg(ifindex, tc_h_make(TC_H_CLSACT, 0), TC_H_CLSACT, 0);
+    msg.attr_str(TCA_KIND, "clsact");
+    match sock.request(msg) {
+        Err(Error::IO(e)) if e.raw_os_error() == Some(EEXIST) => {}
+        ret => ret?,
+    }
+
+    let msg = new_filter(ifindex, point, priority, fd, name);
+    let mut handle = None;
+    sock.request_with(msg, |kind, payload| {
+        if kind == RTM_NEWTFILTER {
+            handle = filter_handle(payload);
+        }
+    })?;
+    // the kernel echoes the filter it created, with its handle
+    let handle = handle.ok_or_else(|| {
+        Error::IO(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the kernel didn't echo the new filter",
+        ))
+    })?;
+
+    Ok(Filter {
+        ifindex,
+        point,
+        priority,
+        handle,
+    })
+}
+
+/// Removes a filter previously installed by `attach`.
+///
+/// The `clsact` qdisc is left in place, as other filters may still be using
+/// it.
+pub(crate) fn detach(filter: &Filter) -> Result<()> {
+    let sock = NetlinkSocket::open()?;
+    sock.request(delete_filter(filter))
+}
+
+fn new_filter(ifindex: i32, point: AttachPoint, priority: u16, fd: RawFd, name: &str) -> Message {
+    let mut msg = Message::new(RTM_NEWTFILTER, NLM_F_CREATE | NLM_F_EXCL | NLM_F_ECHO);
+    msg.tcmsg(ifindex, 0, point.parent(), filter_info(priority));
+    msg.attr_str(TCA_KIND, "bpf");
+    let options = msg.begin_nested(TCA_OPTIONS);
+    msg.attr(TCA_BPF_FD, &(fd as u32).to_ne_bytes());
+    msg.attr_str(TCA_BPF_NAME, name);
+    msg.attr(TCA_BPF_FLAGS, &TCA_BPF_FLAG_ACT_DIRECT.to_ne_bytes());
+    msg.end_nested(options);
+    msg
+}
+
+/// Deletes only `filter`, and not the other filters with the same priority.
+fn delete_filter(filter: &Filter) -> Message {
+    let mut msg = Message::new(RTM_DELTFILTER, 0);
+    msg.tcmsg(
+        filter.ifindex,
+        filter.handle,
+        filter.point.parent(),
+        filter_info(filter.priority),
+    );
+    msg
+}
+
+/// Returns the handle in the `tcmsg` at the start of `payload`.
+fn filter_handle(payload: &[u8]) -> Option<u32> {
+    if payload.len() < mem::size_of::<tcmsg>() {
+        return None;
+    }
+    let msg = unsafe { (payload.as_ptr() as *const tcmsg).read_unaligned() };
+    Some(msg.tcm_handle)
+}
+
+fn tc_h_make(major: u32, minor: u32) -> u32 {
+    (major & TC_H_MAJ_MASK) | (minor & TC_H_MIN_MASK)
+}
+
+fn filter_info(priority: u16) -> u32 {
+    tc_h_make((priority as u32) << 16, (ETH_P_ALL as u16).to_be() as u32)
+}
+
+fn align(len: usize, to: usize) -> usize {
+    (len + to - 1) & !(to - 1)
+}
+
+#[repr(C)]
+struct tcmsg {
+    tcm_family: u8,
+    tcm_pad1: u8,
+    tcm_pad2: u16,
+    tcm_ifindex: i32,
+    tcm_handle: u32,
+    tcm_parent: u32,
+    tcm_info: u32,
+}
+
+#[repr(C)]
+struct rtattr {
+    rta_len: u16,
+    rta_type: u16,
+}
+
+struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    fn new(kind: u16, flags: i32) -> Message {
+        let mut msg = Message { buf: Vec::new() };
+        let hdr = nlmsghdr {
+            nlmsg_len: 0,
+            nlmsg_type: kind,
+            nlmsg_flags: (NLM_F_REQUEST | NLM_F_ACK | flags) as u16,
+            nlmsg_seq: 1,
+            nlmsg_pid: 0,
+        };
+        msg.push(&hdr);
+        msg
+    }
+
+    fn push<T>(&mut self, value: &T) {
+        let bytes = unsafe {
+            std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+        };
+        self.push_bytes(bytes, NLMSG_ALIGNTO);
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8], alignment: usize) {
+        self.buf.extend_from_slice(bytes);
+        self.buf.resize(align(self.buf.len(), alignment), 0);
+    }
+
+    fn tcmsg(&mut self, ifindex: i32, handle: u32, parent: u32, info: u32) {
+        self.push(&tcmsg {
+            tcm_family: AF_UNSPEC as u8,
+            tcm_pad1: 0,
+            tcm_pad2: 0,
+            tcm_ifindex: ifindex,
+            tcm_handle: handle,
+            tcm_parent: parent,
+            tcm_info: info,
+        });
+    }
+
+    fn attr(&mut self, kind: u16, data: &[u8]) {
+        let hdr = rtattr {
+            rta_len: (mem::size_of::<rtattr>() + data.len()) as u16,
+            rta_type: kind,
+        };
+        self.push(&hdr);
+        self.push_bytes(data, RTA_ALIGNTO);
+    }
+
+    fn attr_str(&mut self, kind: u16, value: &str) {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.attr(kind, &data);
+    }
+
+    fn begin_nested(&mut self, kind: u16) -> usize {
+        let offset = self.buf.len();
+        self.push(&rtattr {
+            rta_len: 0,
+            rta_type: kind | NLA_F_NESTED as u16,
+        });
+        offset
+    }
+
+    fn end_nested(&mut self, offset: usize) {
+        let len = (self.buf.len() - offset) as u16;
+        self.buf[offset..offset + 2].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    fn finish(&mut self) -> &[u8] {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+        &self.buf
+    }
+}
+
+struct NetlinkSocket {
+    fd: RawFd,
+}
+
+impl NetlinkSocket {
+    fn open() -> Result<NetlinkSocket> {
+        unsafe {
+            let fd = socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE);
+            if fd < 0 {
+                return Err(Error::IO(io::Error::last_os_error()));
+            }
+            let sock = NetlinkSocket { fd };
+
+            let mut addr: sockaddr_nl = mem::zeroed();
+            addr.nl_family = AF_NETLINK as u16;
+            if bind(
+                fd,
+                &addr as *const _ as *const sockaddr,
+                mem::size_of::<sockaddr_nl>() as u32,
+            ) < 0
+            {
+                return Err(Error::IO(io::Error::last_os_error()));
+            }
+
+            Ok(sock)
+        }
+    }
+
+    /// Sends `msg` and waits for the kernel to acknowledge it.
+    fn request(&self, msg: Message) -> Result<()> {
+        self.request_with(msg, |_, _| {})
+    }
+
+    /// Sends `msg` and waits for the kernel to acknowledge it, passing the
+    /// type and payload of the other messages received to `f`.
+    fn request_with<F: FnMut(u16, &[u8])>(&self, mut msg: Message, mut f: F) -> Result<()> {
+        let data = msg.finish();
+        let ret = unsafe { send(self.fd, data.as_ptr() as *const c_void, data.len(), 0) };
+        if ret < 0 {
+            return Err(Error::IO(io::Error::last_os_error()));
+        }
+
+        let mut buf = [0u8; 4096];
+        loop {
+            let len = unsafe { recv(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+            if len < 0 {
+                return Err(Error::IO(io::Error::last_os_error()));
+            }
+
+            let mut offset = 0;
+            while offset + mem::size_of::<nlmsghdr>() <= len as usize {
+                let hdr = unsafe { &*(buf.as_ptr().add(offset) as *const nlmsghdr) };
+                if hdr.nlmsg_type == NLMSG_ERROR as u16 {
+                    let data = unsafe { buf.as_ptr().add(offset + mem::size_of::<nlmsghdr>()) };
+                    let err = unsafe { &*(data as *const nlmsgerr) };
+                    if err.error == 0 {
+                        return Ok(());
+                    }
+                    return Err(Error::IO(io::Error::from_raw_os_error(-err.error)));
+                }
+                if hdr.nlmsg_len == 0 {
+                    break;
+                }
+                let start = offset + mem::size_of::<nlmsghdr>();
+                let end = (offset + hdr.nlmsg_len as usize).min(len as usize);
+                if start <= end {
+                    f(hdr.nlmsg_type, &buf[start..end]);
+                }
+                offset += align(hdr.nlmsg_len as usize, NLMSG_ALIGNTO);
+            }
+        }
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_u16(buf: &[u8], offset: usize) -> u16 {
+        u16::from_ne_bytes([buf[offset], buf[offset + 1]])
+    }
+
+    fn read_u32(buf: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes([
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn test_handles() {
+        assert_eq!(AttachPoint::Ingress.parent(), 0xFFFF_FFF2);
+        assert_eq!(AttachPoint::Egress.parent(), 0xFFFF_FFF3);
+        // priority 1, protocol ETH_P_ALL in network byte order
+        assert_eq!(filter_info(1), 0x0001_0300);
+    }
+
+    #[test]
+    fn test_message() {
+        let mut msg = new_filter(2, AttachPoint::Ingress, 1, 7, "prog");
+        let buf = msg.finish();
+
+        // nlmsghdr
+        assert_eq!(read_u32(buf, 0) as usize, buf.len());
+        assert_eq!(buf.len(), 16 + 20 + 8 + 4 + 8 + 12 + 8);
+        assert_eq!(read_u16(buf, 4), RTM_NEWTFILTER);
+        assert_eq!(
+            read_u16(buf, 6) as i32,
+            NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_EXCL | NLM_F_ECHO
+        );
+        // tcmsg, the kernel picks the handle
+        assert_eq!(read_u32(buf, 16 + 4), 2);
+        assert_eq!(read_u32(buf, 16 + 8), 0);
+        assert_eq!(read_u32(buf, 16 + 12), 0xFFFF_FFF2);
+        // TCA_KIND, the length doesn't include the padding
+        assert_eq!(read_u16(buf, 36), 8);
+        assert_eq!(read_u16(buf, 38), TCA_KIND);
+        assert_eq!(&buf[40..44], b"bpf\0");
+        // TCA_OPTIONS, nested
+        assert_eq!(read_u16(buf, 44), 4 + 8 + 12 + 8);
+        assert_eq!(read_u16(buf, 46), TCA_OPTIONS | NLA_F_NESTED as u16);
+        assert_eq!(read_u16(buf, 48), 8);
+        assert_eq!(read_u16(buf, 50), TCA_BPF_FD);
+        assert_eq!(read_u32(buf, 52), 7);
+        assert_eq!(read_u16(buf, 56), 4 + 5);
+        assert_eq!(read_u16(buf, 58), TCA_BPF_NAME);
+        assert_eq!(&buf[60..68], b"prog\0\0\0\0");
+        assert_eq!(read_u16(buf, 68), 8);
+        assert_eq!(read_u16(buf, 70), TCA_BPF_FLAGS);
+        assert_eq!(read_u32(buf, 72), TCA_BPF_FLAG_ACT_DIRECT);
+    }
+
+    #[test]
+    fn test_delete_message() {
+        let filter = Filter {
+            ifindex: 2,
+            point: AttachPoint::Egress,
+            priority: 1,
+            handle: 0x2a,
+        };
+        let mut msg = delete_filter(&filter);
+        let buf = msg.finish();
+
+        assert_eq!(buf.len(), 16 + 20);
+        assert_eq!(read_u16(buf, 4), RTM_DELTFILTER);
+        assert_eq!(read_u32(buf, 16 + 4), 2);
+        // only the filter with this handle is deleted
+        assert_eq!(read_u32(buf, 16 + 8), 0x2a);
+        assert_eq!(read_u32(buf, 16 + 12), 0xFFFF_FFF3);
+        assert_eq!(read_u32(buf, 16 + 16), filter_info(1));
+    }
+
+    #[test]
+    fn test_filter_handle() {
+        let mut msg = Message::new(RTM_NEWTFILTER, 0);
+        msg.tcmsg(2, 0x2a, AttachPoint::Ingress.parent(), filter_info(1));
+        msg.attr_str(TCA_KIND, "bpf");
+        let buf = msg.finish();
+        assert_eq!(filter_handle(&buf[16..]), Some(0x2a));
+        assert_eq!(filter_handle(&buf[16..32]), None);
+    }
+}
@@ -23,6 +23,34 @@ impl Default for Flags {
     }
 }
 
+/* NB: this needs to be kept in sync with redbpf_probes::xdp::XdpAction */
+/// The action returned by an XDP program.
+///
+/// See [`redbpf_probes::xdp::XdpAction`](../../redbpf_probes/xdp/enum.XdpAction.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Action {
+    Aborted = 0,
+    Drop = 1,
+    Pass = 2,
+    Tx = 3,
+    Redirect = 4,
+}
+
+impl Action {
+    /// Returns the action for the return value `value` of an XDP program.
+    pub fn from_u32(value: u32) -> Option<Action> {
+        Some(match value {
+            0 => Action::Aborted,
+            1 => Action::Drop,
+            2 => Action::Pass,
+            3 => Action::Tx,
+            4 => Action::Redirect,
+            _ => return None,
+        })
+    }
+}
+
 /* NB: this needs to be kept in sync with redbpf_probes::xdp::MapData */
 #[repr(C)]
 pub struct MapData<T> {
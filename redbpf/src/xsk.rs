@@ -0,0 +1,592 @@
+// Copyright 2020 Authors of Red Sift
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! # AF_XDP sockets
+//!
+//! `XskSocket` receives packets redirected by XDP programs through a
+//! `BPF_MAP_TYPE_XSKMAP` map, without copying them through a perf buffer.
+#![allow(non_camel_case_types)]
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr::{self, null_mut};
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use libc::{
+    bind, c_int, c_void, close, getsockopt, if_nametoindex, mmap, munmap, poll, pollfd, sendto,
+    setsockopt, sockaddr, socket, socklen_t, EINTR, MAP_ANONYMOUS, MAP_FAILED, MAP_POPULATE,
+    MAP_PRIVATE, MAP_SHARED, MSG_DONTWAIT, POLLIN, PROT_READ, PROT_WRITE, SOCK_RAW,
+};
+
+use crate::{Error, Map, Result};
+
+const AF_XDP: c_int = 44;
+const SOL_XDP: c_int = 283;
+
+const XDP_MMAP_OFFSETS: c_int = 1;
+const XDP_RX_RING: c_int = 2;
+const XDP_TX_RING: c_int = 3;
+const XDP_UMEM_REG: c_int = 4;
+const XDP_UMEM_FILL_RING: c_int = 5;
+const XDP_UMEM_COMPLETION_RING: c_int = 6;
+
+const XDP_PGOFF_RX_RING: i64 = 0;
+const XDP_PGOFF_TX_RING: i64 = 0x8000_0000;
+const XDP_UMEM_PGOFF_FILL_RING: i64 = 0x1_0000_0000;
+const XDP_UMEM_PGOFF_COMPLETION_RING: i64 = 0x1_8000_0000;
+
+const XDP_COPY: u16 = 1 << 1;
+const XDP_ZEROCOPY: u16 = 1 << 2;
+
+#[repr(C)]
+struct sockaddr_xdp {
+    sxdp_family: u16,
+    sxdp_flags: u16,
+    sxdp_ifindex: u32,
+    sxdp_queue_id: u32,
+    sxdp_shared_umem_fd: u32,
+}
+
+#[repr(C)]
+struct xdp_umem_reg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct xdp_ring_offset {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct xdp_mmap_offsets {
+    rx: xdp_ring_offset,
+    tx: xdp_ring_offset,
+    fr: xdp_ring_offset,
+    cr: xdp_ring_offset,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct xdp_desc {
+    addr: u64,
+    len: u32,
+    options: u32,
+}
+
+/// How packets are copied between the interface and the UMEM.
+#[derive(Debug, Clone, Copy)]
+#[repr(u16)]
+pub enum XskMode {
+    /// Use zero-copy if the driver supports it, copy mode otherwise.
+    Unset = 0,
+    /// Always copy the packets. This is the only mode available with
+    /// `xdp::Flags::SkbMode`.
+    Copy = XDP_COPY,
+    /// Require zero-copy support from the driver.
+    ZeroCopy = XDP_ZEROCOPY,
+}
+
+impl Default for XskMode {
+    fn default() -> Self {
+        XskMode::Unset
+    }
+}
+
+/// Configuration of a `XskSocket`.
+#[derive(Debug, Clone, Copy)]
+pub struct XskConfig {
+    /// Number of frames in the UMEM.
+    pub frame_count: u32,
+    /// Size of a frame, which is also the maximum size of a packet. Must be a
+    /// power of 2 between 2048 and the page size.
+    pub frame_size: u32,
+    /// Number of descriptors in each ring. Must be a power of 2.
+    pub ring_size: u32,
+    pub mode: XskMode,
+}
+
+impl Default for XskConfig {
+    fn default() -> Self {
+        XskConfig {
+            frame_count: 4096,
+            frame_size: 2048,
+            ring_size: 2048,
+            mode: XskMode::Unset,
+        }
+    }
+}
+
+/// AF_XDP socket.
+///
+/// An AF_XDP socket receives the packets that an XDP program redirects to it
+/// through a `BPF_MAP_TYPE_XSKMAP` map. The packets are written to a memory
+/// area registered by user-space, the UMEM, which is split in frames of equal
+/// size. Ownership of the frames is passed back and forth with the kernel
+/// through four rings shared with it:
+///
+/// * the fill ring, where user-space hands free frames to the kernel to
+///   receive packets in
+/// * the rx ring, where the kernel returns the frames holding received
+///   packets
+/// * the tx ring, where user-space hands frames holding packets to send to the
+///   kernel
+/// * the completion ring, where the kernel returns the frames once the packets
+///   have been sent
+///
+/// `XskSocket` manages the UMEM and the rings, and recycles the frames
+/// automatically.
+///
+/// ```no_run
+/// use redbpf::load::Loader;
+/// use redbpf::xdp;
+/// use redbpf::{XskConfig, XskSocket};
+///
+/// let mut loader = Loader::load_file("xsk.elf").expect("error loading probe");
+/// for prog in loader.xdps_mut() {
+///     prog.attach_xdp("veth0", xdp::Flags::SkbMode).unwrap();
+/// }
+///
+/// let map = loader.map("sockets").unwrap();
+/// let mut socket = XskSocket::bind(map, "veth0", 0, XskConfig::default()).unwrap();
+/// loop {
+///     socket.poll(None).unwrap();
+///     socket.recv(|packet| println!("received {} bytes", packet.len()));
+/// }
+/// ```
+pub struct XskSocket {
+    fill: Ring<u64>,
+    comp: Ring<u64>,
+    rx: Ring<xdp_desc>,
+    tx: Ring<xdp_desc>,
+    umem: Umem,
+    fd: Socket,
+    frame_size: u64,
+    free_frames: Vec<u64>,
+}
+
+// The rings and the UMEM are owned by the socket, and only accessed through
+// `&mut self`.
+unsafe impl Send for XskSocket {}
+
+impl XskSocket {
+    /// Creates a socket receiving the packets of the queue `queue_id` of
+    /// `interface`.
+    ///
+    /// The socket is registered in the `BPF_MAP_TYPE_XSKMAP` map `map` at
+    /// index `queue_id`, so the XDP program attached to `interface` can
+    /// redirect packets to it. Half of the frames of the UMEM are used to
+    /// receive packets, the other half to send them.
+    pub fn bind(map: &Map, interface: &str, queue_id: u32, config: XskConfig) -> Result<XskSocket> {
+        if map.kind != bpf_sys::bpf_map_type_BPF_MAP_TYPE_XSKMAP {
+            return Err(Error::Map);
+        }
+        let ciface = CString::new(interface)?;
+        let ifindex = unsafe { if_nametoindex(ciface.as_ptr()) };
+        if ifindex == 0 {
+            return Err(Error::IO(io::Error::last_os_error()));
+        }
+
+        let mut socket = XskSocket::new(config)?;
+        // hand the receive half of the UMEM to the kernel
+        let rx_frames = (config.frame_count / 2).min(config.ring_size) as usize;
+        let tx_frames = socket.free_frames.len() - rx_frames;
+        for frame in socket.free_frames.split_off(tx_frames) {
+            socket.fill.push(frame);
+        }
+        socket.fill.submit();
+
+        let addr = sockaddr_xdp {
+            sxdp_family: AF_XDP as u16,
+            sxdp_flags: config.mode as u16,
+            sxdp_ifindex: ifindex,
+            sxdp_queue_id: queue_id,
+            sxdp_shared_umem_fd: 0,
+        };
+        if unsafe {
+            bind(
+                socket.fd.0,
+                &addr as *const _ as *const sockaddr,
+                mem::size_of::<sockaddr_xdp>() as socklen_t,
+            )
+        } < 0
+        {
+            return Err(Error::IO(io::Error::last_os_error()));
+        }
+
+        let mut key = queue_id;
+        let mut fd = socket.fd.0;
+        if unsafe {
+            bpf_sys::bpf_update_elem(
+                map.fd,
+                &mut key as *mut _ as *mut _,
+                &mut fd as *mut _ as *mut _,
+                0,
+            )
+        } < 0
+        {
+            return Err(Error::Map);
+        }
+
+        Ok(socket)
+    }
+
+    fn new(config: XskConfig) -> io::Result<XskSocket> {
+        let fd = Socket::new()?;
+        let umem = Umem::new(config.frame_count as usize * config.frame_size as usize)?;
+        let reg = xdp_umem_reg {
+            addr: umem.ptr as u64,
+            len: umem.len as u64,
+            chunk_size: config.frame_size,
+            headroom: 0,
+        };
+        fd.set_option(XDP_UMEM_REG, &reg)?;
+        for ring in &[
+            XDP_UMEM_FILL_RING,
+            XDP_UMEM_COMPLETION_RING,
+            XDP_RX_RING,
+            XDP_TX_RING,
+        ] {
+            fd.set_option(*ring, &config.ring_size)?;
+        }
+        let off = fd.mmap_offsets()?;
+
+        unsafe {
+            Ok(XskSocket {
+                fill: Ring::new(fd.0, &off.fr, config.ring_size, XDP_UMEM_PGOFF_FILL_RING)?,
+                comp: Ring::new(
+                    fd.0,
+                    &off.cr,
+                    config.ring_size,
+                    XDP_UMEM_PGOFF_COMPLETION_RING,
+                )?,
+                rx: Ring::new(fd.0, &off.rx, config.ring_size, XDP_PGOFF_RX_RING)?,
+                tx: Ring::new(fd.0, &off.tx, config.ring_size, XDP_PGOFF_TX_RING)?,
+                umem,
+                fd,
+                frame_size: config.frame_size.into(),
+                free_frames: (0..config.frame_count as u64)
+                    .map(|i| i * config.frame_size as u64)
+                    .collect(),
+            })
+        }
+    }
+
+    /// Waits until packets are received, or for `timeout` if given.
+    ///
+    /// Returns `false` if the timeout expired. Waiting is resumed when
+    /// interrupted by a signal.
+    pub fn poll(&self, timeout: Option<Duration>) -> Result<bool> {
+        let mut fds = pollfd {
+            fd: self.fd.0,
+            events: POLLIN,
+            revents: 0,
+        };
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            let timeout = deadline
+                .map(|d| d.saturating_duration_since(Instant::now()).as_millis() as c_int)
+                .unwrap_or(-1);
+            let ret = unsafe { poll(&mut fds, 1, timeout) };
+            if ret >= 0 {
+                return Ok(ret > 0);
+            }
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(EINTR) {
+                return Err(Error::IO(e));
+            }
+        }
+    }
+
+    /// Calls `f` with each of the packets received since the last call.
+    ///
+    /// `f` is passed the packet in place in the UMEM, which is handed back to
+    /// the kernel to receive new packets once `f` returns. Returns the number
+    /// of packets received.
+    pub fn recv<F: FnMut(&[u8])>(&mut self, mut f: F) -> usize {
+        let mut count = 0;
+        while let Some(desc) = self.rx.pop() {
+            let packet =
+                unsafe { slice::from_raw_parts(self.umem.frame(desc.addr), desc.len as usize) };
+            f(packet);
+            // the packet doesn't necessarily start at the beginning of the frame
+            let frame = desc.addr - desc.addr % self.frame_size;
+            if !self.fill.push(frame) {
+                self.free_frames.push(frame);
+            }
+            count += 1;
+        }
+        self.rx.release();
+        self.fill.submit();
+
+        count
+    }
+
+    /// Sends `packet` out of the interface the socket is bound to.
+    ///
+    /// The packet is copied to a free frame of the UMEM. Fails with
+    /// `io::ErrorKind::WouldBlock` if all the frames are in flight.
+    pub fn send(&mut self, packet: &[u8]) -> Result<()> {
+        while let Some(frame) = self.comp.pop() {
+            self.free_frames.push(frame);
+        }
+        self.comp.release();
+
+        if packet.len() as u64 > self.frame_size {
+            return Err(Error::IO(io::ErrorKind::InvalidInput.into()));
+        }
+        let frame = match self.free_frames.pop() {
+            Some(frame) => frame,
+            None => return Err(Error::IO(io::ErrorKind::WouldBlock.into())),
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(packet.as_ptr(), self.umem.frame(frame), packet.len());
+        }
+        let desc = xdp_desc {
+            addr: frame,
+            len: packet.len() as u32,
+            options: 0,
+        };
+        if !self.tx.push(desc) {
+            self.free_frames.push(frame);
+            return Err(Error::IO(io::ErrorKind::WouldBlock.into()));
+        }
+        self.tx.submit();
+
+        // in copy mode the kernel only transmits when woken up
+        unsafe { sendto(self.fd.0, ptr::null(), 0, MSG_DONTWAIT, ptr::null(), 0) };
+
+        Ok(())
+    }
+}
+
+impl AsRawFd for XskSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.0
+    }
+}
+
+struct Socket(RawFd);
+
+impl Socket {
+    fn new() -> io::Result<Socket> {
+        let fd = unsafe { socket(AF_XDP, SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Socket(fd))
+    }
+
+    fn set_option<T>(&self, name: c_int, value: &T) -> io::Result<()> {
+        if unsafe {
+            setsockopt(
+                self.0,
+                SOL_XDP,
+                name,
+                value as *const _ as *const c_void,
+                mem::size_of::<T>() as socklen_t,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the offsets of the ring fields in the memory mapped rings.
+    fn mmap_offsets(&self) -> io::Result<xdp_mmap_offsets> {
+        let mut off = xdp_mmap_offsets::default();
+        let mut len = mem::size_of::<xdp_mmap_offsets>() as socklen_t;
+        if unsafe {
+            getsockopt(
+                self.0,
+                SOL_XDP,
+                XDP_MMAP_OFFSETS,
+                &mut off as *mut _ as *mut c_void,
+                &mut len,
+            )
+        } < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        if len as usize == mem::size_of::<xdp_mmap_offsets>() {
+            return Ok(off);
+        }
+
+        // kernels older than 5.4 don't have the flags field
+        let raw: [u64; 16] = unsafe { mem::transmute(off) };
+        let ring = |i: usize| xdp_ring_offset {
+            producer: raw[i * 3],
+            consumer: raw[i * 3 + 1],
+            desc: raw[i * 3 + 2],
+            flags: 0,
+        };
+        Ok(xdp_mmap_offsets {
+            rx: ring(0),
+            tx: ring(1),
+            fr: ring(2),
+            cr: ring(3),
+        })
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // closing the socket also removes it from the XSKMAP
+        unsafe { close(self.0) };
+    }
+}
+
+struct Umem {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Umem {
+    fn new(len: usize) -> io::Result<Umem> {
+        let ptr = unsafe {
+            mmap(
+                null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_POPULATE,
+                -1,
+                0,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Umem {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    /// Returns a pointer to the UMEM at `addr`.
+    fn frame(&self, addr: u64) -> *mut u8 {
+        assert!((addr as usize) < self.len);
+        unsafe { self.ptr.add(addr as usize) }
+    }
+}
+
+impl Drop for Umem {
+    fn drop(&mut self) {
+        unsafe { munmap(self.ptr as *mut c_void, self.len) };
+    }
+}
+
+/// A single producer, single consumer ring shared with the kernel.
+///
+/// The producer and consumer positions are free running counters. Updates
+/// are staged in `cached_prod` and `cached_cons`, and published to the kernel
+/// with `submit` and `release`.
+struct Ring<T> {
+    map: *mut c_void,
+    map_len: usize,
+    producer: *const AtomicU32,
+    consumer: *const AtomicU32,
+    descs: *mut T,
+    size: u32,
+    cached_prod: u32,
+    cached_cons: u32,
+}
+
+impl<T: Copy> Ring<T> {
+    unsafe fn new(fd: RawFd, off: &xdp_ring_offset, size: u32, pgoff: i64) -> io::Result<Ring<T>> {
+        let map_len = off.desc as usize + size as usize * mem::size_of::<T>();
+        let map = mmap(
+            null_mut(),
+            map_len,
+            PROT_READ | PROT_WRITE,
+            MAP_SHARED | MAP_POPULATE,
+            fd,
+            pgoff,
+        );
+        if map == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        let base = map as *mut u8;
+        let producer = base.add(off.producer as usize) as *const AtomicU32;
+        let consumer = base.add(off.consumer as usize) as *const AtomicU32;
+        Ok(Ring {
+            map,
+            map_len,
+            producer,
+            consumer,
+            descs: base.add(off.desc as usize) as *mut T,
+            size,
+            cached_prod: (*producer).load(Ordering::Relaxed),
+            cached_cons: (*consumer).load(Ordering::Relaxed),
+        })
+    }
+
+    /// Stages `value` for the kernel, returns `false` if the ring is full.
+    fn push(&mut self, value: T) -> bool {
+        if self.cached_prod.wrapping_sub(self.cached_cons) == self.size {
+            self.cached_cons = unsafe { (*self.consumer).load(Ordering::Acquire) };
+            if self.cached_prod.wrapping_sub(self.cached_cons) == self.size {
+                return false;
+            }
+        }
+        unsafe {
+            let index = (self.cached_prod & (self.size - 1)) as usize;
+            self.descs.add(index).write(value);
+        }
+        self.cached_prod = self.cached_prod.wrapping_add(1);
+
+        true
+    }
+
+    /// Publishes the values staged with `push`.
+    fn submit(&mut self) {
+        unsafe { (*self.producer).store(self.cached_prod, Ordering::Release) };
+    }
+
+    /// Returns the next value produced by the kernel, if any.
+    fn pop(&mut self) -> Option<T> {
+        if self.cached_cons == self.cached_prod {
+            self.cached_prod = unsafe { (*self.producer).load(Ordering::Acquire) };
+            if self.cached_cons == self.cached_prod {
+                return None;
+            }
+        }
+        let value = unsafe {
+            let index = (self.cached_cons & (self.size - 1)) as usize;
+            self.descs.add(index).read()
+        };
+        self.cached_cons = self.cached_cons.wrapping_add(1);
+
+        Some(value)
+    }
+
+    /// Hands the slots of the values returned by `pop` back to the kernel.
+    fn release(&mut self) {
+        unsafe { (*self.consumer).store(self.cached_cons, Ordering::Release) };
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        unsafe { munmap(self.map, self.map_len) };
+    }
+}